@@ -0,0 +1,56 @@
+//! Measure the throughput of the queue under moderate contention, to compare the compare-and-swap
+//! loops with the slots of `SegQueue` and `FaaQueue`:
+//!
+//! cargo run --release --example contention
+
+extern crate lock_free_queue;
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Instant;
+
+use lock_free_queue::{FaaQueue, Queue, SegQueue};
+
+const OPERATIONS: usize = 1_000_000;
+
+fn main() {
+    for &threads in &[1, 2, 4] {
+        measure("Queue", threads, Queue::new(), |queue, i| {
+            queue.enqueue(i).expect("enqueue");
+            queue.dequeue();
+        });
+        measure("SegQueue", threads, SegQueue::new(), |queue, i| {
+            queue.enqueue(i);
+            queue.dequeue();
+        });
+        measure("FaaQueue", threads, FaaQueue::new(), |queue, i| {
+            queue.enqueue(i);
+            queue.dequeue();
+        });
+    }
+}
+
+/// Run `operation`, an enqueue and a dequeue, `OPERATIONS` times in each thread.
+fn measure<Q: Send + Sync + 'static>(name: &str, threads: usize, queue: Q, operation: fn(&Q, usize)) {
+    let queue = Arc::new(queue);
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads).map(|_| {
+        let queue = queue.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            barrier.wait();
+            // Each thread is both a producer and a consumer.
+            for i in 0..OPERATIONS {
+                operation(&queue, i);
+            }
+        })
+    }).collect();
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().expect("join");
+    }
+    let elapsed = start.elapsed();
+    let operations = 2 * OPERATIONS * threads;
+    println!("{}, {} threads: {:.1} M operations/s", name, threads, operations as f64 / elapsed.as_secs_f64() / 1e6);
+}
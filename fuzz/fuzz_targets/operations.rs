@@ -0,0 +1,83 @@
+//! Run arbitrary sequences of operations on a queue from a few threads, and compare their results
+//! with a `VecDeque`:
+//!
+//! cargo +nightly fuzz run operations
+//!
+//! Each operation runs with the lock of the model held, so that the model knows its result
+//! exactly. The operations of the threads still interleave, and the nodes removed by a thread are
+//! reclaimed while the other ones use the queue.
+
+#![no_main]
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use lock_free_queue::Queue;
+
+/// Maximum number of threads running operations.
+const THREADS: usize = 4;
+
+#[derive(Arbitrary, Debug)]
+enum Operation {
+    Enqueue(u8),
+    Dequeue,
+    Batch(Vec<u8>),
+    Claim { commit: bool },
+    IntoRawList,
+}
+
+fn apply(queue: &Queue<u8>, model: &Mutex<VecDeque<u8>>, operation: &Operation) {
+    let mut model = model.lock().expect("lock");
+    match *operation {
+        Operation::Enqueue(value) => {
+            queue.enqueue(value).expect("enqueue");
+            model.push_back(value);
+        },
+        Operation::Dequeue => assert_eq!(queue.dequeue(), model.pop_front()),
+        Operation::Batch(ref values) => {
+            queue.enqueue_batch(values.iter().cloned()).expect("enqueue");
+            model.extend(values);
+        },
+        Operation::Claim { commit } => {
+            match queue.claim() {
+                Some(claim) => {
+                    assert_eq!(Some(&*claim), model.front());
+                    if commit {
+                        claim.commit();
+                        model.pop_front();
+                    }
+                    else {
+                        claim.abort();
+                    }
+                },
+                None => assert!(model.is_empty()),
+            }
+        },
+        Operation::IntoRawList => {
+            let list: Vec<_> = queue.into_raw_list().into_iter().collect();
+            assert_eq!(list, model.drain(..).collect::<Vec<_>>());
+        },
+    }
+    assert_eq!(queue.len(), model.len());
+}
+
+fuzz_target!(|threads: Vec<Vec<Operation>>| {
+    let queue = Queue::new();
+    let model = Mutex::new(VecDeque::new());
+    thread::scope(|scope| {
+        for operations in threads.iter().take(THREADS) {
+            let queue = &queue;
+            let model = &model;
+            scope.spawn(move || {
+                for operation in operations {
+                    apply(queue, model, operation);
+                }
+            });
+        }
+    });
+    let model = model.into_inner().expect("lock");
+    assert_eq!(queue.into_iter().collect::<Vec<_>>(), Vec::from(model));
+});
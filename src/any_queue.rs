@@ -0,0 +1,141 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+use Queue;
+
+/// A message of any type.
+pub type AnyMessage = Box<dyn Any + Send>;
+
+/// A queue of messages of different types, for event buses and plugins whose message types are
+/// not known when the queue is created.
+pub struct AnyQueue {
+    queue: Queue<AnyMessage>,
+}
+
+impl AnyQueue {
+    pub fn new() -> Self {
+        Self {
+            queue: Queue::new(),
+        }
+    }
+
+    pub fn enqueue<T: Any + Send>(&self, value: T) {
+        self.queue.enqueue(Box::new(value)).expect("enqueue, since the queue is never closed");
+    }
+
+    pub fn dequeue(&self) -> Option<AnyMessage> {
+        self.queue.dequeue()
+    }
+
+    /// Dequeue the first message only if it has type `T`. Otherwise, it stays in the queue.
+    pub fn dequeue_as<T: Any>(&self) -> Option<T> {
+        let claim = self.queue.claim()?;
+        if !claim.is::<T>() {
+            claim.abort();
+            return None;
+        }
+        claim.commit().downcast().ok().map(|value| *value)
+    }
+
+    /// Dequeue the first message and call the handler registered for its type.
+    ///
+    /// Returns `None` if the queue is empty and `Some(Err(message))` if no handler accepts the
+    /// message.
+    pub fn dispatch(&self, dispatcher: &Dispatcher) -> Option<Result<(), AnyMessage>> {
+        self.dequeue().map(|message| dispatcher.dispatch(message))
+    }
+}
+
+impl Default for AnyQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Handler = Box<dyn Fn(AnyMessage)>;
+
+/// Handlers called according to the type of the messages.
+pub struct Dispatcher {
+    handlers: HashMap<TypeId, Handler>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Register the handler of the messages of type `T`, replacing the previous one.
+    pub fn on<T: Any, F: Fn(T) + 'static>(mut self, handler: F) -> Self {
+        self.handlers.insert(TypeId::of::<T>(), Box::new(move |message: AnyMessage| {
+            if let Ok(value) = message.downcast::<T>() {
+                handler(*value);
+            }
+        }));
+        self
+    }
+
+    pub fn dispatch(&self, message: AnyMessage) -> Result<(), AnyMessage> {
+        // Deref the box to get the type of the message instead of the one of the box.
+        match self.handlers.get(&(*message).type_id()) {
+            Some(handler) => {
+                handler(message);
+                Ok(())
+            },
+            None => Err(message),
+        }
+    }
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{AnyQueue, Dispatcher};
+
+    #[derive(Debug, PartialEq)]
+    struct Resize(u32, u32);
+
+    #[test]
+    fn test_dequeue_as() {
+        let queue = AnyQueue::new();
+        queue.enqueue(Resize(800, 600));
+        queue.enqueue("quit");
+        assert_eq!(queue.dequeue_as::<&str>(), None);
+        assert_eq!(queue.dequeue_as::<Resize>(), Some(Resize(800, 600)));
+        let message = queue.dequeue().expect("message");
+        assert_eq!(message.downcast_ref::<&str>(), Some(&"quit"));
+        assert!(queue.dequeue().is_none());
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let dispatcher = {
+            let resize_log = log.clone();
+            let text_log = log.clone();
+            Dispatcher::new()
+                .on(move |Resize(width, height)| resize_log.borrow_mut().push(format!("{}x{}", width, height)))
+                .on(move |text: String| text_log.borrow_mut().push(text))
+        };
+
+        let queue = AnyQueue::new();
+        queue.enqueue("hello".to_string());
+        queue.enqueue(42u8);
+        queue.enqueue(Resize(1, 2));
+        assert!(queue.dispatch(&dispatcher).expect("message").is_ok());
+        let unhandled = queue.dispatch(&dispatcher).expect("message").expect_err("unhandled");
+        assert_eq!(unhandled.downcast_ref::<u8>(), Some(&42));
+        assert!(queue.dispatch(&dispatcher).expect("message").is_ok());
+        assert!(queue.dispatch(&dispatcher).is_none());
+        assert_eq!(*log.borrow(), ["hello", "1x2"]);
+    }
+}
@@ -0,0 +1,400 @@
+//! A bounded queue storing its elements in a preallocated array, for workloads with a known
+//! maximum size.
+//!
+//! This is the algorithm of Dmitry Vyukov: each slot has a sequence number telling which position
+//! it can be written or read for, so that reserving a position is a single compare-and-swap and no
+//! node is allocated per element.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+
+use SendError;
+use sync::atomic::{AtomicUsize, Ordering};
+
+pub(crate) struct Slot<T> {
+    /// Position for which the slot can be written (equal) or read (one more).
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    pub(crate) const fn new(position: usize) -> Self {
+        Self {
+            sequence: AtomicUsize::new(position),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+/// Aligned to the size of a pair of cache lines, so that the producers and the consumers do not
+/// invalidate each other's position.
+#[repr(align(128))]
+pub(crate) struct CachePadded<T>(pub(crate) T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// A lock-free bounded MPMC queue backed by an array.
+///
+/// Unlike `RealTimeQueue`, the operations retry until they succeed or observe the queue full or
+/// empty.
+pub struct ArrayQueue<T> {
+    slots: Box<[Slot<T>]>,
+    /// Position of the next element to dequeue.
+    head: CachePadded<AtomicUsize>,
+    /// Position of the next element to enqueue.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// The values are only accessed by the thread which reserved their slot.
+unsafe impl<T: Send> Send for ArrayQueue<T> {}
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    /// Create a queue holding at least `capacity` elements (rounded up to a power of two).
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        Self {
+            slots: (0..capacity).map(Slot::new).collect(),
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Number of elements in the queue, which is only a snapshot with concurrent operations.
+    pub fn len(&self) -> usize {
+        len(&self.slots, &self.head, &self.tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Add an element, or give it back if the queue is full.
+    pub fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        enqueue(&self.slots, &self.tail, value, usize::MAX)
+    }
+
+    /// Remove the first element, or return `None` if the queue is empty.
+    pub fn dequeue(&self) -> Option<T> {
+        dequeue(&self.slots, &self.head, usize::MAX)
+    }
+}
+
+impl<T: Copy> ArrayQueue<T> {
+    /// Copy the values at the start of `data` for which there is room, returning how many were
+    /// enqueued.
+    ///
+    /// Their positions are reserved with a single compare-and-swap, but the values are copied one
+    /// slot at a time, since each slot also holds its sequence number.
+    pub fn try_enqueue_slice(&self, data: &[T]) -> usize {
+        enqueue_slice(&self.slots, &self.tail, data)
+    }
+
+    /// Copy the first elements to `buffer` until it is full or the queue is empty, returning how
+    /// many were dequeued.
+    pub fn dequeue_slice(&self, buffer: &mut [T]) -> usize {
+        dequeue_slice(&self.slots, &self.head, buffer)
+    }
+}
+
+/// Number of elements in `slots` (whose length is a power of two) between the positions.
+pub(crate) fn len<T>(slots: &[Slot<T>], head: &AtomicUsize, tail: &AtomicUsize) -> usize {
+    let head = head.load(Ordering::SeqCst);
+    let tail = tail.load(Ordering::SeqCst);
+    tail.wrapping_sub(head).min(slots.len())
+}
+
+/// Write an element at the position `tail` in `slots`, whose length is a power of two, giving it
+/// back if the queue is full or after `attempts` failed reservations (`usize::MAX` to retry until
+/// the queue is observed full).
+pub(crate) fn enqueue<T>(slots: &[Slot<T>], tail: &AtomicUsize, value: T, attempts: usize) -> Result<(), SendError<T>> {
+    let mut position = tail.load(Ordering::Relaxed);
+    for _ in 0..attempts {
+        let slot = &slots[position & (slots.len() - 1)];
+        let sequence = slot.sequence.load(Ordering::Acquire);
+        if sequence == position {
+            // Not a weak compare-and-swap, whose spurious failures would waste the attempts.
+            match tail.compare_exchange(position, position.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    unsafe { (*slot.value.get()).write(value) };
+                    // Release to publish the value to the consumer of this position.
+                    slot.sequence.store(position.wrapping_add(1), Ordering::Release);
+                    return Ok(());
+                },
+                Err(current) => position = current,
+            }
+        }
+        else if (sequence.wrapping_sub(position) as isize) < 0 {
+            // The slot still holds the element of the previous lap.
+            return Err(SendError::Full(value));
+        }
+        else {
+            position = tail.load(Ordering::Relaxed);
+        }
+    }
+    Err(SendError::Full(value))
+}
+
+/// Read the element at the position `head` in `slots`, whose length is a power of two, returning
+/// `None` if the queue is empty or after `attempts` failed reservations (`usize::MAX` to retry
+/// until the queue is observed empty).
+pub(crate) fn dequeue<T>(slots: &[Slot<T>], head: &AtomicUsize, attempts: usize) -> Option<T> {
+    let mut position = head.load(Ordering::Relaxed);
+    for _ in 0..attempts {
+        let slot = &slots[position & (slots.len() - 1)];
+        let sequence = slot.sequence.load(Ordering::Acquire);
+        let expected = position.wrapping_add(1);
+        if sequence == expected {
+            match head.compare_exchange(position, expected, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    let value = unsafe { (*slot.value.get()).assume_init_read() };
+                    // Release so that the producer of the next lap writes after our read.
+                    slot.sequence.store(position.wrapping_add(slots.len()), Ordering::Release);
+                    return Some(value);
+                },
+                Err(current) => position = current,
+            }
+        }
+        else if (sequence.wrapping_sub(expected) as isize) < 0 {
+            // The element of this position is not written yet.
+            return None;
+        }
+        else {
+            position = head.load(Ordering::Relaxed);
+        }
+    }
+    None
+}
+
+/// Number of consecutive slots from `position`, up to `max`, whose sequence is `position + lag`
+/// for their own position.
+fn ready<T>(slots: &[Slot<T>], position: usize, lag: usize, max: usize) -> usize {
+    let max = max.min(slots.len());
+    (0..max)
+        .take_while(|&offset| {
+            let position = position.wrapping_add(offset);
+            slots[position & (slots.len() - 1)].sequence.load(Ordering::Acquire) == position.wrapping_add(lag)
+        })
+        .count()
+}
+
+/// Write the values at the start of `data` from the position `tail` in `slots`, whose length is a
+/// power of two, returning how many were written.
+pub(crate) fn enqueue_slice<T: Copy>(slots: &[Slot<T>], tail: &AtomicUsize, data: &[T]) -> usize {
+    let mut position = tail.load(Ordering::Relaxed);
+    loop {
+        let count = ready(slots, position, 0, data.len());
+        if count == 0 {
+            let sequence = slots[position & (slots.len() - 1)].sequence.load(Ordering::Acquire);
+            if data.is_empty() || (sequence.wrapping_sub(position) as isize) < 0 {
+                // The first slot still holds the element of the previous lap.
+                return 0;
+            }
+            position = tail.load(Ordering::Relaxed);
+            continue;
+        }
+        // The slots cannot become unready, so reserving their positions makes them ours.
+        match tail.compare_exchange_weak(position, position.wrapping_add(count), Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => {
+                for (offset, &value) in data[..count].iter().enumerate() {
+                    let position = position.wrapping_add(offset);
+                    let slot = &slots[position & (slots.len() - 1)];
+                    unsafe { (*slot.value.get()).write(value) };
+                    // Release to publish the value to the consumer of this position.
+                    slot.sequence.store(position.wrapping_add(1), Ordering::Release);
+                }
+                return count;
+            },
+            Err(current) => position = current,
+        }
+    }
+}
+
+/// Read the elements from the position `head` in `slots`, whose length is a power of two, to the
+/// start of `buffer`, returning how many were read.
+pub(crate) fn dequeue_slice<T: Copy>(slots: &[Slot<T>], head: &AtomicUsize, buffer: &mut [T]) -> usize {
+    let mut position = head.load(Ordering::Relaxed);
+    loop {
+        let count = ready(slots, position, 1, buffer.len());
+        if count == 0 {
+            let sequence = slots[position & (slots.len() - 1)].sequence.load(Ordering::Acquire);
+            if buffer.is_empty() || (sequence.wrapping_sub(position.wrapping_add(1)) as isize) < 0 {
+                // The element of the first position is not written yet.
+                return 0;
+            }
+            position = head.load(Ordering::Relaxed);
+            continue;
+        }
+        match head.compare_exchange_weak(position, position.wrapping_add(count), Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => {
+                for (offset, value) in buffer[..count].iter_mut().enumerate() {
+                    let position = position.wrapping_add(offset);
+                    let slot = &slots[position & (slots.len() - 1)];
+                    *value = unsafe { (*slot.value.get()).assume_init() };
+                    // Release so that the producer of the next lap writes after our read.
+                    slot.sequence.store(position.wrapping_add(slots.len()), Ordering::Release);
+                }
+                return count;
+            },
+            Err(current) => position = current,
+        }
+    }
+}
+
+impl<T> Drop for ArrayQueue<T> {
+    fn drop(&mut self) {
+        while self.dequeue().is_some() {
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use SendError;
+    use super::ArrayQueue;
+
+    #[test]
+    fn test_full() {
+        let queue = ArrayQueue::new(3);
+        assert_eq!(queue.capacity(), 4);
+        for lap in 0..3 {
+            for i in 0..4 {
+                queue.try_enqueue(lap * 10 + i).expect("enqueue");
+            }
+            assert!(queue.is_full());
+            assert_eq!(queue.try_enqueue(99), Err(SendError::Full(99)));
+            for i in 0..4 {
+                assert_eq!(queue.dequeue(), Some(lap * 10 + i));
+            }
+            assert!(queue.is_empty());
+            assert_eq!(queue.dequeue(), None);
+        }
+
+        // The elements left are dropped with the queue.
+        let queue = ArrayQueue::new(4);
+        let value = Arc::new(());
+        queue.try_enqueue(value.clone()).expect("enqueue");
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_slices() {
+        let queue = ArrayQueue::new(4);
+        let mut buffer = [0.0f32; 3];
+        assert_eq!(queue.try_enqueue_slice(&[0.5, 1.5, 2.5]), 3);
+        assert_eq!(queue.dequeue_slice(&mut buffer[..2]), 2);
+        assert_eq!(buffer[..2], [0.5, 1.5]);
+        // Around the end of the array, and only as many as there is room for.
+        assert_eq!(queue.try_enqueue_slice(&[3.5, 4.5, 5.5, 6.5]), 3);
+        assert_eq!(queue.try_enqueue_slice(&[7.5]), 0);
+        assert_eq!(queue.dequeue(), Some(2.5));
+        assert_eq!(queue.dequeue_slice(&mut buffer), 3);
+        assert_eq!(buffer, [3.5, 4.5, 5.5]);
+        assert_eq!(queue.dequeue_slice(&mut buffer), 0);
+        assert_eq!(queue.try_enqueue_slice(&[]), 0);
+    }
+
+    #[test]
+    fn test_slices_multithread() {
+        let count = if cfg!(miri) { 100 } else { 10_000 };
+        let queue = ArrayQueue::new(16);
+        let mut received = thread::scope(|scope| {
+            for thread in 0..4 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    let data: Vec<_> = (thread * count..(thread + 1) * count).collect();
+                    let mut sent = 0;
+                    while sent < count {
+                        let end = (sent + 5).min(count);
+                        match queue.try_enqueue_slice(&data[sent..end]) {
+                            0 => thread::yield_now(),
+                            len => sent += len,
+                        }
+                    }
+                });
+            }
+            let consumers: Vec<_> = (0..2).map(|_| {
+                let queue = &queue;
+                scope.spawn(move || {
+                    let mut elements = vec![];
+                    let mut buffer = [0; 7];
+                    while elements.len() < 2 * count {
+                        match queue.dequeue_slice(&mut buffer[..(2 * count - elements.len()).min(7)]) {
+                            0 => thread::yield_now(),
+                            len => elements.extend_from_slice(&buffer[..len]),
+                        }
+                    }
+                    elements
+                })
+            }).collect();
+            consumers.into_iter().flat_map(|consumer| consumer.join().expect("join")).collect::<Vec<_>>()
+        });
+        received.sort();
+        assert_eq!(received, (0..4 * count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 100 } else { 10_000 };
+        let queue = Arc::new(ArrayQueue::new(16));
+        let producers: Vec<_> = (0..4).map(|thread| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..count {
+                    let mut value = thread * count + i;
+                    while let Err(error) = queue.try_enqueue(value) {
+                        value = error.into_inner();
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect();
+        let consumers: Vec<_> = (0..2).map(|_| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut elements = vec![];
+                while elements.len() < 2 * count {
+                    match queue.dequeue() {
+                        Some(element) => elements.push(element),
+                        None => thread::yield_now(),
+                    }
+                }
+                elements
+            })
+        }).collect();
+        for producer in producers {
+            producer.join().expect("join");
+        }
+        let mut received: Vec<_> = consumers.into_iter()
+            .flat_map(|consumer| consumer.join().expect("join"))
+            .collect();
+        received.sort();
+        assert_eq!(received, (0..4 * count).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,140 @@
+//! What a thread does when its compare-and-swap fails because another thread modified the queue
+//! first.
+//!
+//! Retrying immediately makes the contending threads bounce the cache line of the head or the
+//! tail between them, so the retry loops call a `BackoffPolicy` with the number of consecutive
+//! failures of the operation. It is selected when the queue is created.
+
+use core::hint;
+#[cfg(feature = "std")]
+use std::thread;
+
+use sync::Arc;
+
+use Queue;
+use reclaim::Reclaim;
+
+/// The failures after which `Backoff` stops doubling its spinning.
+const SPIN_LIMIT: u32 = 6;
+
+/// Wait between the attempts of a contended operation.
+pub trait BackoffPolicy: Send + Sync {
+    /// Called after the `step`th consecutive failure of an operation, counting from 0.
+    fn backoff(&self, step: u32);
+}
+
+/// Spin for twice as long after each failure, then yield the thread to the scheduler. This is
+/// the default policy. Without the `std` feature, there is no scheduler and it keeps spinning.
+pub struct Backoff {
+    yields: bool,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self {
+            yields: true,
+        }
+    }
+
+    /// A policy which never yields, for real-time threads which must not be descheduled: it keeps
+    /// spinning for the longest duration instead.
+    pub const fn spin_only() -> Self {
+        Self {
+            yields: false,
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BackoffPolicy for Backoff {
+    fn backoff(&self, step: u32) {
+        if step > SPIN_LIMIT && self.yields {
+            #[cfg(feature = "std")]
+            {
+                thread::yield_now();
+                return;
+            }
+        }
+        for _ in 0..1 << step.min(SPIN_LIMIT) {
+            hint::spin_loop();
+        }
+    }
+}
+
+static DEFAULT_BACKOFF: Backoff = Backoff::new();
+
+impl<T> Queue<T> {
+    /// Create a queue whose contended operations wait with the specified policy.
+    pub fn with_backoff(policy: Arc<dyn BackoffPolicy>) -> Self {
+        let mut queue = Self::new();
+        queue.backoff = Some(policy);
+        queue
+    }
+}
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Wait after a failed attempt, `step` being the number of previous failures.
+    pub(crate) fn back_off(&self, step: &mut u32) {
+        match self.backoff {
+            Some(ref policy) => policy.backoff(*step),
+            None => DEFAULT_BACKOFF.backoff(*step),
+        }
+        *step = step.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use Queue;
+    use super::{Backoff, BackoffPolicy};
+
+    /// A user-provided policy, counting the failures.
+    struct Counting {
+        failures: AtomicUsize,
+        inner: Backoff,
+    }
+
+    impl BackoffPolicy for Counting {
+        fn backoff(&self, step: u32) {
+            self.failures.fetch_add(1, Ordering::SeqCst);
+            self.inner.backoff(step);
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = Arc::new(Counting {
+            failures: AtomicUsize::new(0),
+            inner: Backoff::spin_only(),
+        });
+        let queue = Queue::with_backoff(policy.clone());
+        let mut step = 0;
+        for _ in 0..10 {
+            queue.back_off(&mut step);
+        }
+        assert_eq!(step, 10);
+        assert_eq!(policy.failures.load(Ordering::SeqCst), 10);
+
+        let count = if cfg!(miri) { 100 } else { 10_000 };
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..count {
+                        queue.enqueue(i).expect("enqueue");
+                        queue.dequeue().expect("dequeue");
+                    }
+                });
+            }
+        });
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use {Queue, WakeBackend};
+use wake::Notifier;
+
+const NO_ELEMENT: u64 = u64::MAX;
+
+/// Condition under which a `BatchQueue` notifies its consumers.
+#[derive(Clone, Copy, Debug)]
+pub struct FlushTrigger {
+    /// Notify when at least this many elements are pending.
+    pub items: usize,
+    /// Notify when the oldest pending element is at least this old.
+    pub age: Duration,
+}
+
+impl FlushTrigger {
+    pub fn new(items: usize, age: Duration) -> Self {
+        Self {
+            items,
+            age,
+        }
+    }
+}
+
+/// A queue for micro-batching consumers, which wait until enough elements are pending or until
+/// the oldest one is old enough, instead of polling.
+///
+/// The conditions are checked by the producers on enqueue and by the waiting consumers when the
+/// age deadline is reached, so no timer thread is needed.
+pub struct BatchQueue<T> {
+    queue: Queue<T>,
+    len: AtomicUsize,
+    trigger: FlushTrigger,
+    start: Instant,
+    /// Approximate enqueue time of the oldest pending element, in nanoseconds since `start`.
+    oldest: AtomicU64,
+    notifier: Notifier,
+}
+
+impl<T> BatchQueue<T> {
+    pub fn new(trigger: FlushTrigger) -> Self {
+        Self::with_notifier(trigger, Notifier::new(None))
+    }
+
+    /// Create a batch queue whose consumers use the specified backend to wait.
+    pub fn with_wake_backend(trigger: FlushTrigger, backend: Arc<dyn WakeBackend>) -> Self {
+        Self::with_notifier(trigger, Notifier::new(Some(backend)))
+    }
+
+    fn with_notifier(trigger: FlushTrigger, notifier: Notifier) -> Self {
+        Self {
+            queue: Queue::new(),
+            len: AtomicUsize::new(0),
+            trigger,
+            start: Instant::now(),
+            oldest: AtomicU64::new(NO_ELEMENT),
+            notifier,
+        }
+    }
+
+    pub fn enqueue(&self, value: T) {
+        // Count the element first so that flush() never sees more elements than the length.
+        let len = self.len.fetch_add(1, Ordering::SeqCst) + 1;
+        let now = self.now();
+        if len == 1 {
+            self.oldest.store(now, Ordering::SeqCst);
+        }
+        self.queue.enqueue(value).expect("enqueue, since the queue is never closed");
+        if self.is_due(now) {
+            self.notifier.notify();
+        }
+    }
+
+    /// Remove all the pending elements.
+    pub fn flush(&self) -> Vec<T> {
+        let oldest = self.oldest.load(Ordering::SeqCst);
+        let mut elements = vec![];
+        while let Some(element) = self.queue.dequeue() {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            elements.push(element);
+        }
+        if self.len.load(Ordering::SeqCst) == 0 {
+            // Only forget the age if no producer started a new batch meanwhile.
+            let _ = self.oldest.compare_exchange(oldest, NO_ELEMENT, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        else {
+            // Elements are being enqueued right now.
+            self.oldest.store(self.now(), Ordering::SeqCst);
+        }
+        elements
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Block until the flush trigger fires.
+    pub fn wait_flush(&self) {
+        self.wait(None);
+    }
+
+    /// Block until the flush trigger fires or the timeout expires, returning whether it fired.
+    pub fn wait_flush_timeout(&self, timeout: Duration) -> bool {
+        self.wait(Some(Instant::now() + timeout))
+    }
+
+    /// Wait until the flush trigger fires and remove all the pending elements.
+    pub fn wait_batch(&self) -> Vec<T> {
+        self.wait_flush();
+        self.flush()
+    }
+
+    fn wait(&self, deadline: Option<Instant>) -> bool {
+        loop {
+            let listener = self.notifier.listen();
+            let now = self.now();
+            if self.is_due(now) {
+                return true;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return false;
+            }
+            // Wake up by ourselves when the oldest element becomes old enough, since producers
+            // only check the age when they enqueue.
+            let oldest = self.oldest.load(Ordering::SeqCst);
+            let age_deadline = if oldest != NO_ELEMENT {
+                let until_due = (Duration::from_nanos(oldest) + self.trigger.age)
+                    .saturating_sub(Duration::from_nanos(now));
+                Some(Instant::now() + until_due)
+            }
+            else {
+                None
+            };
+            let deadline = match (deadline, age_deadline) {
+                (Some(deadline), Some(age_deadline)) => Some(deadline.min(age_deadline)),
+                (deadline, age_deadline) => deadline.or(age_deadline),
+            };
+            listener.wait(deadline);
+        }
+    }
+
+    fn is_due(&self, now: u64) -> bool {
+        if self.len.load(Ordering::SeqCst) >= self.trigger.items {
+            return true;
+        }
+        let oldest = self.oldest.load(Ordering::SeqCst);
+        oldest != NO_ELEMENT && now.saturating_sub(oldest) >= self.trigger.age.as_nanos() as u64
+    }
+
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{BatchQueue, FlushTrigger};
+
+    #[test]
+    fn test_size_trigger() {
+        let queue = Arc::new(BatchQueue::new(FlushTrigger::new(3, Duration::from_secs(60))));
+        queue.enqueue(1);
+        queue.enqueue(2);
+        assert!(!queue.wait_flush_timeout(Duration::from_millis(10)));
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                queue.enqueue(3);
+            })
+        };
+        assert_eq!(queue.wait_batch(), vec![1, 2, 3]);
+        assert!(queue.is_empty());
+        producer.join().expect("join");
+    }
+
+    #[test]
+    fn test_age_trigger() {
+        let queue = BatchQueue::new(FlushTrigger::new(100, Duration::from_millis(30)));
+        assert!(!queue.wait_flush_timeout(Duration::from_millis(10)));
+
+        let start = Instant::now();
+        queue.enqueue(1);
+        queue.enqueue(2);
+        assert_eq!(queue.wait_batch(), vec![1, 2]);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        // The age is reset after a flush.
+        queue.enqueue(3);
+        assert!(!queue.wait_flush_timeout(Duration::from_millis(5)));
+        assert!(queue.wait_flush_timeout(Duration::from_secs(10)));
+        assert_eq!(queue.flush(), vec![3]);
+    }
+}
@@ -0,0 +1,842 @@
+//! A bounded queue whose behavior when it is full is selected at construction.
+
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::mem::MaybeUninit;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+#[cfg(feature = "futures")]
+use futures_sink::Sink;
+
+use {Queue, QueueStats, SendError};
+use stats::Losses;
+use wake::{Notifier, Registration};
+
+/// What `BoundedQueue::enqueue()` does with an element when the queue is full.
+pub trait OverflowPolicy<T>: Send + Sync {
+    /// Handle an element which did not fit in the queue: enqueue it later, drop it or give it
+    /// back in the error.
+    fn overflow(&self, queue: &BoundedQueue<T>, value: T) -> Result<(), SendError<T>>;
+}
+
+/// Give the new element back.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Reject;
+
+impl<T> OverflowPolicy<T> for Reject {
+    fn overflow(&self, _queue: &BoundedQueue<T>, value: T) -> Result<(), SendError<T>> {
+        Err(SendError::Full(value))
+    }
+}
+
+/// Wait until a consumer makes room for the new element.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Block;
+
+impl<T> OverflowPolicy<T> for Block {
+    fn overflow(&self, queue: &BoundedQueue<T>, mut value: T) -> Result<(), SendError<T>> {
+        loop {
+            let listener = queue.space.listen();
+            // Check again now that a dequeue is guaranteed to wake us up.
+            match queue.push(value) {
+                Err(SendError::Full(rejected)) => value = rejected,
+                result => return result,
+            }
+            listener.wait(None);
+        }
+    }
+}
+
+/// Drop the oldest elements of the queue to make room for the new one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DropOldest;
+
+impl<T> OverflowPolicy<T> for DropOldest {
+    fn overflow(&self, queue: &BoundedQueue<T>, mut value: T) -> Result<(), SendError<T>> {
+        loop {
+            if queue.dequeue().is_some() {
+                queue.record_drop();
+            }
+            match queue.push(value) {
+                Err(SendError::Full(rejected)) => value = rejected,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Drop the new element.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DropNewest;
+
+impl<T> OverflowPolicy<T> for DropNewest {
+    fn overflow(&self, queue: &BoundedQueue<T>, _value: T) -> Result<(), SendError<T>> {
+        queue.record_drop();
+        Ok(())
+    }
+}
+
+/// Give the new element to a function, for instance to send it to another queue.
+pub struct Callback<F>(pub F);
+
+impl<T, F: Fn(T) + Send + Sync> OverflowPolicy<T> for Callback<F> {
+    fn overflow(&self, _queue: &BoundedQueue<T>, value: T) -> Result<(), SendError<T>> {
+        (self.0)(value);
+        Ok(())
+    }
+}
+
+/// Number of overflows after which an elastic queue grows.
+const GROWTH_PRESSURE: usize = 16;
+
+/// The capacity of a queue, resized as described in `BoundedQueue` when it is elastic. A fixed
+/// capacity has the same minimum and maximum.
+pub(crate) struct Capacity {
+    current: AtomicUsize,
+    min: usize,
+    max: usize,
+    pressure: AtomicUsize,
+    calm: AtomicUsize,
+}
+
+impl Capacity {
+    pub(crate) const fn new(capacity: usize, max_capacity: usize) -> Self {
+        Self {
+            current: AtomicUsize::new(capacity),
+            min: capacity,
+            max: if max_capacity > capacity { max_capacity } else { capacity },
+            pressure: AtomicUsize::new(0),
+            calm: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn get(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// Record an overflow and grow the capacity if there were enough of them, returning whether
+    /// it grew.
+    pub(crate) fn grow(&self) -> bool {
+        let capacity = self.get();
+        if capacity >= self.max || self.pressure.fetch_add(1, Ordering::SeqCst) + 1 < GROWTH_PRESSURE {
+            return false;
+        }
+        self.pressure.store(0, Ordering::SeqCst);
+        let new_capacity = capacity.saturating_mul(2).clamp(1, self.max);
+        // Another thread could have resized it in the meantime, in which case it already grew or
+        // it should not.
+        self.current.compare_exchange(capacity, new_capacity, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+
+    /// Shrink the capacity if the queue, now holding `len` elements, was mostly empty for long
+    /// enough.
+    pub(crate) fn shrink(&self, len: usize) {
+        let capacity = self.get();
+        if capacity <= self.min {
+            return;
+        }
+        if len > capacity / 4 {
+            self.calm.store(0, Ordering::SeqCst);
+            return;
+        }
+        if self.calm.fetch_add(1, Ordering::SeqCst) + 1 >= capacity {
+            self.calm.store(0, Ordering::SeqCst);
+            self.pressure.store(0, Ordering::SeqCst);
+            let new_capacity = (capacity / 2).max(self.min);
+            let _ = self.current.compare_exchange(capacity, new_capacity, Ordering::SeqCst, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A queue holding at most `capacity` elements.
+///
+/// An elastic queue doubles its capacity, up to a maximum, when producers keep overflowing it,
+/// and halves it back, down to its initial capacity, when it stays mostly empty for as many
+/// dequeues as its capacity.
+pub struct BoundedQueue<T> {
+    queue: Queue<T>,
+    capacity: Capacity,
+    len: AtomicUsize,
+    dropped: AtomicUsize,
+    losses: Losses,
+    policy: Box<dyn OverflowPolicy<T>>,
+    space: Notifier,
+    /// Next ticket given to a producer calling `enqueue_blocking()`.
+    tickets: AtomicUsize,
+    /// Ticket of the producer whose turn it is to enqueue in `enqueue_blocking()`.
+    serving: AtomicUsize,
+}
+
+impl<T> BoundedQueue<T> {
+    /// Panics if `capacity` is 0.
+    pub fn new<P: OverflowPolicy<T> + 'static>(capacity: usize, policy: P) -> Self {
+        Self::elastic(capacity, capacity, policy)
+    }
+
+    /// Create a queue whose capacity grows from `capacity` up to `max_capacity` under sustained
+    /// pressure.
+    ///
+    /// Panics if `capacity` is 0: no element would ever fit.
+    pub fn elastic<P: OverflowPolicy<T> + 'static>(capacity: usize, max_capacity: usize, policy: P) -> Self {
+        assert!(capacity > 0, "the capacity of a BoundedQueue must be positive");
+        Self {
+            queue: Queue::new(),
+            capacity: Capacity::new(capacity, max_capacity),
+            len: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            losses: Losses::default(),
+            policy: Box::new(policy),
+            space: Notifier::new(None),
+            tickets: AtomicUsize::new(0),
+            serving: AtomicUsize::new(0),
+        }
+    }
+
+    /// Give a name to the queue. See `Queue::named()`.
+    pub fn named(mut self, name: &str) -> Self {
+        self.queue.set_name(name);
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.queue.name()
+    }
+
+    /// Add an element to the queue, applying the overflow policy if it is full.
+    pub fn enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        let mut result = self.push(value);
+        while let Err(SendError::Full(value)) = result {
+            if !self.capacity.grow() {
+                result = self.policy.overflow(self, value);
+                break;
+            }
+            result = self.push(value);
+        }
+        self.record(result)
+    }
+
+    /// Add an element to the queue, parking the thread while it is full, whatever the overflow
+    /// policy, or give it back if the queue is closed.
+    ///
+    /// The producers blocked in this method enqueue in the order in which they called it, but the
+    /// other enqueue methods do not wait for their turn.
+    pub fn enqueue_blocking(&self, mut value: T) -> Result<(), SendError<T>> {
+        let ticket = self.tickets.fetch_add(1, Ordering::SeqCst);
+        loop {
+            let listener = self.space.listen();
+            // Check again now that a dequeue or the end of the previous turn is guaranteed to wake
+            // us up.
+            if self.serving.load(Ordering::SeqCst) == ticket {
+                match self.push(value) {
+                    Err(SendError::Full(rejected)) => {
+                        value = rejected;
+                        if self.capacity.grow() {
+                            continue;
+                        }
+                    },
+                    result => {
+                        // Pass the turn to the next producer, even if the queue is closed.
+                        self.serving.fetch_add(1, Ordering::SeqCst);
+                        self.space.notify();
+                        return self.record(result);
+                    },
+                }
+            }
+            listener.wait(None);
+        }
+    }
+
+    /// Add an element to the queue, waiting asynchronously while it is full, whatever the
+    /// overflow policy, or give it back if the queue is closed.
+    pub fn enqueue_async(&self, value: T) -> Enqueue<'_, T> {
+        Enqueue {
+            queue: self,
+            value: Some(value),
+            registration: Registration::default(),
+        }
+    }
+
+    /// A handle to enqueue from asynchronous code, implementing `Sink` with the `futures`
+    /// feature. It waits while the queue is full, like `enqueue_async()`.
+    pub fn sink(&self) -> BoundedSink<'_, T> {
+        BoundedSink {
+            queue: self,
+            pending: None,
+            registration: Registration::default(),
+        }
+    }
+
+    /// Enqueue the pending element, or put it back and register the task to be woken when a
+    /// consumer makes room.
+    fn poll_enqueue(&self, pending: &mut Option<T>, registration: &mut Registration, context: &mut Context) -> Poll<Result<(), SendError<T>>> {
+        let mut value = match pending.take() {
+            Some(value) => value,
+            None => return Poll::Ready(Ok(())),
+        };
+        let mut registered = false;
+        loop {
+            match self.push(value) {
+                Err(SendError::Full(rejected)) => value = rejected,
+                result => return Poll::Ready(self.record(result)),
+            }
+            if self.capacity.grow() {
+                continue;
+            }
+            if registered {
+                *pending = Some(value);
+                return Poll::Pending;
+            }
+            self.space.register(registration, context.waker());
+            // Check again in case an element was dequeued before the registration.
+            registered = true;
+        }
+    }
+
+    /// Add an element to the queue if it is not full, without applying the overflow policy.
+    pub fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        let result = self.push(value);
+        self.record(result)
+    }
+
+    fn record(&self, result: Result<(), SendError<T>>) -> Result<(), SendError<T>> {
+        if let Err(ref error) = result {
+            self.losses.record(error);
+            self.queue.record_loss(error);
+        }
+        result
+    }
+
+    fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::SeqCst);
+        self.losses.record_full();
+        self.queue.record_loss(&SendError::Full(()));
+    }
+
+    /// Copy the values at the start of `data` for which there is room, returning how many were
+    /// enqueued: none if the queue is closed. The overflow policy is not applied.
+    ///
+    /// This is not a bulk copy: each value is still moved into its own node. Only the room is
+    /// reserved once for all of them, and the nodes are linked with a single compare-and-swap like
+    /// with `Queue::enqueue_batch()`. `spsc::Producer::try_enqueue_slice()` is the only one which
+    /// copies a slice with `memcpy`, since its buffer is contiguous.
+    pub fn try_enqueue_slice(&self, data: &[T]) -> usize
+    where T: Copy,
+    {
+        if self.is_closed() || data.is_empty() {
+            return 0;
+        }
+        let capacity = self.capacity();
+        let reserved = self.len.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| {
+            (len < capacity).then(|| len + data.len().min(capacity - len))
+        });
+        let count =
+            match reserved {
+                Ok(len) => data.len().min(capacity - len),
+                Err(_) => return 0,
+            };
+        if self.queue.enqueue_batch(data[..count].iter().copied()).is_err() {
+            // Closed in the meantime.
+            self.len.fetch_sub(count, Ordering::SeqCst);
+            return 0;
+        }
+        count
+    }
+
+    /// Add an element to the queue if it is not full, without counting the failures.
+    fn push(&self, value: T) -> Result<(), SendError<T>> {
+        // Report a closed queue even when it is full.
+        if self.is_closed() {
+            return Err(SendError::Closed(value));
+        }
+        let error =
+            if self.len.fetch_add(1, Ordering::SeqCst) < self.capacity() {
+                match self.queue.try_enqueue(value) {
+                    Ok(()) => return Ok(()),
+                    Err(error) => error,
+                }
+            }
+            else {
+                SendError::Full(value)
+            };
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        Err(error)
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        let value = self.queue.dequeue();
+        if value.is_some() {
+            let len = self.len.fetch_sub(1, Ordering::SeqCst) - 1;
+            self.capacity.shrink(len);
+            self.space.notify();
+        }
+        value
+    }
+
+    /// Move the first elements to `buffer` until it is full or the queue is empty, returning how
+    /// many were dequeued.
+    pub fn dequeue_slice(&self, buffer: &mut [T]) -> usize
+    where T: Copy,
+    {
+        // The values are Copy, so they are overwritten without being dropped, and only
+        // initialized values are written.
+        let uninit = unsafe { &mut *(buffer as *mut [T] as *mut [MaybeUninit<T>]) };
+        let count = self.queue.dequeue_into_uninit(uninit);
+        if count > 0 {
+            let len = self.len.fetch_sub(count, Ordering::SeqCst) - count;
+            self.capacity.shrink(len);
+            self.space.notify();
+        }
+        count
+    }
+
+    /// The current capacity, which only changes for elastic queues.
+    pub fn capacity(&self) -> usize {
+        self.capacity.get()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of elements which can be enqueued before the queue is full (without growing it).
+    ///
+    /// With concurrent producers, this is only a hint: they can fill the queue before the next
+    /// enqueue.
+    pub fn remaining(&self) -> usize {
+        // The length can exceed the capacity while a producer which found the queue full gives
+        // its reservation back, or after the capacity shrinks.
+        self.capacity().saturating_sub(self.len())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Refuse the new elements. See `Queue::close()`.
+    pub fn close(&self) {
+        self.queue.close();
+        // Wake up the blocked producers, which will never get room otherwise.
+        self.space.notify();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+
+    /// Whether the queue is closed and no element is left nor about to be enqueued.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.queue.is_finished()
+    }
+
+    /// Number of elements dropped by the `DropOldest` and `DropNewest` policies.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    /// The elements dropped by the overflow policy and the ones given back by `enqueue()` and
+    /// `try_enqueue()` are counted as lost.
+    pub fn stats(&self) -> QueueStats {
+        self.losses.stats(self.len(), self.capacity())
+    }
+}
+
+/// Future returned by `BoundedQueue::enqueue_async()`.
+pub struct Enqueue<'a, T: 'a> {
+    queue: &'a BoundedQueue<T>,
+    value: Option<T>,
+    registration: Registration,
+}
+
+// The element is moved, never pinned.
+impl<'a, T> Unpin for Enqueue<'a, T> {}
+
+impl<'a, T> Future for Enqueue<'a, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), SendError<T>>> {
+        let this = &mut *self;
+        this.queue.poll_enqueue(&mut this.value, &mut this.registration, context)
+    }
+}
+
+/// Handle returned by `BoundedQueue::sink()`, holding the element which did not fit yet.
+///
+/// Closing the sink only flushes this element: the queue stays open for the other producers.
+/// Dropping the sink drops the element if it was not flushed.
+pub struct BoundedSink<'a, T: 'a> {
+    queue: &'a BoundedQueue<T>,
+    pending: Option<T>,
+    registration: Registration,
+}
+
+impl<'a, T> Unpin for BoundedSink<'a, T> {}
+
+impl<'a, T> BoundedSink<'a, T> {
+    /// Wait until the pending element, if any, is enqueued.
+    pub fn poll_flush(&mut self, context: &mut Context) -> Poll<Result<(), SendError<T>>> {
+        self.queue.poll_enqueue(&mut self.pending, &mut self.registration, context)
+    }
+
+    /// Start enqueuing an element. The previous one must have been flushed.
+    pub fn start_send(&mut self, value: T) -> Result<(), SendError<T>> {
+        debug_assert!(self.pending.is_none(), "start_send() called before the sink was ready");
+        match self.queue.push(value) {
+            Err(SendError::Full(value)) => {
+                self.pending = Some(value);
+                Ok(())
+            },
+            result => self.queue.record(result),
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<'a, T> Sink<T> for BoundedSink<'a, T> {
+    type Error = SendError<T>;
+
+    fn poll_ready(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), SendError<T>>> {
+        self.get_mut().poll_flush(context)
+    }
+
+    fn start_send(self: Pin<&mut Self>, value: T) -> Result<(), SendError<T>> {
+        BoundedSink::start_send(self.get_mut(), value)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), SendError<T>>> {
+        self.get_mut().poll_flush(context)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), SendError<T>>> {
+        self.get_mut().poll_flush(context)
+    }
+}
+
+impl<T> Debug for BoundedQueue<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.debug_struct("BoundedQueue")
+            .field("name", &self.name())
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    use {QueueStats, SendError};
+    use super::{Block, BoundedQueue, Callback, DropNewest, DropOldest, Reject};
+
+    fn drain<T>(queue: &BoundedQueue<T>) -> Vec<T> {
+        let mut elements = vec![];
+        while let Some(element) = queue.dequeue() {
+            elements.push(element);
+        }
+        elements
+    }
+
+    #[test]
+    fn test_reject() {
+        let queue = BoundedQueue::new(2, Reject);
+        assert_eq!(queue.enqueue(1), Ok(()));
+        assert_eq!(queue.enqueue(2), Ok(()));
+        assert_eq!(queue.enqueue(3), Err(SendError::Full(3)));
+        assert_eq!(drain(&queue), [1, 2]);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn test_reject_multithread() {
+        let count = if cfg!(miri) { 50 } else { 2_000 };
+        let queue = Arc::new(BoundedQueue::new(8, Reject));
+        let producers: Vec<_> = (0..4).map(|thread| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut rejected = 0;
+                for i in 0..count {
+                    let mut value = thread * count + i;
+                    while let Err(error) = queue.try_enqueue(value) {
+                        assert!(!error.is_closed());
+                        value = error.into_inner();
+                        rejected += 1;
+                        thread::yield_now();
+                    }
+                }
+                rejected
+            })
+        }).collect();
+        // The consumers stop when all the elements were received, whichever received them.
+        let received = Arc::new(AtomicUsize::new(0));
+        let consumers: Vec<_> = (0..2).map(|_| {
+            let queue = queue.clone();
+            let received = received.clone();
+            thread::spawn(move || {
+                let mut elements = vec![];
+                while received.load(Ordering::SeqCst) < 4 * count {
+                    // The length also counts the slots reserved by the producers about to be
+                    // rejected.
+                    assert!(queue.len() <= 8 + 4);
+                    match queue.dequeue() {
+                        Some(element) => {
+                            elements.push(element);
+                            received.fetch_add(1, Ordering::SeqCst);
+                        },
+                        None => thread::yield_now(),
+                    }
+                }
+                elements
+            })
+        }).collect();
+        let rejected: usize = producers.into_iter().map(|producer| producer.join().expect("join")).sum();
+        let mut received: Vec<_> = consumers.into_iter()
+            .flat_map(|consumer| consumer.join().expect("join"))
+            .collect();
+        received.sort();
+        assert_eq!(received, (0..4 * count).collect::<Vec<_>>());
+        assert_eq!(queue.stats().full, rejected);
+    }
+
+    #[test]
+    fn test_remaining() {
+        let queue = BoundedQueue::new(2, Reject);
+        assert_eq!(queue.remaining(), 2);
+        assert!(!queue.is_full());
+        queue.enqueue(1).expect("enqueue");
+        assert_eq!(queue.remaining(), 1);
+        queue.enqueue(2).expect("enqueue");
+        assert_eq!(queue.remaining(), 0);
+        assert!(queue.is_full());
+        assert!(queue.enqueue(3).is_err());
+        assert_eq!(queue.remaining(), 0);
+        queue.dequeue();
+        assert_eq!(queue.remaining(), 1);
+    }
+
+    #[test]
+    fn test_slices() {
+        let queue = BoundedQueue::new(4, Reject);
+        let mut buffer = [0; 3];
+        assert_eq!(queue.try_enqueue_slice(&[0, 1, 2]), 3);
+        assert_eq!(queue.try_enqueue_slice(&[3, 4]), 1);
+        assert!(queue.is_full());
+        assert_eq!(queue.try_enqueue_slice(&[5]), 0);
+        assert_eq!(queue.dequeue_slice(&mut buffer), 3);
+        assert_eq!(buffer, [0, 1, 2]);
+        assert_eq!(queue.remaining(), 3);
+        assert_eq!(queue.dequeue_slice(&mut buffer), 1);
+        assert_eq!(buffer[0], 3);
+        assert_eq!(queue.dequeue_slice(&mut buffer), 0);
+        assert!(queue.is_empty());
+
+        queue.close();
+        assert_eq!(queue.try_enqueue_slice(&[6]), 0);
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let queue = BoundedQueue::new(2, DropOldest);
+        for i in 0..5 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        assert_eq!(drain(&queue), [3, 4]);
+        assert_eq!(queue.dropped(), 3);
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let queue = BoundedQueue::new(2, DropNewest);
+        for i in 0..5 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        assert_eq!(drain(&queue), [0, 1]);
+        assert_eq!(queue.dropped(), 3);
+    }
+
+    #[test]
+    fn test_stats() {
+        let queue = BoundedQueue::new(2, DropOldest);
+        for i in 0..5 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        queue.close();
+        assert_eq!(queue.enqueue(5), Err(SendError::Closed(5)));
+        assert_eq!(queue.try_enqueue(6), Err(SendError::Closed(6)));
+        let stats = queue.stats();
+        assert_eq!(stats, QueueStats {
+            len: 2,
+            capacity: 2,
+            full: 3,
+            closed: 2,
+            alloc_failed: 0,
+        });
+        assert_eq!(stats.lost(), 5);
+
+        let queue = BoundedQueue::new(1, Reject);
+        queue.enqueue(0).expect("enqueue");
+        assert!(queue.enqueue(1).is_err());
+        assert!(queue.try_enqueue(2).is_err());
+        assert_eq!(queue.stats().full, 2);
+        assert_eq!(queue.dropped(), 0);
+        let queue = queue.named("events");
+        assert_eq!(format!("{:?}", queue), r#"BoundedQueue { name: Some("events"), len: 1, capacity: 1, .. }"#);
+    }
+
+    #[test]
+    fn test_callback() {
+        let overflowed = Arc::new(Mutex::new(vec![]));
+        let queue = {
+            let overflowed = overflowed.clone();
+            BoundedQueue::new(1, Callback(move |value| overflowed.lock().expect("lock").push(value)))
+        };
+        for i in 0..3 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        assert_eq!(drain(&queue), [0]);
+        assert_eq!(*overflowed.lock().expect("lock"), [1, 2]);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn test_elastic() {
+        let queue = BoundedQueue::elastic(4, 16, Reject);
+        for i in 0..4 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        // The pressure of the overflows makes the capacity double until the maximum.
+        let mut rejected = 0;
+        for i in 4..100 {
+            if queue.enqueue(i).is_err() {
+                rejected += 1;
+            }
+        }
+        assert_eq!(queue.capacity(), 16);
+        assert_eq!(rejected, 100 - 16);
+
+        // Once the queue stays mostly empty, the capacity goes back to its initial value.
+        drain(&queue);
+        for i in 0..100 {
+            queue.enqueue(i).expect("enqueue");
+            queue.dequeue();
+        }
+        assert_eq!(queue.capacity(), 4);
+    }
+
+    #[test]
+    fn test_block() {
+        let queue = Arc::new(BoundedQueue::new(1, Block));
+        queue.enqueue(0).expect("enqueue");
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut elements = vec![];
+                while elements.len() < 1_000 {
+                    if let Some(element) = queue.dequeue() {
+                        elements.push(element);
+                    }
+                    else {
+                        thread::sleep(Duration::from_micros(10));
+                    }
+                }
+                elements
+            })
+        };
+        for i in 1..1_000 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        assert_eq!(consumer.join().expect("join"), (0..1_000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_enqueue_async() {
+        let queue = BoundedQueue::new(1, Reject);
+        let mut context = Context::from_waker(Waker::noop());
+        queue.enqueue(0).expect("enqueue");
+        let mut enqueue = queue.enqueue_async(1);
+        assert!(Pin::new(&mut enqueue).poll(&mut context).is_pending());
+        assert_eq!(queue.dequeue(), Some(0));
+        assert_eq!(Pin::new(&mut enqueue).poll(&mut context), Poll::Ready(Ok(())));
+
+        let mut enqueue = queue.enqueue_async(2);
+        assert!(Pin::new(&mut enqueue).poll(&mut context).is_pending());
+        queue.close();
+        assert_eq!(Pin::new(&mut enqueue).poll(&mut context), Poll::Ready(Err(SendError::Closed(2))));
+        assert_eq!(queue.dequeue(), Some(1));
+    }
+
+    #[test]
+    #[cfg(feature = "futures")]
+    fn test_sink() {
+        use futures_sink::Sink;
+
+        let queue = BoundedQueue::new(1, Reject);
+        let mut context = Context::from_waker(Waker::noop());
+        let mut sink = queue.sink();
+        assert_eq!(Pin::new(&mut sink).poll_ready(&mut context), Poll::Ready(Ok(())));
+        Pin::new(&mut sink).start_send(1).expect("send");
+        assert_eq!(Pin::new(&mut sink).poll_ready(&mut context), Poll::Ready(Ok(())));
+        // The element which does not fit waits in the sink.
+        Pin::new(&mut sink).start_send(2).expect("send");
+        assert!(Pin::new(&mut sink).poll_flush(&mut context).is_pending());
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(Pin::new(&mut sink).poll_close(&mut context), Poll::Ready(Ok(())));
+        assert_eq!(queue.dequeue(), Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity of a BoundedQueue must be positive")]
+    fn test_zero_capacity() {
+        BoundedQueue::<u32>::new(0, DropOldest);
+    }
+
+    #[test]
+    fn test_enqueue_blocking() {
+        let queue = Arc::new(BoundedQueue::new(1, Reject));
+        queue.enqueue(0).expect("enqueue");
+        // The producers are blocked one after the other, and enqueue in this order.
+        let producers: Vec<_> = (1..4).map(|i| {
+            let queue = queue.clone();
+            let producer = thread::spawn(move || queue.enqueue_blocking(i));
+            thread::sleep(Duration::from_millis(20));
+            producer
+        }).collect();
+        let mut elements = vec![];
+        while elements.len() < 4 {
+            match queue.dequeue() {
+                Some(element) => elements.push(element),
+                None => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        assert_eq!(elements, [0, 1, 2, 3]);
+        for producer in producers {
+            assert_eq!(producer.join().expect("join"), Ok(()));
+        }
+
+        // Closing the queue gives the elements back to the blocked producers.
+        queue.enqueue(4).expect("enqueue");
+        let producers: Vec<_> = (5..7).map(|i| {
+            let queue = queue.clone();
+            thread::spawn(move || queue.enqueue_blocking(i))
+        }).collect();
+        thread::sleep(Duration::from_millis(20));
+        queue.close();
+        let mut rejected: Vec<_> = producers.into_iter()
+            .map(|producer| producer.join().expect("join").expect_err("closed").into_inner())
+            .collect();
+        rejected.sort();
+        assert_eq!(rejected, [5, 6]);
+        assert_eq!(queue.dequeue(), Some(4));
+    }
+}
@@ -0,0 +1,266 @@
+//! A multi-producer queue whose every subscriber receives every element, for fanning out a stream
+//! of events.
+//!
+//! The elements are kept in a single list, where each subscriber holds a cursor on the last node
+//! it read. A node is counted by the cursors on it, by the link from the previous node and by
+//! the tail when it is the last one, and it is freed when nothing counts it anymore: once every
+//! subscriber went past it, which in turn releases the link to the next node.
+//!
+//! The producers link their node like the ones of `mpsc::Queue`: they exchange the tail with it,
+//! then link the previous tail to it. A new subscriber starts at the tail, which is protected by
+//! a hazard pointer while its count is incremented, so the freed nodes are retired to the hazard
+//! pointers instead of being freed directly.
+//!
+//! The cursors of the groups of a `GroupedQueue` are on the same kind of list, but are shared by
+//! the consumers of a group, who advance them with a compare-and-swap.
+
+use std::ptr;
+
+use reclaim::hazard::{self, Hazard};
+use sync::{AtomicPtr, AtomicUsize};
+use sync::atomic::Ordering;
+
+pub(crate) struct Node<T> {
+    pub(crate) next: AtomicPtr<Node<T>>,
+    /// None in the first sentinel.
+    pub(crate) value: Option<T>,
+    pub(crate) references: AtomicUsize,
+}
+
+impl<T> Node<T> {
+    fn new(value: Option<T>, references: usize) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            value,
+            references: AtomicUsize::new(references),
+        }))
+    }
+}
+
+/// Drop a reference to `node`, freeing it and the following nodes which are not referenced
+/// anymore.
+pub(crate) unsafe fn release<T>(mut node: *mut Node<T>) {
+    while !node.is_null() && (*node).references.fetch_sub(1, Ordering::AcqRel) == 1 {
+        // The tail references the last node, so the next one is linked when nothing references
+        // this one, unless the queue was dropped.
+        let next = (*node).next.load(Ordering::Acquire);
+        // A new subscriber could still protect it, if it was the tail.
+        hazard::retire(node, move || drop(Box::from_raw(node)));
+        node = next;
+    }
+}
+
+/// An unbounded lock-free queue where every `Subscriber` receives a clone of each element
+/// enqueued after its subscription.
+pub struct Queue<T> {
+    /// The last node, exchanged by the producers. It holds a reference to it.
+    tail: AtomicPtr<Node<T>>,
+}
+
+// The subscribers clone the same elements concurrently.
+unsafe impl<T: Send + Sync> Send for Queue<T> {}
+unsafe impl<T: Send + Sync> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    pub fn new() -> Self {
+        Self {
+            tail: AtomicPtr::new(Node::new(None, 1)),
+        }
+    }
+
+    pub fn enqueue(&self, value: T) {
+        // Referenced by the tail and by the link from the previous node.
+        let node = Node::new(Some(value), 2);
+        // AcqRel to order the initialization of our node before the next producer links to it.
+        let previous = self.tail.swap(node, Ordering::AcqRel);
+        unsafe {
+            // Until this store, the elements enqueued after ours are invisible to the subscribers.
+            (*previous).next.store(node, Ordering::Release);
+            // The reference of the tail to the previous node was moved to us by the exchange.
+            release(previous);
+        }
+    }
+
+    /// Create a subscriber receiving the elements enqueued from now on.
+    pub fn subscribe(&self) -> Subscriber<T> {
+        Subscriber {
+            cursor: self.reference_tail(),
+        }
+    }
+
+    /// Add a reference to the last node, for a new cursor.
+    pub(crate) fn reference_tail(&self) -> *mut Node<T> {
+        let hazard = Hazard::new();
+        loop {
+            let tail = hazard.protect(&self.tail);
+            // The tail references its node, so the count can only be zero after the node stopped
+            // being the tail.
+            let referenced = unsafe { &(*tail).references }.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                if count == 0 { None } else { Some(count + 1) }
+            });
+            if referenced.is_ok() {
+                return tail;
+            }
+        }
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        unsafe { release(self.tail.load(Ordering::Acquire)) };
+    }
+}
+
+/// A cursor in a `broadcast::Queue`, which it can outlive. Cloning it creates a subscriber at the
+/// same position.
+pub struct Subscriber<T> {
+    /// The last node read, which it references.
+    cursor: *mut Node<T>,
+}
+
+unsafe impl<T: Send + Sync> Send for Subscriber<T> {}
+unsafe impl<T: Send + Sync> Sync for Subscriber<T> {}
+
+impl<T: Clone> Subscriber<T> {
+    /// Receive a clone of the next element, or return `None` if there is none or if its producer
+    /// is between its two steps.
+    pub fn dequeue(&mut self) -> Option<T> {
+        let next = unsafe { (*self.cursor).next.load(Ordering::Acquire) };
+        if next.is_null() {
+            return None;
+        }
+        unsafe {
+            // The link from the current node keeps the next one alive until the cursor moves.
+            (*next).references.fetch_add(1, Ordering::Relaxed);
+            release(self.cursor);
+            self.cursor = next;
+            (*next).value.clone()
+        }
+    }
+}
+
+impl<T> Subscriber<T> {
+    /// Whether every element enqueued so far was received, except the ones of the producers
+    /// between their two steps.
+    pub fn is_empty(&self) -> bool {
+        unsafe { (*self.cursor).next.load(Ordering::Acquire).is_null() }
+    }
+}
+
+impl<T> Clone for Subscriber<T> {
+    fn clone(&self) -> Self {
+        unsafe { (*self.cursor).references.fetch_add(1, Ordering::Relaxed) };
+        Self {
+            cursor: self.cursor,
+        }
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        unsafe { release(self.cursor) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use reclaim::hazard;
+    use super::Queue;
+
+    #[test]
+    fn test_single_thread() {
+        let queue = Queue::new();
+        queue.enqueue(0);
+        let mut first = queue.subscribe();
+        assert!(first.is_empty());
+        queue.enqueue(1);
+        let mut second = queue.subscribe();
+        queue.enqueue(2);
+        let mut third = first.clone();
+        assert_eq!(first.dequeue(), Some(1));
+        assert_eq!(first.dequeue(), Some(2));
+        assert_eq!(first.dequeue(), None);
+        assert_eq!(second.dequeue(), Some(2));
+        assert_eq!(second.dequeue(), None);
+        assert_eq!(third.dequeue(), Some(1));
+        drop(queue);
+        assert_eq!(third.dequeue(), Some(2));
+        assert_eq!(third.dequeue(), None);
+    }
+
+    #[test]
+    fn test_reclaim() {
+        let value = Arc::new(());
+        let queue = Queue::new();
+        let mut subscriber = queue.subscribe();
+        for _ in 0..10 {
+            queue.enqueue(value.clone());
+        }
+        // The subscriber keeps every element it did not receive.
+        hazard::flush();
+        assert_eq!(Arc::strong_count(&value), 11);
+
+        let mut late = queue.subscribe();
+        for _ in 0..5 {
+            assert!(subscriber.dequeue().is_some());
+        }
+        // The node of the cursor keeps its element.
+        hazard::flush();
+        assert_eq!(Arc::strong_count(&value), 7);
+        for _ in 0..5 {
+            assert!(subscriber.dequeue().is_some());
+        }
+        hazard::flush();
+        assert_eq!(Arc::strong_count(&value), 2);
+        assert!(late.dequeue().is_none());
+        drop(subscriber);
+        drop(late);
+        drop(queue);
+        hazard::flush();
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 200 } else { 100_000 };
+        let queue = Queue::new();
+        let subscribers: Vec<_> = (0..4).map(|_| queue.subscribe()).collect();
+        thread::scope(|scope| {
+            for thread in 0..4 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..count {
+                        queue.enqueue((thread, i));
+                    }
+                });
+            }
+
+            for mut subscriber in subscribers {
+                scope.spawn(move || {
+                    // Every subscriber receives the elements of each producer in order.
+                    let mut next = [0; 4];
+                    for _ in 0..4 * count {
+                        loop {
+                            if let Some((thread, i)) = subscriber.dequeue() {
+                                assert_eq!(i, next[thread]);
+                                next[thread] += 1;
+                                break;
+                            }
+                            thread::yield_now();
+                        }
+                    }
+                    assert_eq!(next, [count; 4]);
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,215 @@
+//! Byte-message mode: the elements are opaque byte strings, so that producers and consumers
+//! written in other languages can share a queue without agreeing on a Rust type layout.
+//!
+//! Every message is copied in its own block prefixed by its length, as a little-endian `u32`.
+//! The `lfq_byte_queue_*` functions expose the queue through the C ABI.
+
+use std::os::raw::c_int;
+use std::ptr;
+use std::slice;
+
+use Queue;
+
+const PREFIX_SIZE: usize = 4;
+
+/// A length-prefixed byte message.
+pub struct ByteMessage {
+    block: Box<[u8]>,
+}
+
+impl ByteMessage {
+    pub fn new(payload: &[u8]) -> Self {
+        assert!(payload.len() <= u32::MAX as usize, "byte message too long");
+        let mut block = Vec::with_capacity(PREFIX_SIZE + payload.len());
+        block.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        block.extend_from_slice(payload);
+        Self {
+            block: block.into_boxed_slice(),
+        }
+    }
+
+    /// The message, including its length prefix.
+    pub fn as_prefixed(&self) -> &[u8] {
+        &self.block
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.block[PREFIX_SIZE..]
+    }
+
+    pub fn len(&self) -> usize {
+        self.block.len() - PREFIX_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A queue of byte messages.
+pub struct ByteQueue {
+    queue: Queue<ByteMessage>,
+}
+
+impl ByteQueue {
+    pub fn new() -> Self {
+        Self {
+            queue: Queue::new(),
+        }
+    }
+
+    /// Copy a message in the queue.
+    pub fn enqueue(&self, payload: &[u8]) {
+        self.queue.enqueue(ByteMessage::new(payload)).expect("enqueue, since the queue is never closed");
+    }
+
+    pub fn dequeue(&self) -> Option<ByteMessage> {
+        self.queue.dequeue()
+    }
+
+    /// Copy the first message in `buffer` and remove it from the queue.
+    ///
+    /// Returns the length of the message, or `Err(length)` if the buffer is too small, in which
+    /// case the message stays at the front of the queue.
+    pub fn dequeue_into(&self, buffer: &mut [u8]) -> Option<Result<usize, usize>> {
+        let claim = self.queue.claim()?;
+        let len = claim.len();
+        if len > buffer.len() {
+            claim.abort();
+            return Some(Err(len));
+        }
+        buffer[..len].copy_from_slice(claim.payload());
+        claim.commit();
+        Some(Ok(len))
+    }
+}
+
+impl Default for ByteQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub const LFQ_OK: c_int = 0;
+pub const LFQ_EMPTY: c_int = 1;
+pub const LFQ_TOO_SMALL: c_int = 2;
+
+#[no_mangle]
+pub extern "C" fn lfq_byte_queue_new() -> *mut ByteQueue {
+    Box::into_raw(Box::new(ByteQueue::new()))
+}
+
+/// # Safety
+///
+/// `queue` must come from `lfq_byte_queue_new()` and not be used anymore.
+#[no_mangle]
+pub unsafe extern "C" fn lfq_byte_queue_free(queue: *mut ByteQueue) {
+    if !queue.is_null() {
+        drop(Box::from_raw(queue));
+    }
+}
+
+/// # Safety
+///
+/// `queue` must come from `lfq_byte_queue_new()` and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lfq_byte_queue_enqueue(queue: *const ByteQueue, data: *const u8, len: usize) {
+    let payload =
+        if len == 0 {
+            &[]
+        }
+        else {
+            slice::from_raw_parts(data, len)
+        };
+    (*queue).enqueue(payload);
+}
+
+/// Copy the first message in `buffer` and remove it from the queue, writing its length in `len`.
+///
+/// Returns `LFQ_EMPTY` if the queue is empty and `LFQ_TOO_SMALL` if the message does not fit in
+/// `capacity` bytes, in which case `len` receives the required size.
+///
+/// # Safety
+///
+/// `queue` must come from `lfq_byte_queue_new()`, `buffer` must point to `capacity` writable
+/// bytes and `len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lfq_byte_queue_dequeue(queue: *const ByteQueue, buffer: *mut u8, capacity: usize,
+    len: *mut usize) -> c_int
+{
+    let buffer =
+        if capacity == 0 {
+            &mut []
+        }
+        else {
+            slice::from_raw_parts_mut(buffer, capacity)
+        };
+    match (*queue).dequeue_into(buffer) {
+        None => LFQ_EMPTY,
+        Some(Ok(size)) => {
+            ptr::write(len, size);
+            LFQ_OK
+        },
+        Some(Err(size)) => {
+            ptr::write(len, size);
+            LFQ_TOO_SMALL
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::{
+        ByteQueue,
+        LFQ_EMPTY,
+        LFQ_OK,
+        LFQ_TOO_SMALL,
+        lfq_byte_queue_dequeue,
+        lfq_byte_queue_enqueue,
+        lfq_byte_queue_free,
+        lfq_byte_queue_new,
+    };
+
+    #[test]
+    fn test_messages() {
+        let queue = ByteQueue::new();
+        queue.enqueue(b"hello");
+        queue.enqueue(b"");
+        queue.enqueue(b"world!");
+
+        let message = queue.dequeue().expect("message");
+        assert_eq!(message.payload(), b"hello");
+        assert_eq!(message.as_prefixed(), b"\x05\x00\x00\x00hello");
+
+        let mut buffer = [0; 5];
+        assert_eq!(queue.dequeue_into(&mut buffer), Some(Ok(0)));
+        assert_eq!(queue.dequeue_into(&mut buffer), Some(Err(6)));
+        let mut buffer = [0; 16];
+        assert_eq!(queue.dequeue_into(&mut buffer), Some(Ok(6)));
+        assert_eq!(&buffer[..6], b"world!");
+        assert_eq!(queue.dequeue_into(&mut buffer), None);
+    }
+
+    #[test]
+    fn test_c_api() {
+        unsafe {
+            let queue = lfq_byte_queue_new();
+            lfq_byte_queue_enqueue(queue, b"abc".as_ptr(), 3);
+            lfq_byte_queue_enqueue(queue, ptr::null(), 0);
+
+            let mut len = 0;
+            let mut buffer = [0u8; 2];
+            assert_eq!(lfq_byte_queue_dequeue(queue, buffer.as_mut_ptr(), buffer.len(), &mut len), LFQ_TOO_SMALL);
+            assert_eq!(len, 3);
+            let mut buffer = [0u8; 3];
+            assert_eq!(lfq_byte_queue_dequeue(queue, buffer.as_mut_ptr(), buffer.len(), &mut len), LFQ_OK);
+            assert_eq!(&buffer, b"abc");
+            assert_eq!(lfq_byte_queue_dequeue(queue, ptr::null_mut(), 0, &mut len), LFQ_OK);
+            assert_eq!(len, 0);
+            assert_eq!(lfq_byte_queue_dequeue(queue, ptr::null_mut(), 0, &mut len), LFQ_EMPTY);
+            lfq_byte_queue_free(queue);
+        }
+    }
+}
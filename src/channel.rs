@@ -0,0 +1,234 @@
+//! Sender and receiver handles over a queue, like the channels of `std::sync::mpsc` but with
+//! cloneable receivers.
+//!
+//! The queue is closed when the last sender or the last receiver is dropped: the receivers then
+//! get the remaining elements before being told that the channel is disconnected, and the senders
+//! get their elements back.
+//!
+//! With the `futures` feature, the receivers implement `Stream`.
+
+#[cfg(feature = "futures")]
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "futures")]
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+#[cfg(feature = "futures")]
+use futures_core::Stream;
+
+use {Dequeue, DequeueError, Queue, RecvTimeoutError, SendError};
+use iter::{Iter, TryIter};
+#[cfg(feature = "futures")]
+use wake::Registration;
+
+struct Shared<T> {
+    queue: Queue<T>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+/// Create a channel over a new queue.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    Queue::new().split()
+}
+
+impl<T> Queue<T> {
+    /// Turn the queue into the two ends of a channel, keeping its elements.
+    pub fn split(self) -> (Sender<T>, Receiver<T>) {
+        let shared = Arc::new(Shared {
+            queue: self,
+            senders: AtomicUsize::new(1),
+            receivers: AtomicUsize::new(1),
+        });
+        let receiver = Receiver {
+            shared: shared.clone(),
+            #[cfg(feature = "futures")]
+            registration: Registration::default(),
+        };
+        (Sender { shared }, receiver)
+    }
+}
+
+/// The sending end of a channel.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Add an element to the channel, or give it back if every receiver was dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.shared.queue.try_enqueue(value)
+    }
+
+    /// Whether every receiver was dropped.
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.queue.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::SeqCst);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.queue.close();
+        }
+    }
+}
+
+/// The receiving end of a channel. Each element is received by only one of the clones.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// Waker slot of the stream.
+    #[cfg(feature = "futures")]
+    registration: Registration,
+}
+
+impl<T> Receiver<T> {
+    /// Receive an element, blocking while the channel is empty, or None once every sender was
+    /// dropped and the channel is drained.
+    pub fn recv(&self) -> Option<T> {
+        self.iter().next()
+    }
+
+    /// Receive an element, waiting asynchronously while the channel is empty, or None once
+    /// every sender was dropped and the channel is drained.
+    pub fn recv_async(&self) -> Dequeue<'_, T> {
+        self.shared.queue.dequeue_async()
+    }
+
+    /// Receive an element without blocking.
+    pub fn try_recv(&self) -> Result<T, DequeueError> {
+        self.shared.queue.try_dequeue()
+    }
+
+    /// Receive an element, blocking while the channel is empty until the timeout expires or
+    /// every sender is dropped.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.shared.queue.dequeue_timeout(timeout)
+    }
+
+    /// Iterate over the elements, blocking while the channel is empty, until every sender is
+    /// dropped and the channel is drained.
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.shared.queue.iter()
+    }
+
+    /// Iterate over the elements available without blocking.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        self.shared.queue.try_iter()
+    }
+
+    /// Whether every sender was dropped. Elements can still be waiting in the channel.
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.queue.is_closed()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.is_empty()
+    }
+}
+
+/// The stream ends once every sender was dropped and the channel is drained.
+#[cfg(feature = "futures")]
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        this.shared.queue.poll_dequeue(&mut this.registration, context)
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::SeqCst);
+        Self {
+            shared: self.shared.clone(),
+            #[cfg(feature = "futures")]
+            registration: Registration::default(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.queue.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use {DequeueError, SendError};
+    use super::channel;
+
+    #[test]
+    fn test_senders_dropped() {
+        let (sender, receiver) = channel();
+        let producers: Vec<_> = (0..4).map(|i| {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for j in 0..100 {
+                    sender.send(i * 100 + j).expect("send");
+                }
+            })
+        }).collect();
+        drop(sender);
+        let mut received: Vec<_> = receiver.iter().collect();
+        for producer in producers {
+            producer.join().expect("join");
+        }
+        received.sort();
+        assert_eq!(received, (0..400).collect::<Vec<_>>());
+        assert!(receiver.is_disconnected());
+        assert_eq!(receiver.recv(), None);
+        assert_eq!(receiver.try_recv(), Err(DequeueError::Closed));
+    }
+
+    #[test]
+    fn test_receivers_dropped() {
+        let (sender, receiver) = channel();
+        sender.send(1).expect("send");
+        let other = receiver.clone();
+        drop(receiver);
+        assert!(!sender.is_disconnected());
+        assert_eq!(other.try_recv(), Ok(1));
+        drop(other);
+        assert!(sender.is_disconnected());
+        assert_eq!(sender.send(2), Err(SendError::Closed(2)));
+    }
+
+    #[test]
+    #[cfg(feature = "futures")]
+    fn test_stream() {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Waker};
+
+        use futures_core::Stream;
+
+        let (sender, mut receiver) = channel();
+        let mut context = Context::from_waker(Waker::noop());
+        assert!(Pin::new(&mut receiver).poll_next(&mut context).is_pending());
+        sender.send(1).expect("send");
+        assert_eq!(Pin::new(&mut receiver).poll_next(&mut context), Poll::Ready(Some(1)));
+        drop(sender);
+        assert_eq!(Pin::new(&mut receiver).poll_next(&mut context), Poll::Ready(None));
+    }
+}
@@ -0,0 +1,182 @@
+//! A queue checking itself against a reference model, with the `checked` feature.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+
+use {Queue, SendError};
+
+struct Model<T> {
+    elements: VecDeque<T>,
+    closed: bool,
+    operations: usize,
+}
+
+/// A queue running every operation on both the lock-free queue and a reference model protected
+/// by a mutex, and panicking as soon as their results differ.
+///
+/// The operations are serialized by the mutex, so this is only meant to validate the crate in
+/// a real workload before trusting it in production, not for performance. The remaining elements
+/// are also compared when the queue is dropped.
+pub struct CheckedQueue<T: Clone + Debug + PartialEq> {
+    queue: Queue<T>,
+    model: Mutex<Model<T>>,
+}
+
+impl<T: Clone + Debug + PartialEq> CheckedQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            queue: Queue::new(),
+            model: Mutex::new(Model {
+                elements: VecDeque::new(),
+                closed: false,
+                operations: 0,
+            }),
+        }
+    }
+
+    pub fn enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        let mut model = self.lock();
+        let expected = if model.closed { Err(SendError::Closed(value.clone())) } else { Ok(()) };
+        if expected.is_ok() {
+            model.elements.push_back(value.clone());
+        }
+        let result = self.queue.enqueue(value);
+        check(&model, "enqueue", &result, &expected);
+        self.check_invariants(&model, "enqueue");
+        result
+    }
+
+    pub fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        let mut model = self.lock();
+        let expected = if model.closed { Err(SendError::Closed(value.clone())) } else { Ok(()) };
+        if expected.is_ok() {
+            model.elements.push_back(value.clone());
+        }
+        let result = self.queue.try_enqueue(value);
+        check(&model, "try_enqueue", &result, &expected);
+        self.check_invariants(&model, "try_enqueue");
+        result
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        let mut model = self.lock();
+        let expected = model.elements.pop_front();
+        let result = self.queue.dequeue();
+        check(&model, "dequeue", &result, &expected);
+        self.check_invariants(&model, "dequeue");
+        result
+    }
+
+    pub fn close(&self) {
+        let mut model = self.lock();
+        model.closed = true;
+        self.queue.close();
+        self.check_invariants(&model, "close");
+    }
+
+    pub fn reopen(&self) {
+        let mut model = self.lock();
+        model.closed = false;
+        self.queue.reopen();
+        self.check_invariants(&model, "reopen");
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+
+    /// Number of operations checked.
+    pub fn operations(&self) -> usize {
+        self.model.lock().unwrap_or_else(|error| error.into_inner()).operations
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Model<T>> {
+        let mut model = self.model.lock().unwrap_or_else(|error| error.into_inner());
+        model.operations += 1;
+        model
+    }
+
+    fn check_invariants(&self, model: &Model<T>, operation: &str) {
+        check(model, &format!("{} (closed)", operation), &self.queue.is_closed(), &model.closed);
+        check(model, &format!("{} (empty)", operation), &self.queue.is_drained(), &model.elements.is_empty());
+    }
+}
+
+impl<T: Clone + Debug + PartialEq> Default for CheckedQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + Debug + PartialEq> Drop for CheckedQueue<T> {
+    fn drop(&mut self) {
+        // Do not hide the original panic behind a second one, which would abort.
+        if thread::panicking() {
+            return;
+        }
+        let model = self.model.get_mut().unwrap_or_else(|error| error.into_inner());
+        let remaining: Vec<_> = self.queue.try_iter().collect();
+        let expected: Vec<_> = model.elements.drain(..).collect();
+        check(model, "drop", &remaining, &expected);
+    }
+}
+
+fn check<T, U: Debug + PartialEq>(model: &Model<T>, operation: &str, result: &U, expected: &U) {
+    assert!(result == expected, "CheckedQueue: {} (operation #{}) returned {:?} instead of {:?}", operation,
+        model.operations, result, expected);
+}
+
+#[cfg(all(test, feature = "checked"))]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use SendError;
+    use super::CheckedQueue;
+
+    #[test]
+    fn test_operations() {
+        let queue = CheckedQueue::new();
+        queue.enqueue(1).expect("enqueue");
+        queue.enqueue(2).expect("enqueue");
+        assert_eq!(queue.dequeue(), Some(1));
+        queue.close();
+        assert_eq!(queue.try_enqueue(3), Err(SendError::Closed(3)));
+        queue.reopen();
+        assert_eq!(queue.try_enqueue(4), Ok(()));
+        assert_eq!(queue.operations(), 7);
+        // 2 and 4 are checked when the queue is dropped.
+    }
+
+    #[test]
+    fn test_multithread() {
+        let queue = Arc::new(CheckedQueue::new());
+        let handles: Vec<_> = (0..4).map(|thread| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..200 {
+                    queue.enqueue(thread * 1_000 + i).expect("enqueue");
+                    if i % 2 == 0 {
+                        queue.dequeue();
+                    }
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().expect("join");
+        }
+        assert_eq!(queue.operations(), 4 * 300);
+    }
+
+    #[test]
+    #[should_panic(expected = "dequeue (operation #2) returned None instead of Some(1)")]
+    fn test_mismatch() {
+        let queue = CheckedQueue::new();
+        queue.enqueue(1).expect("enqueue");
+        // Pausing is not part of the model.
+        queue.queue.pause();
+        queue.dequeue();
+    }
+}
@@ -0,0 +1,153 @@
+use core::ops::{Deref, DerefMut};
+
+use Queue;
+use completion::Tracker;
+use reclaim::{DefaultReclaim, Reclaim};
+use sojourn::Stamp;
+
+/// An element removed from the queue which can still be given back.
+///
+/// While the claim is alive, the element is invisible to the other consumers. Dropping the claim
+/// without calling `commit()` aborts it.
+pub struct Claim<'a, T: 'a, R: 'a + Reclaim = DefaultReclaim> {
+    queue: &'a Queue<T, R>,
+    value: Option<T>,
+    tracker: Option<Tracker>,
+    pub(crate) stamp: Stamp,
+}
+
+impl<'a, T, R: Reclaim> Claim<'a, T, R> {
+    /// Finalize the removal of the element and take ownership of it.
+    ///
+    /// This is what resolves the `Completion` of an element enqueued with `enqueue_tracked()`.
+    pub fn commit(mut self) -> T {
+        if let Some(tracker) = self.tracker.take() {
+            tracker.complete();
+        }
+        self.queue.record_dequeue(self.stamp);
+        self.value.take().expect("value of claim")
+    }
+
+    /// Give the element back to the queue: it will be the next one to be dequeued.
+    pub fn abort(self) {
+        // The element is returned by the Drop implementation.
+    }
+}
+
+impl<'a, T, R: Reclaim> Deref for Claim<'a, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().expect("value of claim")
+    }
+}
+
+impl<'a, T, R: Reclaim> DerefMut for Claim<'a, T, R> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().expect("value of claim")
+    }
+}
+
+impl<'a, T, R: Reclaim> Drop for Claim<'a, T, R> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            let guard = self.queue.pin();
+            self.queue.push_front(value, self.tracker.take(), self.stamp, &guard);
+        }
+    }
+}
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Remove the first element of the queue, but only tentatively: see `Claim`.
+    pub fn claim(&self) -> Option<Claim<'_, T, R>> {
+        self.claim_guarded(&self.pin())
+    }
+
+    pub(crate) fn claim_guarded(&self, guard: &R::Guard) -> Option<Claim<'_, T, R>> {
+        self.pop(guard).map(|(value, tracker, stamp)| Claim {
+            queue: self,
+            value: Some(value),
+            tracker,
+            stamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use Queue;
+
+    #[test]
+    fn test_commit() {
+        let queue = Queue::new();
+        queue.enqueue(1).expect("enqueue");
+        queue.enqueue(2).expect("enqueue");
+        let claim = queue.claim().expect("claim");
+        assert_eq!(*claim, 1);
+        assert_eq!(claim.commit(), 1);
+        assert_eq!(queue.dequeue(), Some(2));
+        assert!(queue.claim().is_none());
+    }
+
+    #[test]
+    fn test_abort() {
+        let queue = Queue::new();
+        queue.enqueue(1).expect("enqueue");
+        queue.enqueue(2).expect("enqueue");
+        {
+            let claim = queue.claim().expect("claim");
+            // The claimed element is invisible to other consumers.
+            assert_eq!(queue.dequeue(), Some(2));
+            claim.abort();
+        }
+        queue.enqueue(3).expect("enqueue");
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.dequeue(), Some(3));
+        assert_eq!(queue.dequeue(), None);
+
+        // Dropping a claim also returns the element, even to an empty queue.
+        drop(queue.claim());
+        queue.enqueue(4).expect("enqueue");
+        drop(queue.claim());
+        assert_eq!(queue.dequeue(), Some(4));
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_abort_multithread() {
+        // Miri is too slow for more elements, but still interleaves the threads enough.
+        let count = if cfg!(miri) { 200 } else { 10_000 };
+        let queue = Arc::new(Queue::new());
+        for i in 0..count {
+            queue.enqueue(i).expect("enqueue");
+        }
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut elements = vec![];
+                let mut attempts = 0;
+                while let Some(claim) = queue.claim() {
+                    attempts += 1;
+                    // Give back one claim out of three.
+                    if attempts % 3 == 0 {
+                        claim.abort();
+                    }
+                    else {
+                        elements.push(claim.commit());
+                    }
+                }
+                elements
+            })
+        }).collect();
+
+        let mut results: Vec<_> = handles.into_iter()
+            .flat_map(|handle| handle.join().expect("join"))
+            .collect();
+        results.sort();
+        assert_eq!(results, (0..count).collect::<Vec<_>>());
+    }
+}
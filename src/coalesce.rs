@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ptr;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use Queue;
+
+/// The pending value of a key, or null once it was dequeued.
+struct Slot<K, V> {
+    key: K,
+    value: AtomicPtr<V>,
+}
+
+impl<K, V> Drop for Slot<K, V> {
+    fn drop(&mut self) {
+        let value = *self.value.get_mut();
+        if !value.is_null() {
+            drop(unsafe { Box::from_raw(value) });
+        }
+    }
+}
+
+/// A queue keeping only the latest value of each key, for consumers which only care about the
+/// current state, like the views of a UI.
+///
+/// Enqueuing a value for a key which is still pending replaces its value, which keeps the place of
+/// the first one in the queue. Each key has a slot holding its pending value, which the producers
+/// exchange, and which is in the queue while it is not empty: the value is replaced without
+/// taking any lock nor enqueuing anything.
+///
+/// The slots are found in a map, which is only locked for writing when a key is seen for the
+/// first time. They are kept after being dequeued, so the set of keys must be bounded.
+pub struct CoalescingQueue<K, V> {
+    slots: RwLock<HashMap<K, Arc<Slot<K, V>>>>,
+    /// The slots which hold a value.
+    queue: Queue<Arc<Slot<K, V>>>,
+}
+
+// The values are moved in by the producers and out by the consumers, and the keys are shared.
+unsafe impl<K: Send + Sync, V: Send> Send for CoalescingQueue<K, V> {}
+unsafe impl<K: Send + Sync, V: Send> Sync for CoalescingQueue<K, V> {}
+
+impl<K: Clone + Eq + Hash, V> CoalescingQueue<K, V> {
+    pub fn new() -> Self {
+        Self {
+            slots: RwLock::new(HashMap::new()),
+            queue: Queue::new(),
+        }
+    }
+
+    /// Enqueue the value of `key`, or replace its pending value, which is returned.
+    pub fn enqueue_keyed(&self, key: K, value: V) -> Option<V> {
+        let slot = self.slot(key);
+        let previous = slot.value.swap(Box::into_raw(Box::new(value)), Ordering::AcqRel);
+        if previous.is_null() {
+            // The slot was empty, so it was not in the queue.
+            self.queue.enqueue(slot).expect("enqueue, since the queue is never closed");
+            None
+        }
+        else {
+            Some(*unsafe { Box::from_raw(previous) })
+        }
+    }
+
+    /// Dequeue the key whose value is pending for the longest time, with its latest value.
+    pub fn dequeue(&self) -> Option<(K, V)> {
+        let slot = self.queue.dequeue()?;
+        // Only the consumer which dequeued the slot empties it.
+        let value = slot.value.swap(ptr::null_mut(), Ordering::AcqRel);
+        debug_assert!(!value.is_null(), "queued slot without value");
+        Some((slot.key.clone(), *unsafe { Box::from_raw(value) }))
+    }
+
+    /// Number of keys with a pending value.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn slot(&self, key: K) -> Arc<Slot<K, V>> {
+        if let Some(slot) = self.slots.read().expect("read lock").get(&key) {
+            return slot.clone();
+        }
+        self.slots.write().expect("write lock")
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(Slot {
+                key,
+                value: AtomicPtr::new(ptr::null_mut()),
+            }))
+            .clone()
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> Default for CoalescingQueue<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::CoalescingQueue;
+
+    #[test]
+    fn test_replace() {
+        let queue = CoalescingQueue::new();
+        assert_eq!(queue.enqueue_keyed("width", 800), None);
+        assert_eq!(queue.enqueue_keyed("height", 600), None);
+        assert_eq!(queue.enqueue_keyed("width", 1024), Some(800));
+        assert_eq!(queue.len(), 2);
+        // The replaced value keeps the place of the first one.
+        assert_eq!(queue.dequeue(), Some(("width", 1024)));
+        assert_eq!(queue.enqueue_keyed("width", 1280), None);
+        assert_eq!(queue.dequeue(), Some(("height", 600)));
+        assert_eq!(queue.dequeue(), Some(("width", 1280)));
+        assert_eq!(queue.dequeue(), None);
+
+        // The pending values are dropped with the queue.
+        let value = Arc::new(());
+        let queue = CoalescingQueue::new();
+        queue.enqueue_keyed(0, value.clone());
+        queue.enqueue_keyed(1, value.clone());
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 200 } else { 10_000 };
+        let queue = CoalescingQueue::new();
+        let last = thread::scope(|scope| {
+            for key in 0..4 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..count {
+                        queue.enqueue_keyed(key, i);
+                    }
+                });
+            }
+
+            // The values of each key are received in order, with some of them skipped.
+            let mut last = [None; 4];
+            while last.iter().any(|&value| value != Some(count - 1)) {
+                if let Some((key, value)) = queue.dequeue() {
+                    assert!(last[key].is_none_or(|last| last < value));
+                    last[key] = Some(value);
+                }
+            }
+            last
+        });
+        assert_eq!(last, [Some(count - 1); 4]);
+        assert!(queue.is_empty());
+    }
+}
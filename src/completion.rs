@@ -0,0 +1,272 @@
+#[cfg(feature = "std")]
+use std::future::Future;
+#[cfg(feature = "std")]
+use std::pin::Pin;
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+#[cfg(feature = "std")]
+use std::task::{Context, Poll};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "std")]
+use {Node, Queue, SendError};
+#[cfg(feature = "std")]
+use reclaim::Reclaim;
+#[cfg(feature = "std")]
+use wake::{Notifier, Registration};
+
+#[cfg(feature = "std")]
+const PENDING: usize = 0;
+#[cfg(feature = "std")]
+const CONSUMED: usize = 1;
+#[cfg(feature = "std")]
+const DROPPED: usize = 2;
+
+#[cfg(feature = "std")]
+struct Signal {
+    state: AtomicUsize,
+    notifier: Notifier,
+}
+
+#[cfg(feature = "std")]
+impl Signal {
+    fn resolve(&self, state: usize) {
+        self.state.store(state, Ordering::SeqCst);
+        self.notifier.notify();
+    }
+
+    fn outcome(&self) -> Option<bool> {
+        match self.state.load(Ordering::SeqCst) {
+            PENDING => None,
+            state => Some(state == CONSUMED),
+        }
+    }
+}
+
+/// The producer side of a `Completion`, stored along with the element.
+///
+/// If it is dropped without being completed, the element was discarded without being consumed.
+#[cfg(feature = "std")]
+pub(crate) struct Tracker {
+    signal: Arc<Signal>,
+}
+
+#[cfg(feature = "std")]
+impl Tracker {
+    pub(crate) fn complete(self) {
+        self.signal.resolve(CONSUMED);
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        if self.signal.outcome().is_none() {
+            self.signal.resolve(DROPPED);
+        }
+    }
+}
+
+/// Handle resolved when an element enqueued with `enqueue_tracked()` is dequeued (or when its
+/// claim is committed).
+///
+/// It can be waited for synchronously or awaited: the outcome is `true` if the element was
+/// consumed and `false` if it was dropped without being consumed.
+#[cfg(feature = "std")]
+pub struct Completion {
+    signal: Arc<Signal>,
+    registration: Registration,
+}
+
+#[cfg(feature = "std")]
+impl Completion {
+    /// Return the outcome if the element was already consumed or dropped.
+    pub fn try_wait(&self) -> Option<bool> {
+        self.signal.outcome()
+    }
+
+    /// Block until the element is consumed or dropped.
+    pub fn wait(self) -> bool {
+        loop {
+            let listener = self.signal.notifier.listen();
+            if let Some(outcome) = self.signal.outcome() {
+                return outcome;
+            }
+            listener.wait(None);
+        }
+    }
+
+    /// Block until the element is consumed or dropped, or until the timeout expires, in which
+    /// case `None` is returned.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let listener = self.signal.notifier.listen();
+            if let Some(outcome) = self.signal.outcome() {
+                return Some(outcome);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            listener.wait(Some(deadline));
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Future for Completion {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<bool> {
+        if let Some(outcome) = self.signal.outcome() {
+            return Poll::Ready(outcome);
+        }
+        let this = &mut *self;
+        this.signal.notifier.register(&mut this.registration, context.waker());
+        // Check again in case the element was consumed before the registration.
+        match self.signal.outcome() {
+            Some(outcome) => Poll::Ready(outcome),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Add an element to the queue and return a handle resolved when it is consumed, or give the
+    /// element back if the queue is closed.
+    pub fn enqueue_tracked(&self, value: T) -> Result<Completion, SendError<T>> {
+        self.enqueue_open(value, |value| {
+            let signal = Arc::new(Signal {
+                state: AtomicUsize::new(PENDING),
+                notifier: Notifier::new(self.notifier.backend_handle()),
+            });
+            let tracker = Tracker {
+                signal: signal.clone(),
+            };
+            let guard = self.pin();
+            self.enqueue_node(self.new_node(Node::new(value, Some(tracker))), &guard);
+            Ok(Completion {
+                signal,
+                registration: Registration::default(),
+            })
+        })
+    }
+}
+
+/// Without the `std` feature, the elements cannot be tracked.
+#[cfg(not(feature = "std"))]
+pub(crate) enum Tracker {}
+
+#[cfg(not(feature = "std"))]
+impl Tracker {
+    pub(crate) fn complete(self) {
+        match self {}
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+    use std::time::Duration;
+
+    use Queue;
+    use wake::ParkBackend;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(mut future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        // Safety: the future is never moved after being pinned.
+        let mut future = unsafe { Pin::new_unchecked(&mut future) };
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_dequeue_completes() {
+        let queue = Queue::new();
+        let first = queue.enqueue_tracked(1).expect("enqueue");
+        let second = queue.enqueue_tracked(2).expect("enqueue");
+        assert_eq!(first.try_wait(), None);
+        assert_eq!(first.wait_timeout(Duration::from_millis(10)), None);
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(first.try_wait(), Some(true));
+        assert!(first.wait());
+        assert_eq!(second.try_wait(), None);
+    }
+
+    #[test]
+    fn test_drop_queue() {
+        let queue = Queue::new();
+        let completion = queue.enqueue_tracked(1).expect("enqueue");
+        drop(queue);
+        assert_eq!(completion.try_wait(), Some(false));
+    }
+
+    #[test]
+    fn test_claim_completes_on_commit() {
+        let queue = Queue::new();
+        let completion = queue.enqueue_tracked(1).expect("enqueue");
+        queue.claim().expect("claim").abort();
+        assert_eq!(completion.try_wait(), None);
+        let claim = queue.claim().expect("claim");
+        assert_eq!(completion.try_wait(), None);
+        assert_eq!(claim.commit(), 1);
+        assert_eq!(completion.try_wait(), Some(true));
+    }
+
+    #[test]
+    fn test_wait_from_other_threads() {
+        let queue = Arc::new(Queue::new());
+        let sync = queue.enqueue_tracked(1).expect("enqueue");
+        let async_ = queue.enqueue_tracked(2).expect("enqueue");
+
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                assert_eq!(queue.dequeue(), Some(1));
+                thread::sleep(Duration::from_millis(50));
+                assert_eq!(queue.dequeue(), Some(2));
+            })
+        };
+
+        assert!(sync.wait());
+        assert!(block_on(async_));
+        consumer.join().expect("join");
+    }
+
+    #[test]
+    fn test_wake_backend() {
+        let queue = Arc::new(Queue::with_wake_backend(Arc::new(ParkBackend::new())));
+        let completion = queue.enqueue_tracked(1).expect("enqueue");
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                assert_eq!(queue.dequeue(), Some(1));
+            })
+        };
+        assert!(completion.wait());
+        consumer.join().expect("join");
+    }
+}
@@ -0,0 +1,197 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use Queue;
+
+/// An element waiting for its deadline, ordered by deadline then by arrival.
+struct Delayed<T> {
+    deadline: Instant,
+    sequence: u64,
+    value: T,
+}
+
+impl<T> PartialEq for Delayed<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Delayed<T> {
+}
+
+impl<T> PartialOrd for Delayed<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Delayed<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deadline, self.sequence).cmp(&(other.deadline, other.sequence))
+    }
+}
+
+/// A queue whose elements can only be dequeued once their deadline has passed, for instance to
+/// retry failed operations after a backoff.
+///
+/// The producers enqueue into a lock-free queue and never block. The consumers move the elements
+/// from it to a heap ordered by deadline, which they lock to take the ripe elements. The elements
+/// with the same deadline are dequeued in the order they were moved to the heap.
+pub struct DelayQueue<T> {
+    incoming: Queue<(Instant, T)>,
+    /// The elements moved from `incoming`, and the number of elements moved so far.
+    delayed: Mutex<(BinaryHeap<Reverse<Delayed<T>>>, u64)>,
+}
+
+impl<T> DelayQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            incoming: Queue::new(),
+            delayed: Mutex::new((BinaryHeap::new(), 0)),
+        }
+    }
+
+    /// Add an element which stays hidden until `deadline`.
+    pub fn enqueue_after(&self, value: T, deadline: Instant) {
+        self.incoming.enqueue((deadline, value)).expect("enqueue, since the queue is never closed");
+    }
+
+    /// Add an element which stays hidden for `delay`.
+    pub fn enqueue_in(&self, value: T, delay: Duration) {
+        self.enqueue_after(value, Instant::now() + delay);
+    }
+
+    /// Dequeue the element with the earliest deadline, if it has passed.
+    pub fn dequeue(&self) -> Option<T> {
+        self.dequeue_ripe(Instant::now()).ok()
+    }
+
+    /// Dequeue the element with the earliest deadline, parking the thread until it passes or an
+    /// element is enqueued.
+    pub fn dequeue_blocking(&self) -> T {
+        self.dequeue_until(None).expect("element, since the wait has no deadline")
+    }
+
+    /// Dequeue the element with the earliest deadline, parking the thread until it passes or the
+    /// timeout expires.
+    pub fn dequeue_timeout(&self, timeout: Duration) -> Option<T> {
+        self.dequeue_until(Some(Instant::now() + timeout))
+    }
+
+    /// The earliest deadline of the elements, passed or not.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let delayed = self.lock();
+        delayed.0.peek().map(|Reverse(element)| element.deadline)
+    }
+
+    /// Number of elements in the queue, ripe or not.
+    pub fn len(&self) -> usize {
+        self.lock().0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn dequeue_until(&self, deadline: Option<Instant>) -> Option<T> {
+        loop {
+            let listener = self.incoming.notifier.listen();
+            // An element enqueued from now on wakes us up, in case its deadline is earlier.
+            let now = Instant::now();
+            let next_deadline =
+                match self.dequeue_ripe(now) {
+                    Ok(value) => return Some(value),
+                    Err(next_deadline) => next_deadline,
+                };
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return None;
+            }
+            let wake_up = next_deadline.into_iter().chain(deadline).min();
+            listener.wait(wake_up);
+        }
+    }
+
+    /// Dequeue the earliest element if its deadline is before `now`, or return the earliest
+    /// deadline.
+    fn dequeue_ripe(&self, now: Instant) -> Result<T, Option<Instant>> {
+        let mut delayed = self.lock();
+        let (ref mut heap, _) = *delayed;
+        match heap.peek() {
+            Some(Reverse(element)) if element.deadline <= now => (),
+            Some(Reverse(element)) => return Err(Some(element.deadline)),
+            None => return Err(None),
+        }
+        heap.pop().map(|Reverse(element)| element.value).ok_or(None)
+    }
+
+    /// Lock the heap after moving the incoming elements to it.
+    fn lock(&self) -> MutexGuard<'_, (BinaryHeap<Reverse<Delayed<T>>>, u64)> {
+        let mut delayed = self.delayed.lock().expect("lock");
+        let (ref mut heap, ref mut sequence) = *delayed;
+        for (deadline, value) in self.incoming.try_iter() {
+            heap.push(Reverse(Delayed {
+                deadline,
+                sequence: *sequence,
+                value,
+            }));
+            *sequence += 1;
+        }
+        delayed
+    }
+}
+
+impl<T> Default for DelayQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::DelayQueue;
+
+    #[test]
+    fn test_deadlines() {
+        let queue = DelayQueue::new();
+        let now = Instant::now();
+        queue.enqueue_after(3, now + Duration::from_secs(3600));
+        queue.enqueue_after(1, now);
+        queue.enqueue_after(2, now);
+        queue.enqueue_after(0, now - Duration::from_secs(1));
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.dequeue(), Some(0));
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.dequeue(), Some(2));
+        // The last element is not ripe yet.
+        assert_eq!(queue.dequeue(), None);
+        assert_eq!(queue.next_deadline(), Some(now + Duration::from_secs(3600)));
+        assert_eq!(queue.dequeue_timeout(Duration::from_millis(10)), None);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_blocking() {
+        let queue = Arc::new(DelayQueue::new());
+        let start = Instant::now();
+        queue.enqueue_in(2, Duration::from_millis(100));
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                // Earlier than the element the consumer is waiting for.
+                queue.enqueue_in(1, Duration::from_millis(20));
+            })
+        };
+        assert_eq!(queue.dequeue_blocking(), 1);
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(queue.dequeue_blocking(), 2);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        producer.join().expect("join");
+    }
+}
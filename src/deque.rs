@@ -0,0 +1,301 @@
+//! A work-stealing deque, for the schedulers where each thread pushes and pops its own tasks at
+//! one end while the idle threads steal the oldest ones at the other end.
+//!
+//! This is the algorithm of Chase and Lev, with the memory orderings of Lê et al.: the owner
+//! moves `bottom` without any atomic read-modify-write except when taking the last element, which
+//! it races for with the stealers by a compare-and-swap on `top`.
+//!
+//! The elements are in a circular buffer which the owner replaces by one twice as large when it
+//! is full. A stealer protects the buffer it reads with a hazard pointer, so the old buffers are
+//! retired to the hazard pointers.
+
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::Arc;
+
+use array_queue::CachePadded;
+use reclaim::hazard::{self, Hazard};
+use sync::AtomicPtr;
+use sync::atomic::{AtomicIsize, Ordering, fence};
+
+const MIN_CAPACITY: usize = 16;
+
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+impl<T> Buffer<T> {
+    fn new(capacity: usize) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+        }))
+    }
+
+    fn slot(&self, index: isize) -> *mut MaybeUninit<T> {
+        // The capacity is a power of two.
+        self.slots[index as usize & (self.slots.len() - 1)].get()
+    }
+}
+
+struct Inner<T> {
+    /// Index of the oldest element, incremented by the stealers and by the owner taking the last
+    /// element.
+    top: CachePadded<AtomicIsize>,
+    /// Index after the newest element, only written by the owner.
+    bottom: CachePadded<AtomicIsize>,
+    buffer: AtomicPtr<Buffer<T>>,
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let top = self.top.load(Ordering::Relaxed);
+        let bottom = self.bottom.load(Ordering::Relaxed);
+        let buffer = unsafe { Box::from_raw(self.buffer.load(Ordering::Relaxed)) };
+        for index in top..bottom {
+            unsafe { (*buffer.slot(index)).assume_init_drop() };
+        }
+    }
+}
+
+/// The result of `Stealer::steal()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Steal<T> {
+    /// The deque was empty.
+    Empty,
+    Success(T),
+    /// The element was taken by another thread meanwhile: the deque can still hold elements.
+    Retry,
+}
+
+impl<T> Steal<T> {
+    /// The stolen element, if any.
+    pub fn success(self) -> Option<T> {
+        match self {
+            Steal::Success(value) => Some(value),
+            Steal::Empty | Steal::Retry => None,
+        }
+    }
+}
+
+/// The owner end of a work-stealing deque, which pushes and pops the newest elements. It can be
+/// sent to another thread, but not shared.
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T: Send> Send for Worker<T> {}
+
+impl<T> Worker<T> {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                top: CachePadded(AtomicIsize::new(0)),
+                bottom: CachePadded(AtomicIsize::new(0)),
+                buffer: AtomicPtr::new(Buffer::new(MIN_CAPACITY)),
+            }),
+            _not_sync: PhantomData,
+        }
+    }
+
+    /// Create a handle stealing the oldest elements from other threads.
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let bottom = self.inner.bottom.load(Ordering::Relaxed);
+        let top = self.inner.top.load(Ordering::Acquire);
+        let mut buffer = self.inner.buffer.load(Ordering::Relaxed);
+        if bottom - top >= unsafe { &*buffer }.slots.len() as isize {
+            buffer = self.grow(top, bottom);
+        }
+        unsafe { (*buffer).slot(bottom).write(MaybeUninit::new(value)) };
+        // Publish the element before the stealers can see it.
+        fence(Ordering::Release);
+        self.inner.bottom.store(bottom + 1, Ordering::Relaxed);
+    }
+
+    /// Remove the newest element.
+    pub fn pop(&self) -> Option<T> {
+        let bottom = self.inner.bottom.load(Ordering::Relaxed) - 1;
+        let buffer = self.inner.buffer.load(Ordering::Relaxed);
+        self.inner.bottom.store(bottom, Ordering::Relaxed);
+        // Either a stealer sees the new bottom, or we see its increment of top.
+        fence(Ordering::SeqCst);
+        let top = self.inner.top.load(Ordering::Relaxed);
+        if top > bottom {
+            self.inner.bottom.store(bottom + 1, Ordering::Relaxed);
+            return None;
+        }
+        if top == bottom {
+            // The last element: race for it with the stealers.
+            let won = self.inner.top.compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed).is_ok();
+            self.inner.bottom.store(bottom + 1, Ordering::Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        Some(unsafe { (*buffer).slot(bottom).read().assume_init() })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        let bottom = self.inner.bottom.load(Ordering::Relaxed);
+        let top = self.inner.top.load(Ordering::Relaxed);
+        (bottom - top).max(0) as usize
+    }
+
+    /// Move the elements to a buffer twice as large.
+    fn grow(&self, top: isize, bottom: isize) -> *mut Buffer<T> {
+        let old = self.inner.buffer.load(Ordering::Relaxed);
+        let new = Buffer::new(unsafe { &*old }.slots.len() * 2);
+        for index in top..bottom {
+            unsafe { ptr::copy_nonoverlapping((*old).slot(index), (*new).slot(index), 1) };
+        }
+        self.inner.buffer.store(new, Ordering::Release);
+        // The stealers still reading the old buffer copy their element from it, which they only
+        // keep if they win it: the elements are only dropped from the new buffer.
+        unsafe { hazard::retire(old, move || drop(Box::from_raw(old))) };
+        new
+    }
+}
+
+impl<T> Default for Worker<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A handle stealing the oldest elements of a `Worker`, which can be cloned and shared between
+/// threads.
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Stealer<T> {}
+unsafe impl<T: Send> Sync for Stealer<T> {}
+
+impl<T> Stealer<T> {
+    /// Take the oldest element.
+    pub fn steal(&self) -> Steal<T> {
+        let top = self.inner.top.load(Ordering::Acquire);
+        // Either the owner sees our increment of top, or we see its new bottom.
+        fence(Ordering::SeqCst);
+        let bottom = self.inner.bottom.load(Ordering::Acquire);
+        if top >= bottom {
+            return Steal::Empty;
+        }
+        let hazard = Hazard::new();
+        let buffer = hazard.protect(&self.inner.buffer);
+        // The slot can be overwritten by the owner once another thread took the element, in which
+        // case the compare-and-swap fails and the copy is forgotten.
+        let value = unsafe { ptr::read((*buffer).slot(top)) };
+        if self.inner.top.compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed).is_err() {
+            return Steal::Retry;
+        }
+        Steal::Success(unsafe { value.assume_init() })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let top = self.inner.top.load(Ordering::Acquire);
+        let bottom = self.inner.bottom.load(Ordering::Acquire);
+        top >= bottom
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::{Steal, Worker};
+
+    #[test]
+    fn test_single_thread() {
+        let worker = Worker::new();
+        let stealer = worker.stealer();
+        assert_eq!(worker.pop(), None);
+        assert_eq!(stealer.steal(), Steal::Empty);
+        // Enough to grow the buffer.
+        for i in 0..100 {
+            worker.push(i);
+        }
+        assert_eq!(worker.len(), 100);
+        assert_eq!(worker.pop(), Some(99));
+        assert_eq!(stealer.steal(), Steal::Success(0));
+        assert_eq!(stealer.clone().steal(), Steal::Success(1));
+        assert_eq!(worker.pop(), Some(98));
+        assert_eq!(worker.len(), 96);
+
+        // The elements left are dropped with the deque.
+        let value = Arc::new(());
+        let worker = Worker::new();
+        let stealer = worker.stealer();
+        for _ in 0..20 {
+            worker.push(value.clone());
+        }
+        drop(worker);
+        assert!(stealer.steal().success().is_some());
+        drop(stealer);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 200 } else { 100_000 };
+        let worker = Worker::new();
+        let done = AtomicBool::new(false);
+        let (popped, stolen) = thread::scope(|scope| {
+            let thieves: Vec<_> = (0..4).map(|_| {
+                let stealer = worker.stealer();
+                let done = &done;
+                scope.spawn(move || {
+                    let mut stolen = vec![];
+                    loop {
+                        match stealer.steal() {
+                            Steal::Success(value) => stolen.push(value),
+                            Steal::Retry => (),
+                            Steal::Empty if done.load(Ordering::SeqCst) => break,
+                            Steal::Empty => thread::yield_now(),
+                        }
+                    }
+                    stolen
+                })
+            }).collect();
+
+            let mut popped = vec![];
+            for i in 0..count {
+                worker.push(i);
+                if i % 3 == 0 {
+                    popped.extend(worker.pop());
+                }
+            }
+            popped.extend(std::iter::from_fn(|| worker.pop()));
+            done.store(true, Ordering::SeqCst);
+            let stolen: Vec<_> = thieves.into_iter().flat_map(|thief| thief.join().expect("join")).collect();
+            (popped, stolen)
+        });
+
+        // Every element is taken exactly once.
+        let mut received: Vec<_> = popped.into_iter().chain(stolen).collect();
+        received.sort();
+        assert_eq!(received, (0..count).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,156 @@
+use core::sync::atomic::Ordering;
+#[cfg(feature = "std")]
+use std::future::Future;
+#[cfg(feature = "std")]
+use std::pin::Pin;
+#[cfg(feature = "std")]
+use std::task::{Context, Poll};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use Queue;
+#[cfg(feature = "std")]
+use RawList;
+#[cfg(feature = "std")]
+use reclaim::DefaultReclaim;
+use reclaim::Reclaim;
+#[cfg(feature = "std")]
+use wake::Registration;
+
+#[cfg(feature = "std")]
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Close the queue, then wait until the consumers have dequeued every element or the timeout
+    /// expires, and return the elements left.
+    pub fn drain_and_close(&self, timeout: Duration) -> RawList<T> {
+        self.close();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let listener = self.drained.listen();
+            if self.is_drained() || Instant::now() >= deadline {
+                return self.into_raw_list();
+            }
+            listener.wait(Some(deadline));
+        }
+    }
+
+    /// Asynchronous version of `drain_and_close()`.
+    ///
+    /// Since the queue does not depend on a runtime, the timeout is a future provided by the
+    /// caller, like the sleep future of its runtime.
+    pub fn drain_and_close_async<F: Future<Output = ()>>(&self, timeout: F) -> DrainAndClose<'_, T, F, R> {
+        self.close();
+        DrainAndClose {
+            queue: self,
+            timeout: Box::pin(timeout),
+            registration: Registration::default(),
+        }
+    }
+}
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Whether there's no element left in the queue, even if it is paused.
+    pub(crate) fn is_drained(&self) -> bool {
+        let guard = self.pin();
+        let head = self.reclaim.protect(&guard, 0, &self.head);
+        unsafe { (*head).next.load(Ordering::SeqCst).is_null() && self.counted() == 0 }
+    }
+}
+
+/// Future returned by `Queue::drain_and_close_async()`, resolved with the elements left in the
+/// queue.
+#[cfg(feature = "std")]
+pub struct DrainAndClose<'a, T: 'a, F, R: 'a + Reclaim = DefaultReclaim> {
+    queue: &'a Queue<T, R>,
+    timeout: Pin<Box<F>>,
+    registration: Registration,
+}
+
+#[cfg(feature = "std")]
+impl<'a, T, F: Future<Output = ()>, R: Reclaim> Future for DrainAndClose<'a, T, F, R> {
+    type Output = RawList<T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<RawList<T>> {
+        if !self.queue.is_drained() {
+            let this = &mut *self;
+            this.queue.drained.register(&mut this.registration, context.waker());
+            // Check again in case the queue was drained before the registration.
+            if !self.queue.is_drained() && self.timeout.as_mut().poll(context).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(self.queue.into_raw_list())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::future::{self, Future};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    use Queue;
+
+    #[test]
+    fn test_drain_and_close() {
+        let count = if cfg!(miri) { 50 } else { 1_000 };
+        let queue = Arc::new(Queue::new());
+        for i in 0..count {
+            queue.enqueue(i).expect("enqueue");
+        }
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut elements = vec![];
+                while elements.len() < count {
+                    if let Some(element) = queue.dequeue() {
+                        elements.push(element);
+                    }
+                }
+                elements
+            })
+        };
+        let leftovers = queue.drain_and_close(Duration::from_secs(10));
+        assert!(leftovers.is_empty());
+        assert!(queue.try_enqueue(count).is_err());
+        assert_eq!(consumer.join().expect("join"), (0..count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_drain_timeout() {
+        let queue = Queue::new();
+        queue.enqueue(1).expect("enqueue");
+        queue.enqueue(2).expect("enqueue");
+        let leftovers = queue.drain_and_close(Duration::from_millis(10));
+        assert_eq!(leftovers.into_iter().collect::<Vec<_>>(), [1, 2]);
+        assert!(queue.is_closed());
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_drain_async() {
+        let queue = Queue::new();
+        queue.enqueue(1).expect("enqueue");
+        queue.enqueue(2).expect("enqueue");
+        let mut context = Context::from_waker(Waker::noop());
+
+        let mut drain = queue.drain_and_close_async(future::pending());
+        assert!(Pin::new(&mut drain).poll(&mut context).is_pending());
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.dequeue(), Some(2));
+        match Pin::new(&mut drain).poll(&mut context) {
+            Poll::Ready(leftovers) => assert!(leftovers.is_empty()),
+            Poll::Pending => panic!("queue not drained"),
+        }
+
+        queue.reopen();
+        queue.enqueue(3).expect("enqueue");
+        let mut drain = queue.drain_and_close_async(future::ready(()));
+        match Pin::new(&mut drain).poll(&mut context) {
+            Poll::Ready(leftovers) => assert_eq!(leftovers.into_iter().collect::<Vec<_>>(), [3]),
+            Poll::Pending => panic!("timeout not expired"),
+        }
+    }
+}
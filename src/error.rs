@@ -0,0 +1,123 @@
+//! The errors of the fallible operations of the queues.
+//!
+//! Every error implements `Display` and `Error`, and the ones returned by enqueue operations give
+//! the element back.
+
+use core::error::Error;
+use core::fmt::{self, Debug, Display, Formatter};
+
+/// The reason why an element could not be enqueued, along with the element itself so that the
+/// caller can retry, send it elsewhere or log it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SendError<T> {
+    /// The queue was closed.
+    Closed(T),
+    /// The queue was at capacity.
+    Full(T),
+    /// The node of the element could not be allocated.
+    AllocFailed(T),
+    /// Every node of the pool of the queue was in use.
+    PoolExhausted(T),
+}
+
+impl<T> SendError<T> {
+    /// Take back the element which was not enqueued.
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Closed(value) | SendError::Full(value) | SendError::AllocFailed(value)
+                | SendError::PoolExhausted(value) => value,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(*self, SendError::Closed(_))
+    }
+
+    pub fn is_full(&self) -> bool {
+        matches!(*self, SendError::Full(_))
+    }
+}
+
+// Not derived so that the element does not need to implement Debug.
+impl<T> Debug for SendError<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            SendError::Closed(_) => write!(formatter, "Closed(..)"),
+            SendError::Full(_) => write!(formatter, "Full(..)"),
+            SendError::AllocFailed(_) => write!(formatter, "AllocFailed(..)"),
+            SendError::PoolExhausted(_) => write!(formatter, "PoolExhausted(..)"),
+        }
+    }
+}
+
+impl<T> Display for SendError<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let message =
+            match *self {
+                SendError::Closed(_) => "enqueuing in a closed queue",
+                SendError::Full(_) => "enqueuing in a full queue",
+                SendError::AllocFailed(_) => "cannot allocate the node of the element",
+                SendError::PoolExhausted(_) => "no node left in the pool for the element",
+            };
+        write!(formatter, "{}", message)
+    }
+}
+
+impl<T> Error for SendError<T> {
+}
+
+/// The reason why `try_dequeue()` returned no element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DequeueError {
+    /// The queue is empty (or paused), but elements could still be enqueued.
+    Empty,
+    /// The queue is closed and every element was dequeued.
+    Closed,
+}
+
+impl Display for DequeueError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let message =
+            match *self {
+                DequeueError::Empty => "dequeuing from an empty queue",
+                DequeueError::Closed => "dequeuing from a closed and empty queue",
+            };
+        write!(formatter, "{}", message)
+    }
+}
+
+impl Error for DequeueError {
+}
+
+/// The reason why `dequeue_timeout()` returned no element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// The timeout expired while the queue was empty.
+    Timeout,
+    /// The queue is closed and every element was dequeued.
+    Closed,
+}
+
+impl Display for RecvTimeoutError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let message =
+            match *self {
+                RecvTimeoutError::Timeout => "timed out waiting for an element",
+                RecvTimeoutError::Closed => "dequeuing from a closed and empty queue",
+            };
+        write!(formatter, "{}", message)
+    }
+}
+
+impl Error for RecvTimeoutError {
+}
+
+/// An empty queue is treated like a timeout of zero.
+impl From<DequeueError> for RecvTimeoutError {
+    fn from(error: DequeueError) -> Self {
+        match error {
+            DequeueError::Empty => RecvTimeoutError::Timeout,
+            DequeueError::Closed => RecvTimeoutError::Closed,
+        }
+    }
+}
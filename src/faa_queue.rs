@@ -0,0 +1,340 @@
+//! An unbounded queue reserving its slots with a fetch-and-add instead of a compare-and-swap, so
+//! that it scales with the number of contending threads.
+//!
+//! This is the FAA array queue of Correia and Ramalhete, a relative of LCRQ which needs no
+//! double-width compare-and-swap. The slots are in a list of segments. A producer increments the
+//! enqueue index of the last segment to reserve a slot, and a consumer the dequeue index of the
+//! first one, so the contending threads never retry on the same index. A consumer which reaches a
+//! slot before its producer marks it as taken, which makes the producer try the next slot. Only
+//! the moves to another segment use compare-and-swaps, once per `SEGMENT_SIZE` elements.
+//!
+//! The elements are boxed, so that a slot can be written and taken atomically. The segments are
+//! reclaimed with a `Reclaim` strategy like the nodes of `Queue`, since the threads read the
+//! first and last segments after loading them.
+
+use std::ptr;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+
+use array_queue::CachePadded;
+use reclaim::{DefaultReclaim, Reclaim};
+use sync::{AtomicPtr, Exclusive};
+
+/// Number of slots per segment.
+const SEGMENT_SIZE: usize = 1024;
+
+/// Stored in the slots which were taken, so that their producer does not write them anymore.
+static TAKEN: u8 = 0;
+
+fn taken<T>() -> *mut T {
+    // No element is boxed at the address of a static.
+    &TAKEN as *const u8 as *mut T
+}
+
+struct Segment<T> {
+    /// Number of slots reserved by the consumers, which can exceed the size of the segment.
+    dequeued: CachePadded<AtomicUsize>,
+    /// Number of slots reserved by the producers, which can exceed the size of the segment.
+    enqueued: CachePadded<AtomicUsize>,
+    next: AtomicPtr<Segment<T>>,
+    /// The boxed elements, null before being written, and `TAKEN` once taken.
+    slots: [atomic::AtomicPtr<T>; SEGMENT_SIZE],
+}
+
+impl<T> Segment<T> {
+    /// A segment whose first slot holds `element`, if it is not null.
+    fn new(element: *mut T) -> *mut Self {
+        let segment = Box::new(Self {
+            dequeued: CachePadded(AtomicUsize::new(0)),
+            enqueued: CachePadded(AtomicUsize::new(if element.is_null() { 0 } else { 1 })),
+            next: AtomicPtr::new(ptr::null_mut()),
+            slots: [const { atomic::AtomicPtr::new(ptr::null_mut()) }; SEGMENT_SIZE],
+        });
+        segment.slots[0].store(element, Ordering::Relaxed);
+        Box::into_raw(segment)
+    }
+}
+
+impl<T> Drop for Segment<T> {
+    fn drop(&mut self) {
+        for slot in &mut self.slots {
+            let element = *slot.get_mut();
+            if !element.is_null() && element != taken() {
+                drop(unsafe { Box::from_raw(element) });
+            }
+        }
+    }
+}
+
+/// A lock-free unbounded MPMC queue whose operations reserve their slot with a fetch-and-add.
+///
+/// It is meant for many threads contending on many cores, where the producers of `Queue` keep
+/// retrying their compare-and-swap on the tail, like the threads of `SegQueue` on its positions.
+/// With few threads, `SegQueue` is faster since it does not box the elements: compare them with
+/// the `contention` example. It has none of the features of `Queue`.
+pub struct FaaQueue<T, R: Reclaim = DefaultReclaim> {
+    head: CachePadded<AtomicPtr<Segment<T>>>,
+    tail: CachePadded<AtomicPtr<Segment<T>>>,
+    reclaim: R,
+}
+
+// The elements are only accessed by the thread which wrote their slot and the one which took it.
+unsafe impl<T: Send, R: Reclaim + Send> Send for FaaQueue<T, R> {}
+unsafe impl<T: Send, R: Reclaim + Sync> Sync for FaaQueue<T, R> {}
+
+impl<T> FaaQueue<T> {
+    pub fn new() -> Self {
+        Self::with_reclaim(DefaultReclaim::default())
+    }
+}
+
+impl<T, R: Reclaim> FaaQueue<T, R> {
+    /// Create a queue reclaiming its segments with the specified strategy.
+    pub fn with_reclaim(reclaim: R) -> Self {
+        let segment = Segment::new(ptr::null_mut());
+        Self {
+            head: CachePadded(AtomicPtr::new(segment)),
+            tail: CachePadded(AtomicPtr::new(segment)),
+            reclaim,
+        }
+    }
+
+    pub fn enqueue(&self, value: T) {
+        let element = Box::into_raw(Box::new(value));
+        let guard = self.reclaim.guard();
+        loop {
+            let tail = self.reclaim.protect(&guard, 0, &self.tail);
+            let index = unsafe { (*tail).enqueued.fetch_add(1, Ordering::SeqCst) };
+            if index < SEGMENT_SIZE {
+                // Fails if a consumer already took the slot.
+                let slot = unsafe { &(*tail).slots[index] };
+                if slot.compare_exchange(ptr::null_mut(), element, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                    return;
+                }
+                continue;
+            }
+
+            // The segment is full: append a new one starting with the element.
+            if self.tail.load(Ordering::SeqCst) != tail {
+                continue;
+            }
+            let next = unsafe { (*tail).next.load(Ordering::SeqCst) };
+            if !next.is_null() {
+                let _ = self.tail.compare_exchange(tail, next, Ordering::SeqCst, Ordering::Relaxed);
+                continue;
+            }
+            let segment = Segment::new(element);
+            if unsafe { (*tail).next.compare_exchange(ptr::null_mut(), segment, Ordering::SeqCst, Ordering::Relaxed) }.is_ok() {
+                fail_point!("faa.linked");
+                let _ = self.tail.compare_exchange(tail, segment, Ordering::SeqCst, Ordering::Relaxed);
+                return;
+            }
+            // Another producer appended a segment first: take the element back.
+            unsafe {
+                (*segment).slots[0].store(ptr::null_mut(), Ordering::Relaxed);
+                drop(Box::from_raw(segment));
+            }
+        }
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        let guard = self.reclaim.guard();
+        loop {
+            let head = self.reclaim.protect(&guard, 0, &self.head);
+            let segment = unsafe { &*head };
+            if segment.dequeued.load(Ordering::SeqCst) >= segment.enqueued.load(Ordering::SeqCst) &&
+                segment.next.load(Ordering::SeqCst).is_null()
+            {
+                return None;
+            }
+            let index = segment.dequeued.fetch_add(1, Ordering::SeqCst);
+            if index < SEGMENT_SIZE {
+                // Null if the producer of the slot did not write it yet: it will try another one.
+                let element = segment.slots[index].swap(taken(), Ordering::SeqCst);
+                if element.is_null() {
+                    continue;
+                }
+                return Some(*unsafe { Box::from_raw(element) });
+            }
+
+            // The segment is exhausted: move to the next one.
+            let next = segment.next.load(Ordering::SeqCst);
+            if next.is_null() {
+                return None;
+            }
+            // The producer which appended the next segment may not have moved the tail yet: move
+            // it first, so that no thread can load the segment from the tail once it is retired.
+            if self.tail.load(Ordering::SeqCst) == head {
+                let _ = self.tail.compare_exchange(head, next, Ordering::SeqCst, Ordering::Relaxed);
+            }
+            if self.head.compare_exchange(head, next, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                // Every slot was taken by a consumer, so the segment holds no element.
+                unsafe { self.reclaim.retire(&guard, head, move || drop(Box::from_raw(head))) };
+            }
+        }
+    }
+
+    /// Whether the queue is observed empty.
+    pub fn is_empty(&self) -> bool {
+        let guard = self.reclaim.guard();
+        let head = self.reclaim.protect(&guard, 0, &self.head);
+        let segment = unsafe { &*head };
+        let dequeued = segment.dequeued.load(Ordering::SeqCst);
+        // The slots still empty after being reserved by a producer were taken by a consumer.
+        (dequeued >= SEGMENT_SIZE || dequeued >= segment.enqueued.load(Ordering::SeqCst)) &&
+            segment.next.load(Ordering::SeqCst).is_null()
+    }
+
+    /// Destroy the segments which are not accessed anymore, instead of waiting for the
+    /// reclamation strategy to do it periodically.
+    pub fn collect_garbage(&self) {
+        self.reclaim.collect(&self.reclaim.guard());
+    }
+}
+
+impl<T> Default for FaaQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, R: Reclaim> Drop for FaaQueue<T, R> {
+    fn drop(&mut self) {
+        let mut segment = self.head.load_mut();
+        while !segment.is_null() {
+            unsafe {
+                let next = (*segment).next.load_mut();
+                drop(Box::from_raw(segment));
+                segment = next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use failpoint::{clear_hook, set_hook};
+    use reclaim::HazardPointers;
+    use super::{FaaQueue, SEGMENT_SIZE};
+
+    #[test]
+    fn test_segments() {
+        let queue = FaaQueue::new();
+        assert!(queue.is_empty());
+        for lap in 0..3 {
+            for i in 0..3 * SEGMENT_SIZE {
+                queue.enqueue(lap * 10_000 + i);
+            }
+            for i in 0..3 * SEGMENT_SIZE {
+                assert_eq!(queue.dequeue(), Some(lap * 10_000 + i));
+            }
+            assert!(queue.is_empty());
+            assert_eq!(queue.dequeue(), None);
+        }
+
+        // The elements left are dropped with the queue, across segments.
+        let queue = FaaQueue::new();
+        let value = Arc::new(());
+        for _ in 0..SEGMENT_SIZE + 10 {
+            queue.enqueue(value.clone());
+        }
+        drop(queue.dequeue());
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 200 } else { 100_000 };
+        let queue = FaaQueue::with_reclaim(HazardPointers);
+        let received: Vec<Vec<(usize, usize)>> = thread::scope(|scope| {
+            for thread in 0..4 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for i in 0..count {
+                        queue.enqueue((thread, i));
+                    }
+                });
+            }
+            let consumers: Vec<_> = (0..4).map(|_| {
+                let queue = &queue;
+                scope.spawn(move || {
+                    let mut received = vec![];
+                    while received.len() < count {
+                        received.extend(queue.dequeue());
+                    }
+                    received
+                })
+            }).collect();
+            consumers.into_iter().map(|consumer| consumer.join().expect("join")).collect()
+        });
+
+        // Each consumer receives the elements of each producer in order, and every element is
+        // received once.
+        let mut all = vec![];
+        for elements in received {
+            let mut last = [None; 4];
+            for &(thread, i) in &elements {
+                assert!(last[thread].is_none_or(|last| last < i));
+                last[thread] = Some(i);
+            }
+            all.extend(elements);
+        }
+        all.sort();
+        let expected: Vec<_> = (0..4).flat_map(|thread| (0..count).map(move |i| (thread, i))).collect();
+        assert_eq!(all, expected);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_suspended_producer() {
+        let queue = Arc::new(FaaQueue::with_reclaim(HazardPointers));
+        for i in 0..SEGMENT_SIZE {
+            queue.enqueue(i);
+        }
+        // Suspend a producer after it appended a segment, before it moves the tail.
+        let suspended = Arc::new(Barrier::new(2));
+        let resumed = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let queue = queue.clone();
+            let suspended = suspended.clone();
+            let resumed = resumed.clone();
+            thread::spawn(move || {
+                set_hook(move |name| {
+                    if name == "faa.linked" {
+                        suspended.wait();
+                        while !resumed.load(Ordering::SeqCst) {
+                            thread::park();
+                        }
+                    }
+                });
+                queue.enqueue(SEGMENT_SIZE);
+                clear_hook();
+            })
+        };
+        suspended.wait();
+
+        // Moving the head past the first segment moves the tail too, before the segment is
+        // retired.
+        for i in 0..=SEGMENT_SIZE {
+            assert_eq!(queue.dequeue(), Some(i));
+        }
+        let head = queue.head.load(Ordering::SeqCst);
+        assert_eq!(queue.tail.load(Ordering::SeqCst), head);
+        queue.collect_garbage();
+        for i in 0..10 {
+            queue.enqueue(i);
+        }
+        resumed.store(true, Ordering::SeqCst);
+        producer.thread().unpark();
+        producer.join().expect("join");
+        for i in 0..10 {
+            assert_eq!(queue.dequeue(), Some(i));
+        }
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+//! Hooks called at interesting points of the queue algorithms (the fail points), available with
+//! the `fail-points` feature.
+//!
+//! The hooks are per thread, so that a test can suspend one thread in the middle of an operation
+//! or count the steps taken by another one without affecting the rest of the process. The fail
+//! points are:
+//!
+//!  * `enqueue.loop`: start of an attempt to link a new node.
+//!  * `enqueue.linked`: the node is linked, but the tail was not moved yet.
+//!  * `dequeue.loop`: start of an attempt to remove the first node.
+//!  * `dequeue.loaded`: the head, tail and first node were read, but not modified yet.
+//!  * `push_front.loop`: start of an attempt to put a claimed element back.
+//!  * `faa.linked`: a producer of `FaaQueue` appended a segment, but did not move the tail yet.
+//!  * `scan.cloned`: `peek_with()` cloned the first element, but did not call its function yet.
+
+use std::cell::RefCell;
+
+type Hook = Box<dyn Fn(&'static str)>;
+
+thread_local! {
+    static HOOK: RefCell<Option<Hook>> = RefCell::new(None);
+}
+
+/// Set the hook called by the fail points reached by the current thread.
+pub fn set_hook<F: Fn(&'static str) + 'static>(hook: F) {
+    HOOK.with(|current| *current.borrow_mut() = Some(Box::new(hook)));
+}
+
+/// Remove the hook of the current thread.
+pub fn clear_hook() {
+    HOOK.with(|current| *current.borrow_mut() = None);
+}
+
+pub(crate) fn hit(name: &'static str) {
+    HOOK.with(|hook| {
+        // A hook using the queue does not call itself recursively.
+        if let Ok(hook) = hook.try_borrow() {
+            if let Some(ref hook) = *hook {
+                hook(name);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use {DequeueError, Queue};
+    use super::{clear_hook, set_hook};
+
+    /// Maximum number of attempts an operation can need when no other thread makes progress. Miri
+    /// makes the weak compare-and-swaps fail spuriously, so the tests of this bound do not run there.
+    const MAX_STEPS: usize = 2;
+
+    /// Start a thread which gets suspended at the fail point `point` during `operation`, and
+    /// return a function resuming it.
+    fn suspend_in<F>(queue: &Arc<Queue<usize>>, point: &'static str, operation: F) -> impl FnOnce()
+    where F: FnOnce(&Queue<usize>) + Send + 'static,
+    {
+        let suspended = Arc::new(Barrier::new(2));
+        let resumed = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let queue = queue.clone();
+            let suspended = suspended.clone();
+            let resumed = resumed.clone();
+            thread::spawn(move || {
+                let once = Cell::new(true);
+                set_hook(move |name| {
+                    if name == point && once.replace(false) {
+                        suspended.wait();
+                        while !resumed.load(Ordering::SeqCst) {
+                            thread::park();
+                        }
+                    }
+                });
+                operation(&queue);
+                clear_hook();
+            })
+        };
+        suspended.wait();
+        move || {
+            resumed.store(true, Ordering::SeqCst);
+            handle.thread().unpark();
+            handle.join().expect("join");
+        }
+    }
+
+    /// Run enqueues and dequeues on the current thread and check that each one completes in a
+    /// bounded number of steps.
+    fn check_progress(queue: &Queue<usize>) -> Vec<usize> {
+        let steps = Rc::new(Cell::new(0));
+        {
+            let steps = steps.clone();
+            set_hook(move |name| {
+                if name.ends_with(".loop") {
+                    steps.set(steps.get() + 1);
+                }
+            });
+        }
+        let mut received = vec![];
+        for i in 0..1_000 {
+            steps.set(0);
+            queue.enqueue(100 + i).expect("enqueue");
+            assert!(steps.get() <= MAX_STEPS, "enqueue took {} steps", steps.get());
+
+            if i % 2 == 0 {
+                steps.set(0);
+                received.extend(queue.dequeue());
+                assert!(steps.get() <= MAX_STEPS, "dequeue took {} steps", steps.get());
+            }
+        }
+        clear_hook();
+        received
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_progress_with_suspended_enqueuer() {
+        let queue = Arc::new(Queue::new());
+        queue.enqueue(1).expect("enqueue");
+        let resume = suspend_in(&queue, "enqueue.linked", |queue| queue.enqueue(2).expect("enqueue"));
+
+        // The tail is lagging behind: the other threads must help instead of waiting.
+        let received = check_progress(&queue);
+        assert_eq!(&received[..2], &[1, 2]);
+        resume();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_progress_with_suspended_dequeuer() {
+        let queue = Arc::new(Queue::new());
+        queue.enqueue(1).expect("enqueue");
+        queue.enqueue(2).expect("enqueue");
+        let resume = suspend_in(&queue, "dequeue.loaded", |queue| assert_eq!(queue.dequeue(), Some(3)));
+
+        let received = check_progress(&queue);
+        assert_eq!(&received[..2], &[1, 2]);
+        while queue.dequeue().is_some() {
+        }
+        // The suspended dequeue must start again from the new head.
+        queue.enqueue(3).expect("enqueue");
+        resume();
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    #[cfg_attr(miri, ignore)]
+    fn test_progress_with_suspended_peek() {
+        let queue = Arc::new(Queue::new());
+        queue.enqueue(1).expect("enqueue");
+        let resume = suspend_in(&queue, "scan.cloned", |queue| assert_eq!(queue.peek_with(|&value| value), Some(1)));
+
+        // The element being peeked is dequeued without waiting for the function.
+        let received = check_progress(&queue);
+        assert_eq!(received[0], 1);
+        resume();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_close_with_suspended_enqueuer() {
+        let queue = Arc::new(Queue::new());
+        let resume = suspend_in(&queue, "enqueue.loop", |queue| queue.enqueue(1).expect("enqueue"));
+        queue.close();
+        // The producer checked the queue before it was closed, so its element is still coming.
+        assert_eq!(queue.try_dequeue(), Err(DequeueError::Empty));
+        resume();
+        assert_eq!(queue.try_dequeue(), Ok(1));
+        assert_eq!(queue.try_dequeue(), Err(DequeueError::Closed));
+    }
+}
@@ -0,0 +1,255 @@
+//! Recycling of the nodes removed from the queue.
+//!
+//! The nodes are only recycled once the reclamation scheme guarantees that no other thread can
+//! access them anymore, so that a recycled node cannot be mistaken for the one it was (ABA). The
+//! nodes of the elements discarded by `reset()`, which has exclusive access to the queue, are
+//! recycled directly.
+//!
+//! The list also holds the allocator of the nodes, since it frees them. With the `allocator-api`
+//! feature, which needs a nightly compiler, it can be chosen with `Queue::new_in()`. The nodes of
+//! a queue created with `Queue::with_pool()` are given back to its pool instead of being kept in
+//! the list, so that the other queues of the pool can take them.
+
+#[cfg(feature = "allocator-api")]
+use alloc::alloc::Allocator;
+use alloc::alloc::{self, Layout};
+#[cfg(feature = "allocator-api")]
+use alloc::boxed::Box;
+#[cfg(feature = "allocator-api")]
+use core::ptr::NonNull;
+use core::ptr;
+
+use {Node, Queue};
+use pool::NodeSource;
+#[cfg(feature = "allocator-api")]
+use reclaim::DefaultReclaim;
+use reclaim::Reclaim;
+use sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Maximum number of nodes kept for recycling by a queue: the others are freed.
+const CAPACITY: usize = 64;
+
+/// A bounded set of free nodes, whose memory is uninitialized.
+///
+/// Each slot is taken with a swap and filled with a compare-and-swap from null, so that a node
+/// cannot be taken twice.
+pub(crate) struct FreeList<T> {
+    slots: [AtomicPtr<Node<T>>; CAPACITY],
+    /// Number of nodes in the slots, so that allocating does not scan an empty list.
+    len: AtomicUsize,
+    /// The allocator of the nodes, or None for the global allocator.
+    #[cfg(feature = "allocator-api")]
+    allocator: Option<Box<dyn Allocator + Send + Sync>>,
+    /// The pool of the nodes, if they are not allocated. A pointer since the pools are
+    /// `'static` while `T` does not need to be.
+    pool: Option<*const (dyn NodeSource<T> + Sync)>,
+}
+
+impl<T> FreeList<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            slots: [const { AtomicPtr::new(ptr::null_mut()) }; CAPACITY],
+            len: AtomicUsize::new(0),
+            #[cfg(feature = "allocator-api")]
+            allocator: None,
+            pool: None,
+        }
+    }
+
+    pub(crate) fn with_pool(pool: &'static (dyn NodeSource<T> + Sync)) -> Self {
+        let mut free_list = Self::new();
+        free_list.pool = Some(pool);
+        free_list
+    }
+
+    #[cfg(feature = "allocator-api")]
+    pub(crate) fn new_in<A: Allocator + Send + Sync + 'static>(allocator: A) -> Self {
+        let mut free_list = Self::new();
+        free_list.allocator = Some(Box::new(allocator));
+        free_list
+    }
+
+    /// Memory for a new node, recycled or allocated, or null if the allocation failed.
+    pub(crate) fn allocate(&self) -> *mut Node<T> {
+        if let Some(pool) = self.pool {
+            return unsafe { (*pool).allocate() };
+        }
+        self.pop().unwrap_or_else(|| {
+            let layout = Layout::new::<Node<T>>();
+            #[cfg(feature = "allocator-api")]
+            if let Some(ref allocator) = self.allocator {
+                return allocator.allocate(layout).map_or(ptr::null_mut(), |memory| memory.as_ptr().cast());
+            }
+            unsafe { alloc::alloc(layout) }.cast()
+        })
+    }
+
+    pub(crate) fn has_pool(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    /// Move `node` to new memory, aborting if it cannot be allocated.
+    pub(crate) fn create(&self, node: Node<T>) -> *mut Node<T> {
+        let pointer = self.allocate();
+        if pointer.is_null() {
+            alloc::handle_alloc_error(Layout::new::<Node<T>>());
+        }
+        unsafe { pointer.write(node) };
+        pointer
+    }
+
+    /// Free the memory of a node whose content was already dropped.
+    pub(crate) unsafe fn deallocate(&self, node: *mut Node<T>) {
+        if let Some(pool) = self.pool {
+            (*pool).deallocate(node);
+            return;
+        }
+        let layout = Layout::new::<Node<T>>();
+        #[cfg(feature = "allocator-api")]
+        if let Some(ref allocator) = self.allocator {
+            allocator.deallocate(NonNull::new_unchecked(node).cast(), layout);
+            return;
+        }
+        alloc::dealloc(node.cast(), layout);
+    }
+
+    /// Take a free node, if any.
+    pub(crate) fn pop(&self) -> Option<*mut Node<T>> {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let node = self.slots.iter()
+            .filter(|slot| !slot.load(Ordering::Relaxed).is_null())
+            .map(|slot| slot.swap(ptr::null_mut(), Ordering::Acquire))
+            .find(|node| !node.is_null())?;
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        Some(node)
+    }
+
+    /// Drop the content of a node which no other thread can access, and keep its memory for a
+    /// new node, or free it if the list is full.
+    pub(crate) unsafe fn recycle(&self, node: *mut Node<T>) {
+        ptr::drop_in_place(node);
+        let kept = self.pool.is_none() && self.slots.iter()
+            .any(|slot| slot.compare_exchange(ptr::null_mut(), node, Ordering::Release, Ordering::Relaxed).is_ok());
+        if kept {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        else {
+            self.deallocate(node);
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Drop for FreeList<T> {
+    fn drop(&mut self) {
+        while let Some(node) = self.pop() {
+            unsafe { self.deallocate(node) };
+        }
+    }
+}
+
+#[cfg(feature = "allocator-api")]
+impl<T> Queue<T> {
+    /// Create a queue whose nodes are allocated by `allocator`, for instance an arena shared
+    /// with other data instead of the global heap.
+    pub fn new_in<A: Allocator + Send + Sync + 'static>(allocator: A) -> Self {
+        Self::with_free_list(DefaultReclaim::default(), FreeList::new_in(allocator))
+    }
+}
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Memory for a new node, recycled or allocated, or null if the allocation failed.
+    pub(crate) fn allocate_node(&self) -> *mut Node<T> {
+        self.free_list.allocate()
+    }
+
+    pub(crate) fn new_node(&self, node: Node<T>) -> *mut Node<T> {
+        self.free_list.create(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Node, Queue};
+    use super::{CAPACITY, FreeList};
+
+    #[test]
+    fn test_capacity() {
+        let queue = Queue::<u32>::new();
+        let free_list = FreeList::new();
+        for _ in 0..CAPACITY + 1 {
+            unsafe { free_list.recycle(queue.new_node(Node::new(1, None))) };
+        }
+        assert_eq!(free_list.len(), CAPACITY);
+        let node = free_list.pop().expect("node");
+        assert_eq!(free_list.len(), CAPACITY - 1);
+        unsafe {
+            node.write(Node::new(2, None));
+            free_list.recycle(node);
+        }
+        // The recycled nodes are dropped with the list.
+    }
+
+    #[test]
+    #[cfg(feature = "crossbeam-epoch")]
+    fn test_recycle() {
+        use crossbeam_epoch::Collector;
+
+        let collector = Collector::new();
+        let queue = Queue::with_collector(collector.clone());
+        let handle = collector.register();
+        for i in 0..1_000 {
+            let guard = handle.pin();
+            queue.enqueue_with_guard(i, &guard).expect("enqueue");
+            assert_eq!(queue.dequeue_with_guard(&guard), Some(i));
+            guard.flush();
+        }
+        assert!(queue.free_list.len() > 0);
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api")]
+    fn test_allocator() {
+        use std::alloc::{AllocError, Allocator, Global, Layout};
+        use std::ptr::NonNull;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicIsize, Ordering};
+
+        struct Counting(Arc<AtomicIsize>);
+
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, pointer: NonNull<u8>, layout: Layout) {
+                self.0.fetch_sub(1, Ordering::SeqCst);
+                Global.deallocate(pointer, layout);
+            }
+        }
+
+        let live = Arc::new(AtomicIsize::new(0));
+        {
+            let queue = Queue::new_in(Counting(live.clone()));
+            for i in 0..100 {
+                queue.enqueue(i).expect("enqueue");
+            }
+            assert!(live.load(Ordering::SeqCst) > 0);
+            for i in 0..50 {
+                assert_eq!(queue.dequeue(), Some(i));
+            }
+            // Recycle the removed nodes, so that they are freed with the list.
+            for _ in 0..3 {
+                queue.collect_garbage();
+            }
+        }
+        assert_eq!(live.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,244 @@
+//! Futures dequeuing the elements of a queue, for the asynchronous tasks of any runtime.
+//!
+//! A task polling an empty queue registers its waker in the notifier of the queue, which the
+//! producers wake after linking their element. The queue is checked again after the registration,
+//! so that an element enqueued in between is not missed.
+//!
+//! The same notifier wakes the threads blocked in `dequeue_blocking()` or `dequeue_timeout()`, so
+//! threads and tasks can wait on the same queue, and be fed by producers of either kind.
+//!
+//! Only the `Waker` of the task is used, so the futures behave the same under every executor. The
+//! crate depends on no runtime: the operations with a timeout take the sleep future of the
+//! caller's runtime, like `tokio::time::sleep()` or `smol::Timer::after()`.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use {DequeueError, Queue, RecvTimeoutError};
+use reclaim::{DefaultReclaim, Reclaim};
+use wake::Registration;
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Dequeue an element, waiting asynchronously while the queue is empty (or paused), or
+    /// resolve to None once it is closed and drained.
+    pub fn dequeue_async(&self) -> Dequeue<'_, T, R> {
+        Dequeue {
+            queue: self,
+            registration: Registration::default(),
+        }
+    }
+
+    /// Dequeue an element, waiting asynchronously while the queue is empty until the `timeout`
+    /// future resolves or the queue is closed.
+    pub fn dequeue_timeout_async<F: Future<Output = ()>>(&self, timeout: F) -> DequeueTimeout<'_, T, F, R> {
+        DequeueTimeout {
+            queue: self,
+            timeout: Box::pin(timeout),
+            registration: Registration::default(),
+        }
+    }
+
+    /// Dequeue an element, or register the task to be woken when one is enqueued.
+    pub(crate) fn poll_dequeue(&self, registration: &mut Registration, context: &mut Context) -> Poll<Option<T>> {
+        match self.try_dequeue() {
+            Ok(value) => return Poll::Ready(Some(value)),
+            Err(DequeueError::Closed) => return Poll::Ready(None),
+            Err(DequeueError::Empty) => (),
+        }
+        self.notifier.register(registration, context.waker());
+        // Check again in case an element was enqueued before the registration.
+        match self.try_dequeue() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(DequeueError::Closed) => Poll::Ready(None),
+            Err(DequeueError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+/// Future returned by `Queue::dequeue_async()`.
+pub struct Dequeue<'a, T: 'a, R: 'a + Reclaim = DefaultReclaim> {
+    queue: &'a Queue<T, R>,
+    registration: Registration,
+}
+
+impl<'a, T, R: Reclaim> Future for Dequeue<'a, T, R> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        let this = &mut *self;
+        this.queue.poll_dequeue(&mut this.registration, context)
+    }
+}
+
+/// Future returned by `Queue::dequeue_timeout_async()`.
+pub struct DequeueTimeout<'a, T: 'a, F, R: 'a + Reclaim = DefaultReclaim> {
+    queue: &'a Queue<T, R>,
+    timeout: Pin<Box<F>>,
+    registration: Registration,
+}
+
+impl<'a, T, F: Future<Output = ()>, R: Reclaim> Future for DequeueTimeout<'a, T, F, R> {
+    type Output = Result<T, RecvTimeoutError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<T, RecvTimeoutError>> {
+        let this = &mut *self;
+        match this.queue.poll_dequeue(&mut this.registration, context) {
+            Poll::Ready(Some(value)) => Poll::Ready(Ok(value)),
+            Poll::Ready(None) => Poll::Ready(Err(RecvTimeoutError::Closed)),
+            Poll::Pending => this.timeout.as_mut().poll(context).map(|()| Err(RecvTimeoutError::Timeout)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{self, Future};
+    use std::pin::{Pin, pin};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+    use std::time::Duration;
+
+    use {Queue, RecvTimeoutError};
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// The simplest executor: it parks the thread until the future is woken.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unparker(Thread);
+
+        impl Wake for Unparker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unparker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_executor() {
+        let queue = Arc::new(Queue::new());
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    queue.enqueue(i).expect("enqueue");
+                    thread::sleep(Duration::from_micros(100));
+                }
+                queue.close();
+            })
+        };
+        let mut received = vec![];
+        while let Some(value) = block_on(queue.dequeue_async()) {
+            received.push(value);
+        }
+        producer.join().expect("join");
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_mixed_waiters() {
+        let queue = Arc::new(Queue::new());
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut context = Context::from_waker(&waker);
+        let mut dequeue = queue.dequeue_async();
+        assert!(Pin::new(&mut dequeue).poll(&mut context).is_pending());
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.dequeue_blocking())
+        };
+        thread::sleep(Duration::from_millis(20));
+
+        // A single enqueue wakes both the task and the thread: one of them gets the element.
+        queue.enqueue(1).expect("enqueue");
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        let task_value = match Pin::new(&mut dequeue).poll(&mut context) {
+            Poll::Ready(value) => value,
+            Poll::Pending => None,
+        };
+        if task_value.is_some() {
+            queue.enqueue(2).expect("enqueue");
+        }
+        let thread_value = consumer.join().expect("join");
+        assert_eq!(task_value.is_some(), thread_value == 2);
+    }
+
+    #[test]
+    fn test_repoll() {
+        let queue = Queue::new();
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut context = Context::from_waker(&waker);
+        let mut dequeue = queue.dequeue_async();
+        // Polling again replaces the waker of the future instead of adding one.
+        for _ in 0..10_000 {
+            assert!(Pin::new(&mut dequeue).poll(&mut context).is_pending());
+        }
+        assert_eq!(queue.notifier.registrations(), (1, 1));
+        queue.enqueue(1).expect("enqueue");
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        assert_eq!(Pin::new(&mut dequeue).poll(&mut context), Poll::Ready(Some(1)));
+
+        // The slot of a dropped future is reused.
+        assert!(Pin::new(&mut dequeue).poll(&mut context).is_pending());
+        drop(dequeue);
+        assert_eq!(queue.notifier.registrations(), (1, 0));
+        let mut dequeue = queue.dequeue_async();
+        assert!(Pin::new(&mut dequeue).poll(&mut context).is_pending());
+        assert_eq!(queue.notifier.registrations(), (1, 1));
+    }
+
+    #[test]
+    fn test_dequeue_timeout_async() {
+        let queue = Queue::new();
+        let mut context = Context::from_waker(Waker::noop());
+        let mut dequeue = queue.dequeue_timeout_async(future::pending());
+        assert!(Pin::new(&mut dequeue).poll(&mut context).is_pending());
+        queue.enqueue(1).expect("enqueue");
+        assert_eq!(Pin::new(&mut dequeue).poll(&mut context), Poll::Ready(Ok(1)));
+
+        let mut dequeue = queue.dequeue_timeout_async(future::ready(()));
+        assert_eq!(Pin::new(&mut dequeue).poll(&mut context), Poll::Ready(Err(RecvTimeoutError::Timeout)));
+        queue.close();
+        let mut dequeue = queue.dequeue_timeout_async(future::pending());
+        assert_eq!(Pin::new(&mut dequeue).poll(&mut context), Poll::Ready(Err(RecvTimeoutError::Closed)));
+    }
+
+    #[test]
+    fn test_dequeue_async() {
+        let queue = Queue::new();
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut context = Context::from_waker(&waker);
+
+        let mut dequeue = queue.dequeue_async();
+        assert!(Pin::new(&mut dequeue).poll(&mut context).is_pending());
+        queue.enqueue(1).expect("enqueue");
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        assert_eq!(Pin::new(&mut dequeue).poll(&mut context), Poll::Ready(Some(1)));
+
+        let mut dequeue = queue.dequeue_async();
+        assert!(Pin::new(&mut dequeue).poll(&mut context).is_pending());
+        queue.close();
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 2);
+        assert_eq!(Pin::new(&mut dequeue).poll(&mut context), Poll::Ready(None));
+    }
+}
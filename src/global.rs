@@ -0,0 +1,150 @@
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+use {Claim, Completion, Queue, SendError};
+use iter::{Iter, TryIter};
+
+/// A queue which can be declared in a `static`, since it is only allocated on first use.
+///
+/// See the `static_queue!` macro.
+pub struct GlobalQueue<T> {
+    queue: OnceLock<Queue<T>>,
+}
+
+impl<T> GlobalQueue<T> {
+    pub const fn new() -> Self {
+        Self {
+            queue: OnceLock::new(),
+        }
+    }
+
+    /// A handle which can only enqueue.
+    pub fn producer(&self) -> Producer<'_, T> {
+        Producer {
+            queue: self,
+        }
+    }
+
+    /// A handle which can only dequeue.
+    pub fn consumer(&self) -> Consumer<'_, T> {
+        Consumer {
+            queue: self,
+        }
+    }
+}
+
+impl<T> Default for GlobalQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for GlobalQueue<T> {
+    type Target = Queue<T>;
+
+    fn deref(&self) -> &Queue<T> {
+        self.queue.get_or_init(Queue::new)
+    }
+}
+
+/// The producing side of a `GlobalQueue`.
+pub struct Producer<'a, T: 'a> {
+    queue: &'a GlobalQueue<T>,
+}
+
+// Not derived, since that would require T to be Clone.
+impl<'a, T> Clone for Producer<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Producer<'a, T> {}
+
+impl<'a, T> Producer<'a, T> {
+    pub fn enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        self.queue.enqueue(value)
+    }
+
+    pub fn enqueue_tracked(&self, value: T) -> Result<Completion, SendError<T>> {
+        self.queue.enqueue_tracked(value)
+    }
+}
+
+/// The consuming side of a `GlobalQueue`.
+pub struct Consumer<'a, T: 'a> {
+    queue: &'a GlobalQueue<T>,
+}
+
+// Not derived, since that would require T to be Clone.
+impl<'a, T> Clone for Consumer<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Consumer<'a, T> {}
+
+impl<'a, T> Consumer<'a, T> {
+    pub fn dequeue(&self) -> Option<T> {
+        self.queue.dequeue()
+    }
+
+    pub fn claim(&self) -> Option<Claim<'a, T>> {
+        let queue: &'a Queue<T> = self.queue;
+        queue.claim()
+    }
+
+    /// Iterate over the elements available without blocking.
+    pub fn try_iter(&self) -> TryIter<'a, T> {
+        let queue: &'a Queue<T> = self.queue;
+        queue.try_iter()
+    }
+
+    /// Iterate over the elements, blocking while the queue is empty, until it is closed and
+    /// drained.
+    pub fn iter(&self) -> Iter<'a, T> {
+        let queue: &'a Queue<T> = self.queue;
+        queue.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    static_queue!(EVENTS: u32);
+    static_queue!(
+        /// A queue with documentation.
+        pub(crate) NAMES: String
+    );
+
+    #[test]
+    fn test_static_queue() {
+        let count = if cfg!(miri) { 50 } else { 1_000 };
+        let producers: Vec<_> = (0..4).map(|thread| {
+            thread::spawn(move || {
+                for i in 0..count {
+                    EVENTS.producer().enqueue(thread * count + i).expect("enqueue");
+                }
+            })
+        }).collect();
+        for producer in producers {
+            producer.join().expect("join");
+        }
+
+        let mut received: Vec<_> = EVENTS.consumer().try_iter().collect();
+        received.sort();
+        assert_eq!(received, (0..4 * count).collect::<Vec<_>>());
+
+        // The handles are copied whatever the type of the elements.
+        let producer = NAMES.producer();
+        let consumer = NAMES.consumer();
+        for handle in [producer, producer] {
+            handle.enqueue("name".to_string()).expect("enqueue");
+        }
+        for handle in [consumer, consumer] {
+            assert_eq!(handle.claim().expect("claim").commit(), "name");
+        }
+    }
+}
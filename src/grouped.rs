@@ -0,0 +1,262 @@
+//! A queue whose consumer groups each receive every element, while the consumers of a group
+//! compete for them.
+//!
+//! The elements are kept once, in the list of a `broadcast::Queue`. Each group has a cursor on
+//! the last node taken by one of its consumers, which references the node like a subscriber. A
+//! consumer takes the next node by moving the cursor to it with a compare-and-swap, so each
+//! element is taken once per group. The consumers of a group read the node of the cursor while
+//! another one can move the cursor and release the node, so they protect it with a hazard
+//! pointer, like the producers protect the tail.
+
+use std::ptr;
+
+use broadcast::{self, Node};
+use reclaim::hazard::Hazard;
+use sync::{AtomicPtr, AtomicUsize, Exclusive};
+use sync::atomic::Ordering;
+
+/// Identifier of a consumer group of a `GroupedQueue`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GroupId(usize);
+
+/// The cursor of a group, in a stack only freed with the queue.
+struct Cursor<T> {
+    id: GroupId,
+    /// The last node taken by a consumer of the group, which it references.
+    node: AtomicPtr<Node<T>>,
+    next: *mut Cursor<T>,
+}
+
+/// A queue where every consumer group receives all the elements, while the consumers of a same
+/// group compete for them.
+///
+/// A group receives a clone of the elements enqueued after it was registered.
+pub struct GroupedQueue<T> {
+    list: broadcast::Queue<T>,
+    /// Top of the stack of the cursors of the groups.
+    groups: AtomicPtr<Cursor<T>>,
+    group_count: AtomicUsize,
+}
+
+// The groups clone the same elements concurrently.
+unsafe impl<T: Send + Sync> Send for GroupedQueue<T> {}
+unsafe impl<T: Send + Sync> Sync for GroupedQueue<T> {}
+
+impl<T: Clone> GroupedQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            list: broadcast::Queue::new(),
+            groups: AtomicPtr::new(ptr::null_mut()),
+            group_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Register a new group, which only sees the elements enqueued from now on.
+    pub fn register_group(&self) -> GroupId {
+        let id = GroupId(self.group_count.fetch_add(1, Ordering::Relaxed));
+        let cursor = Box::into_raw(Box::new(Cursor {
+            id,
+            node: AtomicPtr::new(self.list.reference_tail()),
+            next: ptr::null_mut(),
+        }));
+        // The cursors are never removed, so the top cannot be reused between the load and the CAS.
+        let mut top = self.groups.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*cursor).next = top };
+            match self.groups.compare_exchange_weak(top, cursor, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return id,
+                Err(current) => top = current,
+            }
+        }
+    }
+
+    /// Enqueue the element for every group. It is dropped once every group took it, right away if
+    /// there's no group.
+    pub fn enqueue(&self, value: T) {
+        self.list.enqueue(value);
+    }
+
+    /// A handle to consume the elements of a group.
+    ///
+    /// Panics if the group was not registered on this queue.
+    pub fn group(&self, id: GroupId) -> Group<'_, T> {
+        let mut cursor = self.groups.load(Ordering::Acquire);
+        while !cursor.is_null() {
+            let current = unsafe { &*cursor };
+            if current.id == id {
+                return Group {
+                    cursor: current,
+                };
+            }
+            cursor = current.next;
+        }
+        panic!("group not registered on this queue");
+    }
+
+    pub fn group_count(&self) -> usize {
+        self.group_count.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Clone> Default for GroupedQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for GroupedQueue<T> {
+    fn drop(&mut self) {
+        let mut cursor = self.groups.load_mut();
+        while !cursor.is_null() {
+            let current = unsafe { Box::from_raw(cursor) };
+            unsafe { broadcast::release(current.node.load(Ordering::Acquire)) };
+            cursor = current.next;
+        }
+    }
+}
+
+/// The consuming side of a group of a `GroupedQueue`.
+pub struct Group<'a, T: 'a> {
+    cursor: &'a Cursor<T>,
+}
+
+impl<'a, T> Clone for Group<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Group<'a, T> {}
+
+// The cursor is only modified through its atomics.
+unsafe impl<'a, T: Send + Sync> Send for Group<'a, T> {}
+unsafe impl<'a, T: Send + Sync> Sync for Group<'a, T> {}
+
+impl<'a, T: Clone> Group<'a, T> {
+    /// Take a clone of the next element of the group, or return `None` if there is none or if its
+    /// producer is between its two steps.
+    pub fn dequeue(&self) -> Option<T> {
+        let node_hazard = Hazard::new();
+        let next_hazard = Hazard::new();
+        loop {
+            let node = node_hazard.protect(&self.cursor.node);
+            let next = next_hazard.protect(unsafe { &(*node).next });
+            if next.is_null() {
+                return None;
+            }
+            // The link from the node keeps the next one alive while the node is referenced by the
+            // cursor, so the next node was not retired before being protected if the cursor did
+            // not move.
+            if self.cursor.node.load(Ordering::SeqCst) != node {
+                continue;
+            }
+            unsafe {
+                // The reference of the cursor once it is moved.
+                (*next).references.fetch_add(1, Ordering::Relaxed);
+                match self.cursor.node.compare_exchange(node, next, Ordering::AcqRel, Ordering::Relaxed) {
+                    Ok(_) => {
+                        broadcast::release(node);
+                        // The node stays protected even if the cursor moves past it.
+                        return (*next).value.clone();
+                    },
+                    Err(_) => broadcast::release(next),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use reclaim::hazard;
+    use super::GroupedQueue;
+
+    #[test]
+    fn test_groups() {
+        let queue = GroupedQueue::new();
+        queue.enqueue(0);
+        let billing = queue.register_group();
+        queue.enqueue(1);
+        let audit = queue.register_group();
+        queue.enqueue(2);
+        assert_eq!(queue.group_count(), 2);
+
+        let billing = queue.group(billing);
+        let audit = queue.group(audit);
+        assert_eq!(billing.dequeue(), Some(1));
+        assert_eq!(audit.dequeue(), Some(2));
+        assert_eq!(billing.dequeue(), Some(2));
+        assert_eq!(billing.dequeue(), None);
+        assert_eq!(audit.dequeue(), None);
+    }
+
+    #[test]
+    fn test_reclaim() {
+        let value = Arc::new(());
+        let queue = GroupedQueue::new();
+        let first = queue.register_group();
+        let second = queue.register_group();
+        for _ in 0..10 {
+            queue.enqueue(value.clone());
+        }
+        // The elements are kept once for both groups.
+        assert_eq!(Arc::strong_count(&value), 11);
+        for _ in 0..10 {
+            assert!(queue.group(first).dequeue().is_some());
+        }
+        hazard::flush();
+        assert_eq!(Arc::strong_count(&value), 11);
+        for _ in 0..5 {
+            assert!(queue.group(second).dequeue().is_some());
+        }
+        // The node of each cursor keeps its element.
+        hazard::flush();
+        assert_eq!(Arc::strong_count(&value), 7);
+        drop(queue);
+        hazard::flush();
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_load_balancing() {
+        let queue = Arc::new(GroupedQueue::new());
+        let groups = [queue.register_group(), queue.register_group()];
+        for i in 0..10_000 {
+            queue.enqueue(i);
+        }
+
+        // Two consumers per group, and a third group registered concurrently.
+        let late = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.register_group())
+        };
+        let handles: Vec<_> = groups.iter().flat_map(|&group| vec![group, group]).map(|group| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut elements = vec![];
+                while let Some(element) = queue.group(group).dequeue() {
+                    elements.push(element);
+                }
+                (group, elements)
+            })
+        }).collect();
+
+        let mut received = vec![vec![]; 2];
+        for handle in handles {
+            let (group, elements) = handle.join().expect("join");
+            let index = groups.iter().position(|&id| id == group).expect("group");
+            received[index].extend(elements);
+        }
+        for elements in &mut received {
+            elements.sort();
+            assert_eq!(*elements, (0..10_000).collect::<Vec<_>>());
+        }
+        let late = late.join().expect("join");
+        assert_eq!(queue.group(late).dequeue(), None);
+        assert_eq!(queue.group_count(), 3);
+    }
+}
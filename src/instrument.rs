@@ -0,0 +1,236 @@
+//! Reporting of the activity of the queues to the `metrics` facade, with the `metrics` feature.
+//!
+//! A queue created with `Queue::named()` reports, with a `queue` label holding its name:
+//!
+//! * `queue_depth`: a gauge of the number of elements in the queue;
+//! * `queue_enqueued_total` and `queue_dequeued_total`: the throughput counters;
+//! * `queue_sojourn_seconds`: a histogram of the time spent in the queue, with the
+//!   `sojourn-time` feature;
+//! * `queue_lost_total`: the elements dropped or rejected by a `BoundedQueue` or a `LossyQueue`,
+//!   or not mirrored in the queue by a `Tee`, with a `reason` label (`full`, `closed` or
+//!   `alloc_failed`).
+
+#[cfg(feature = "metrics")]
+use metrics::{Counter, Gauge, Histogram, counter, gauge, histogram};
+
+use core::sync::atomic::Ordering;
+
+use Queue;
+#[cfg(feature = "std")]
+use SendError;
+use reclaim::Reclaim;
+use sojourn::Stamp;
+
+#[cfg(feature = "metrics")]
+pub(crate) struct Metrics {
+    depth: Gauge,
+    enqueued: Counter,
+    dequeued: Counter,
+    #[cfg_attr(not(feature = "sojourn-time"), allow(dead_code))]
+    sojourn: Histogram,
+    full: Counter,
+    closed: Counter,
+    alloc_failed: Counter,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    fn new(name: &str) -> Self {
+        let labels = [("queue", name.to_string())];
+        let lost = |reason: &'static str| {
+            counter!("queue_lost_total", &[("queue", name.to_string()), ("reason", reason.to_string())])
+        };
+        Self {
+            depth: gauge!("queue_depth", &labels),
+            enqueued: counter!("queue_enqueued_total", &labels),
+            dequeued: counter!("queue_dequeued_total", &labels),
+            sojourn: histogram!("queue_sojourn_seconds", &labels),
+            full: lost("full"),
+            closed: lost("closed"),
+            alloc_failed: lost("alloc_failed"),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<T, R: Reclaim> Queue<T, R> {
+    pub(crate) fn set_metrics(&mut self, name: &str) {
+        self.metrics = Some(Metrics::new(name));
+    }
+}
+
+// The hooks are always defined, so that the queue does not need to check the features. They also
+// maintain the length of the queue, which is Relaxed since it does not publish anything: the
+// modifications are still totally ordered, so it is exact once they are all visible.
+impl<T, R: Reclaim> Queue<T, R> {
+    pub(crate) fn record_enqueue(&self, count: usize) {
+        self.len.fetch_add(count, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        if let Some(ref metrics) = self.metrics {
+            metrics.enqueued.increment(count as u64);
+            metrics.depth.increment(count as f64);
+        }
+    }
+
+    /// Record an element which left the queue for good.
+    pub(crate) fn record_dequeue(&self, _stamp: Stamp) {
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "sojourn-time")]
+        let sojourn = _stamp.elapsed();
+        #[cfg(feature = "sojourn-time")]
+        self.latencies.record(sojourn);
+        #[cfg(feature = "metrics")]
+        if let Some(ref metrics) = self.metrics {
+            metrics.dequeued.increment(1);
+            metrics.depth.decrement(1.0);
+            #[cfg(feature = "sojourn-time")]
+            metrics.sojourn.record(sojourn);
+        }
+    }
+
+    /// Record elements dropped from the queue without being dequeued.
+    pub(crate) fn record_discard(&self, count: usize) {
+        self.len.fetch_sub(count, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        if let Some(ref metrics) = self.metrics {
+            metrics.depth.decrement(count as f64);
+        }
+    }
+
+    /// Only the bounded and lossy queues lose elements, and they need the `std` feature.
+    #[cfg(feature = "std")]
+    pub(crate) fn record_loss<U>(&self, _error: &SendError<U>) {
+        #[cfg(feature = "metrics")]
+        if let Some(ref metrics) = self.metrics {
+            let counter =
+                match *_error {
+                    SendError::Full(_) => &metrics.full,
+                    SendError::Closed(_) => &metrics.closed,
+                    SendError::AllocFailed(_) | SendError::PoolExhausted(_) => &metrics.alloc_failed,
+                };
+            counter.increment(1);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use metrics::{self, Counter, CounterFn, Gauge, GaugeFn, Histogram, Key, KeyName, Metadata, Recorder,
+        SharedString, Unit};
+
+    use {BoundedQueue, LossyQueue, MirrorPolicy, Queue, Tee};
+    use bounded::DropNewest;
+
+    /// A counter or a gauge, whose value is stored as `f64` bits.
+    struct Value(AtomicU64);
+
+    impl Value {
+        fn update<F: Fn(f64) -> f64>(&self, function: F) {
+            let _ = self.0.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |bits| {
+                Some(function(f64::from_bits(bits)).to_bits())
+            });
+        }
+    }
+
+    impl CounterFn for Value {
+        fn increment(&self, value: u64) {
+            self.update(|current| current + value as f64);
+        }
+
+        fn absolute(&self, value: u64) {
+            self.update(|_| value as f64);
+        }
+    }
+
+    impl GaugeFn for Value {
+        fn increment(&self, value: f64) {
+            self.update(|current| current + value);
+        }
+
+        fn decrement(&self, value: f64) {
+            self.update(|current| current - value);
+        }
+
+        fn set(&self, value: f64) {
+            self.update(|_| value);
+        }
+    }
+
+    #[derive(Default)]
+    struct TestRecorder {
+        values: Mutex<HashMap<String, Arc<Value>>>,
+    }
+
+    impl TestRecorder {
+        fn register(&self, key: &Key) -> Arc<Value> {
+            let labels: Vec<_> = key.labels().map(|label| format!("{}={}", label.key(), label.value())).collect();
+            let name = format!("{}{{{}}}", key.name(), labels.join(","));
+            self.values.lock().expect("lock").entry(name)
+                .or_insert_with(|| Arc::new(Value(AtomicU64::new(0f64.to_bits()))))
+                .clone()
+        }
+
+        fn value(&self, name: &str) -> f64 {
+            let values = self.values.lock().expect("lock");
+            f64::from_bits(values[name].0.load(Ordering::SeqCst))
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {
+        }
+
+        fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {
+        }
+
+        fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {
+        }
+
+        fn register_counter(&self, key: &Key, _metadata: &Metadata) -> Counter {
+            Counter::from_arc(self.register(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _metadata: &Metadata) -> Gauge {
+            Gauge::from_arc(self.register(key))
+        }
+
+        fn register_histogram(&self, _key: &Key, _metadata: &Metadata) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let recorder = TestRecorder::default();
+        let (queue, bounded, audit) = metrics::with_local_recorder(&recorder, || {
+            (Queue::named("jobs"), BoundedQueue::new(1, DropNewest).named("events"), Arc::new(LossyQueue::new(1).named("audit")))
+        });
+
+        for i in 0..3 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        queue.dequeue();
+        drop(queue.claim());
+        assert_eq!(recorder.value("queue_enqueued_total{queue=jobs}"), 3.0);
+        assert_eq!(recorder.value("queue_dequeued_total{queue=jobs}"), 1.0);
+        assert_eq!(recorder.value("queue_depth{queue=jobs}"), 2.0);
+
+        bounded.enqueue(1).expect("enqueue");
+        bounded.enqueue(2).expect("enqueue");
+        bounded.close();
+        assert!(bounded.enqueue(3).is_err());
+        assert_eq!(recorder.value("queue_depth{queue=events}"), 1.0);
+        assert_eq!(recorder.value("queue_lost_total{queue=events,reason=full}"), 1.0);
+        assert_eq!(recorder.value("queue_lost_total{queue=events,reason=closed}"), 1.0);
+
+        let tee = Tee::new(Arc::new(Queue::new()), audit.clone(), MirrorPolicy::Skip);
+        tee.enqueue(1).expect("enqueue");
+        tee.enqueue(2).expect("enqueue");
+        assert!(!audit.enqueue(3));
+        assert_eq!(recorder.value("queue_lost_total{queue=audit,reason=full}"), 2.0);
+    }
+}
@@ -0,0 +1,222 @@
+//! Iterators dequeuing the elements of a queue.
+
+use core::iter::FromIterator;
+
+use Queue;
+use reclaim::{DefaultReclaim, Reclaim};
+use sojourn::Stamp;
+use sync::Exclusive;
+
+/// Iterator dequeuing the elements currently in the queue, which stops when it is observed empty.
+///
+/// Returned by `Queue::try_iter()`.
+pub struct TryIter<'a, T: 'a, R: 'a + Reclaim = DefaultReclaim> {
+    queue: &'a Queue<T, R>,
+}
+
+impl<'a, T, R: Reclaim> Iterator for TryIter<'a, T, R> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.queue.dequeue()
+    }
+}
+
+/// Iterator dequeuing the elements of the queue, blocking while it is empty, which ends when the
+/// queue is closed and drained.
+///
+/// Returned by `Queue::iter()`.
+#[cfg(feature = "std")]
+pub struct Iter<'a, T: 'a, R: 'a + Reclaim = DefaultReclaim> {
+    queue: &'a Queue<T, R>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, T, R: Reclaim> Iterator for Iter<'a, T, R> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.queue.dequeue_until(None, || self.queue.is_finished())
+    }
+}
+
+/// Iterator consuming a queue, which yields its elements without any synchronization.
+///
+/// Returned by `Queue::into_iter()`. Since nobody else can dequeue, the elements are yielded even
+/// if the queue is paused.
+pub struct IntoIter<T, R: Reclaim = DefaultReclaim> {
+    queue: Queue<T, R>,
+}
+
+impl<T, R: Reclaim> Iterator for IntoIter<T, R> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let queue = &mut self.queue;
+        // Like in pop(), the counted elements come first.
+        let counted = queue.counted.load_mut();
+        if counted > 0 {
+            queue.counted.store_mut(counted - 1);
+            queue.record_dequeue(Stamp::now());
+            return Some(unsafe { Queue::<T, R>::recreate() });
+        }
+        loop {
+            let head = queue.head.load_mut();
+            unsafe {
+                let first_node = (*head).next.load_mut();
+                if first_node.is_null() {
+                    return None;
+                }
+                queue.head.store_mut(first_node);
+                // The tail can lag behind after a batch.
+                if queue.tail.load_mut() == head {
+                    queue.tail.store_mut(first_node);
+                }
+                queue.free_list.recycle(head);
+                // The sentinels left by push_front() have no value.
+                if let Some(value) = (*first_node).value.take() {
+                    if let Some(tracker) = (*first_node).tracker.take() {
+                        tracker.complete();
+                    }
+                    queue.record_dequeue((*first_node).stamp);
+                    return Some(value);
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.queue.len();
+        (len, Some(len))
+    }
+}
+
+impl<T, R: Reclaim> IntoIterator for Queue<T, R> {
+    type Item = T;
+    type IntoIter = IntoIter<T, R>;
+
+    fn into_iter(self) -> IntoIter<T, R> {
+        IntoIter {
+            queue: self,
+        }
+    }
+}
+
+/// Build a queue with `enqueue_batch()`.
+impl<T, R: Reclaim + Default> FromIterator<T> for Queue<T, R> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let queue = Self::default();
+        queue.enqueue_batch(iter).expect("enqueue, since the queue is not closed yet");
+        queue
+    }
+}
+
+/// Append the elements with `enqueue_batch()`.
+///
+/// Panics if the queue is closed.
+impl<T, R: Reclaim> Extend<T> for Queue<T, R> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        if self.enqueue_batch(iter).is_err() {
+            panic!("extend a closed queue");
+        }
+    }
+}
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Iterate over the elements, waiting for new ones until the queue is closed and every
+    /// element was dequeued.
+    ///
+    /// A worker thread can then be written as `for job in queue.iter() { ... }`.
+    #[cfg(feature = "std")]
+    pub fn iter(&self) -> Iter<'_, T, R> {
+        Iter {
+            queue: self,
+        }
+    }
+
+    /// Iterate over the elements available without blocking.
+    ///
+    /// Like `std::sync::mpsc::Receiver::try_iter()`, the iterator stops at the first empty
+    /// observation, but calling `next()` again can return elements enqueued since.
+    pub fn try_iter(&self) -> TryIter<'_, T, R> {
+        TryIter {
+            queue: self,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use Queue;
+
+    #[test]
+    fn test_iter() {
+        let queue = Arc::new(Queue::new());
+        let worker = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut jobs = vec![];
+                for job in queue.iter() {
+                    jobs.push(job);
+                }
+                jobs
+            })
+        };
+        for i in 0..1_000 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        queue.close();
+        assert_eq!(worker.join().expect("join"), (0..1_000).collect::<Vec<_>>());
+        assert_eq!(queue.iter().next(), None);
+    }
+
+    #[test]
+    fn test_try_iter() {
+        let queue = Queue::new();
+        assert_eq!(queue.try_iter().next(), None);
+        for i in 0..5 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        let mut iter = queue.try_iter();
+        assert_eq!(iter.by_ref().take(2).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(iter.collect::<Vec<_>>(), [2, 3, 4]);
+
+        queue.pause();
+        queue.enqueue(5).expect("enqueue");
+        assert_eq!(queue.try_iter().count(), 0);
+        queue.resume();
+        assert_eq!(queue.try_iter().collect::<Vec<_>>(), [5]);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let queue = Queue::new();
+        queue.enqueue_batch(0..3).expect("enqueue");
+        queue.claim().expect("claim").abort();
+        let completion = queue.enqueue_tracked(3).expect("enqueue");
+        queue.enqueue(4).expect("enqueue");
+        queue.pause();
+        let mut iter = queue.into_iter();
+        assert_eq!(iter.size_hint(), (5, Some(5)));
+        assert_eq!(iter.by_ref().take(4).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        assert_eq!(completion.try_wait(), Some(true));
+        // The element left is dropped with the iterator.
+        drop(iter);
+
+        let queue = Queue::new();
+        queue.enqueue(()).expect("enqueue");
+        queue.enqueue(()).expect("enqueue");
+        assert_eq!(queue.into_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut queue: Queue<_> = (0..3).collect();
+        queue.extend(3..5);
+        assert_eq!(queue.len(), 5);
+        assert_eq!(queue.into_iter().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+    }
+}
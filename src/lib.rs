@@ -1,18 +1,254 @@
-// TODO: check if could use weaker ordering than SeqCst.
+// The links between the nodes, the head and the tail are accessed with SeqCst: the hazard
+// pointers need a total order between the removal of a node and the scan of the slots, and the
+// notifiers between the linking of a node and the check for listeners. The other accesses use the
+// weakest ordering whose justification is written next to them.
 
-use std::ptr;
-use std::sync::atomic::{AtomicPtr, Ordering};
+// The pointers never go through integers, so that the crate works with strict provenance. The
+// tests check it with:
+// MIRIFLAGS="-Zmiri-strict-provenance" cargo +nightly miri test
+// The tests counting or replaying the fail points are ignored there, because Miri makes the weak
+// compare-and-swaps fail spuriously. They run without these failures with:
+// MIRIFLAGS="-Zmiri-strict-provenance -Zmiri-compare-exchange-weak-failure-rate=0" \
+//     cargo +nightly miri test --lib -- --include-ignored failpoint:: schedule::
+
+// Without the std feature, only core and alloc are used. The tests always have the standard
+// library, and check the queue without the feature with:
+// cargo test --no-default-features
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![cfg_attr(feature = "allocator-api", feature(allocator_api))]
+
+/// Hook called at interesting points of the algorithms, to let tests suspend a thread in the
+/// middle of an operation or count its steps. See the `failpoint` module.
+macro_rules! fail_point {
+    ($name:expr) => {
+        #[cfg(any(test, feature = "fail-points"))]
+        ::failpoint::hit($name);
+    };
+}
+
+/// Declare a global queue.
+///
+/// `static_queue!(pub EVENTS: Event);` declares a `static EVENTS: GlobalQueue<Event>`, which is
+/// used directly as a `Queue<Event>` or through its `producer()` and `consumer()` handles. It
+/// needs the `std` feature, since the queue is allocated on first use.
+///
+/// `static_queue!(pub EVENTS: Event; 16);` declares a `static EVENTS: StaticQueue<Event, 16>`
+/// instead, a bounded queue needing no allocator, for instance in firmware.
+#[macro_export]
+macro_rules! static_queue {
+    ($(#[$attribute:meta])* $visibility:vis $name:ident : $type:ty; $capacity:expr) => {
+        $(#[$attribute])*
+        $visibility static $name: $crate::StaticQueue<$type, $capacity> = $crate::StaticQueue::new();
+    };
+    ($(#[$attribute:meta])* $visibility:vis $name:ident : $type:ty) => {
+        $(#[$attribute])*
+        $visibility static $name: $crate::GlobalQueue<$type> = $crate::GlobalQueue::new();
+    };
+}
+
+#[cfg(feature = "std")]
+pub use any_queue::{AnyMessage, AnyQueue, Dispatcher};
+pub use array_queue::ArrayQueue;
+pub use backoff::{Backoff, BackoffPolicy};
+#[cfg(feature = "std")]
+pub use batch::{BatchQueue, FlushTrigger};
+#[cfg(feature = "std")]
+pub use bounded::{BoundedQueue, OverflowPolicy};
+#[cfg(feature = "checked")]
+pub use checked::CheckedQueue;
+pub use claim::Claim;
+#[cfg(feature = "std")]
+pub use coalesce::CoalescingQueue;
+#[cfg(feature = "std")]
+pub use completion::Completion;
+#[cfg(feature = "std")]
+pub use delay::DelayQueue;
+#[cfg(feature = "std")]
+pub use drain::DrainAndClose;
+pub use error::{DequeueError, RecvTimeoutError, SendError};
+#[cfg(feature = "std")]
+pub use faa_queue::FaaQueue;
+#[cfg(feature = "std")]
+pub use future::{Dequeue, DequeueTimeout};
+#[cfg(feature = "std")]
+pub use global::GlobalQueue;
+#[cfg(feature = "std")]
+pub use grouped::{Group, GroupId, GroupedQueue};
+#[cfg(feature = "std")]
+pub use lossy::LossyQueue;
+#[cfg(feature = "std")]
+pub use mailbox::{Mailbox, Recv};
+#[cfg(feature = "std")]
+pub use merge::MergeByKey;
+#[cfg(feature = "std")]
+pub use parallel::{ParallelConsumers, consume_parallel};
+pub use pool::NodePool;
+#[cfg(feature = "std")]
+pub use priority::PriorityLanes;
+pub use raw_list::RawList;
+#[cfg(feature = "std")]
+pub use realtime::RealTimeQueue;
+pub use scan::DebugFront;
+#[cfg(feature = "std")]
+pub use seg_queue::SegQueue;
+#[cfg(feature = "sojourn-time")]
+pub use sojourn::LatencyPercentiles;
+#[cfg(feature = "std")]
+pub use sqcq::{SqCq, Submission};
+pub use stack::Stack;
+pub use static_queue::StaticQueue;
+#[cfg(feature = "std")]
+pub use stats::QueueStats;
+#[cfg(feature = "std")]
+pub use tee::{MirrorPolicy, Tee, TeeTarget};
+#[cfg(feature = "std")]
+pub use variant::VariantQueue;
+#[cfg(feature = "std")]
+pub use wake::{NoThreadBackend, WakeBackend};
+
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate core;
+#[cfg(feature = "crossbeam-epoch")]
+extern crate crossbeam_epoch;
+#[cfg(feature = "futures")]
+extern crate futures_core;
+#[cfg(feature = "futures")]
+extern crate futures_sink;
+#[cfg(kani)]
+extern crate kani;
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+extern crate libc;
+#[cfg(loom)]
+extern crate loom;
+#[cfg(shuttle)]
+extern crate shuttle;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "portable-atomic")]
+extern crate portable_atomic;
+#[cfg(feature = "portable-atomic")]
+extern crate portable_atomic_util;
+
+#[cfg(feature = "std")]
+mod any_queue;
+mod array_queue;
+pub mod backoff;
+#[cfg(feature = "std")]
+mod batch;
+#[cfg(feature = "std")]
+pub mod bounded;
+#[cfg(feature = "std")]
+pub mod broadcast;
+#[cfg(feature = "std")]
+pub mod bytes;
+#[cfg(feature = "std")]
+pub mod channel;
+#[cfg(feature = "std")]
+mod coalesce;
+#[cfg(feature = "checked")]
+mod checked;
+mod claim;
+mod completion;
+#[cfg(feature = "std")]
+mod delay;
+#[cfg(feature = "std")]
+pub mod deque;
+mod drain;
+pub mod error;
+#[cfg(feature = "std")]
+mod faa_queue;
+#[cfg(any(test, feature = "fail-points"))]
+pub mod failpoint;
+mod free_list;
+#[cfg(feature = "std")]
+mod future;
+#[cfg(feature = "std")]
+pub mod global;
+#[cfg(feature = "std")]
+mod grouped;
+mod instrument;
+pub mod iter;
+#[cfg(feature = "std")]
+mod lossy;
+#[cfg(feature = "std")]
+mod mailbox;
+#[cfg(feature = "std")]
+mod merge;
+pub mod mpsc;
+#[cfg(feature = "std")]
+mod parallel;
+mod pool;
+#[cfg(feature = "std")]
+mod priority;
+#[cfg(kani)]
+mod proofs;
+pub mod raw_list;
+#[cfg(feature = "std")]
+mod realtime;
+pub mod reclaim;
+mod scan;
+#[cfg(any(test, feature = "fail-points"))]
+pub mod schedule;
+#[cfg(feature = "std")]
+mod seg_queue;
+mod sojourn;
+pub mod spsc;
+#[cfg(feature = "std")]
+mod sqcq;
+mod static_queue;
+pub mod stack;
+#[cfg(feature = "std")]
+mod stats;
+mod sync;
+#[cfg(feature = "std")]
+mod tee;
+#[cfg(feature = "std")]
+pub mod variant;
+pub mod wake;
+mod zst;
+
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use core::ptr;
+use core::sync::atomic::Ordering;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use completion::Tracker;
+use free_list::FreeList;
+use reclaim::{DefaultReclaim, Reclaim};
+use sojourn::Stamp;
+use sync::{Arc, AtomicBool, AtomicPtr, AtomicUsize, Exclusive, hint};
+use wake::Notifier;
+
+/// Bit of `Node::readers` set when the value is being taken.
+const TAKING: usize = 1 << (usize::BITS - 1);
+
+/// Bit of `Queue::state` set when the queue is closed.
+const CLOSED: usize = 1 << (usize::BITS - 1);
 
 struct Node<T> {
     next: AtomicPtr<Node<T>>,
     value: Option<T>,
+    tracker: Option<Tracker>,
+    stamp: Stamp,
+    /// Number of threads cloning the value in place, see `Queue::any()`.
+    readers: AtomicUsize,
 }
 
 impl<T> Node<T> {
-    fn new(value: T) -> Self {
+    fn new(value: T, tracker: Option<Tracker>) -> Self {
         Node {
             next: AtomicPtr::new(ptr::null_mut()),
             value: Some(value),
+            tracker,
+            stamp: Stamp::now(),
+            readers: AtomicUsize::new(0),
         }
     }
 
@@ -20,116 +256,1007 @@ impl<T> Node<T> {
         Node {
             next: AtomicPtr::new(ptr::null_mut()),
             value: None,
+            tracker: None,
+            stamp: Stamp::now(),
+            readers: AtomicUsize::new(0),
         }
     }
+
+    /// Take the value of a node removed from the queue, waiting for the threads still cloning
+    /// it.
+    ///
+    /// A node can be removed twice: when a claim is aborted while the node is the head, it is
+    /// put back in the list behind the claimed element, possibly before the thread which removed
+    /// it first took its value. Only the first caller gets the value.
+    unsafe fn take_value(node: *mut Self) -> Option<T> {
+        let readers = &(*node).readers;
+        // From now on, no new reader can start.
+        // The readers only synchronize through this counter, whose modifications are totally
+        // ordered: a reader either sees TAKING or is waited for.
+        let previous = readers.fetch_or(TAKING, Ordering::AcqRel);
+        if previous & TAKING != 0 {
+            return None;
+        }
+        if previous != 0 {
+            // Acquire the end of the clones of the value, before it is taken.
+            while readers.load(Ordering::Acquire) != TAKING {
+                hint::spin_loop();
+            }
+        }
+        (*node).value.take()
+    }
+
+    /// Clone the value of the node, unless it was taken or is being taken.
+    ///
+    /// Only the clone is done while the value is protected from the consumers, so that the
+    /// caller can run any code on the copy without delaying them.
+    unsafe fn clone_value(node: *const Self) -> Option<T> where T: Clone {
+        let readers = &(*node).readers;
+        // Leave even if the clone panics, so that the consumer does not wait forever.
+        let _reading = Reading(readers);
+        if readers.fetch_add(1, Ordering::Acquire) & TAKING != 0 {
+            return None;
+        }
+        (*node).value.clone()
+    }
+}
+
+struct Reading<'a>(&'a AtomicUsize);
+
+impl<'a> Drop for Reading<'a> {
+    fn drop(&mut self) {
+        // Release the clone of the value to the thread taking it.
+        self.0.fetch_sub(1, Ordering::Release);
+    }
 }
 
-pub struct Queue<T> {
+/// A lock-free queue, whose removed nodes are reclaimed with the strategy `R`.
+///
+/// The queue can only be shared between threads if its elements can be sent to another thread:
+///
+/// ```compile_fail
+/// # extern crate lock_free_queue;
+/// # use std::rc::Rc;
+/// # use std::sync::Arc;
+/// # use std::thread;
+/// # use lock_free_queue::Queue;
+/// # fn main() {
+/// let queue = Arc::new(Queue::new());
+/// queue.enqueue(Rc::new(1)).expect("enqueue");
+/// thread::spawn(move || queue.dequeue());
+/// # }
+/// ```
+///
+/// ```compile_fail
+/// # extern crate lock_free_queue;
+/// # use std::rc::Rc;
+/// # use std::thread;
+/// # use lock_free_queue::Queue;
+/// # fn main() {
+/// let queue = Queue::new();
+/// queue.enqueue(Rc::new(1)).expect("enqueue");
+/// thread::spawn(move || queue.dequeue());
+/// # }
+/// ```
+pub struct Queue<T, R: Reclaim = DefaultReclaim> {
     head: AtomicPtr<Node<T>>,
     tail: AtomicPtr<Node<T>>,
+    reclaim: R,
+    notifier: Notifier,
+    /// The policy of the retry loops, or the default `Backoff` if None.
+    backoff: Option<Arc<dyn BackoffPolicy>>,
+    #[cfg(feature = "sojourn-time")]
+    latencies: sojourn::Histogram,
+    #[cfg(feature = "metrics")]
+    metrics: Option<instrument::Metrics>,
+    /// Notified when the queue becomes empty.
+    drained: Notifier,
+    paused: AtomicBool,
+    /// The `CLOSED` bit, and the number of producers between their check of this bit and the
+    /// link of their element, in the same word so that a single RMW does both.
+    state: AtomicUsize,
+    name: Option<String>,
+    /// Number of zero-sized elements enqueued without a node, see the `zst` module.
+    counted: AtomicUsize,
+    /// Number of elements, maintained by the hooks of the `instrument` module.
+    len: AtomicUsize,
+    /// Removed nodes kept for new elements, shared with the deferred reclamation.
+    free_list: Arc<FreeList<T>>,
+    /// The auto traits would be implemented for any `T`, since the elements are only reached
+    /// through atomic pointers: see the impls below instead.
+    _marker: PhantomData<*const T>,
 }
 
+// The elements are moved in by the producers and out by the consumers, and the removed nodes can
+// be dropped by any thread, so the elements only need to be Send, even for the queue to be Sync:
+// a value is never accessed by two threads at once, except by the clones of the scans like `any()`
+// and `position_of()`, which require T: Sync.
+unsafe impl<T: Send, R: Reclaim + Send> Send for Queue<T, R> {}
+unsafe impl<T: Send, R: Reclaim + Sync> Sync for Queue<T, R> {}
+
 impl<T> Queue<T> {
     pub fn new() -> Self {
-        let pointer = Box::into_raw(Box::new(Node::sentinel()));
+        Self::with_reclaim(DefaultReclaim::default())
+    }
+
+    /// Create a queue whose blocking operations use the specified backend to wait.
+    #[cfg(feature = "std")]
+    pub fn with_wake_backend(backend: Arc<dyn WakeBackend>) -> Self {
+        let mut queue = Self::new();
+        queue.notifier = Notifier::new(Some(backend.clone()));
+        queue.drained = Notifier::new(Some(backend));
+        queue
+    }
+
+    /// Create a queue with a name identifying it in the debug output and, with the `metrics`
+    /// feature, in the metrics.
+    pub fn named(name: &str) -> Self {
+        let mut queue = Self::new();
+        queue.set_name(name);
+        queue
+    }
+}
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Create a queue reclaiming its removed nodes with the specified strategy.
+    pub fn with_reclaim(reclaim: R) -> Self {
+        Self::with_free_list(reclaim, FreeList::new())
+    }
+
+    fn with_free_list(reclaim: R, free_list: FreeList<T>) -> Self {
+        let pointer = free_list.create(Node::sentinel());
         Self {
             head: AtomicPtr::new(pointer),
             tail: AtomicPtr::new(pointer),
+            reclaim,
+            notifier: Notifier::default(),
+            backoff: None,
+            #[cfg(feature = "sojourn-time")]
+            latencies: sojourn::Histogram::new(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            drained: Notifier::default(),
+            paused: AtomicBool::new(false),
+            state: AtomicUsize::new(0),
+            name: None,
+            counted: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            free_list: Arc::new(free_list),
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+        #[cfg(feature = "metrics")]
+        self.set_metrics(name);
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Add an element to the queue, or give it back if the queue is closed.
+    ///
+    /// Aborts if the node cannot be allocated: use `try_enqueue()` to get the element back instead.
+    /// A queue taking its nodes from a `NodePool` gives the element back in
+    /// `SendError::PoolExhausted` when the pool is empty.
+    pub fn enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        if self.free_list.has_pool() {
+            return self.try_enqueue(value);
         }
+        self.enqueue_open(value, |value| {
+            if Self::is_counted() {
+                self.enqueue_counted(value);
+                return Ok(());
+            }
+            let guard = self.pin();
+            self.enqueue_node(self.new_node(Node::new(value, None)), &guard);
+            Ok(())
+        })
+    }
+
+    /// Add an element to the queue, or give it back if the queue is closed or if its node cannot
+    /// be allocated or taken from the pool of the queue.
+    pub fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        self.try_enqueue_guarded(value, &self.pin())
+    }
+
+    fn try_enqueue_guarded(&self, value: T, guard: &R::Guard) -> Result<(), SendError<T>> {
+        self.enqueue_open(value, |value| {
+            if Self::is_counted() {
+                self.enqueue_counted(value);
+                return Ok(());
+            }
+            let node = self.allocate_node();
+            if node.is_null() {
+                if self.free_list.has_pool() {
+                    return Err(SendError::PoolExhausted(value));
+                }
+                return Err(SendError::AllocFailed(value));
+            }
+            unsafe { node.write(Node::new(value, None)) };
+            self.enqueue_node(node, guard);
+            Ok(())
+        })
+    }
+
+    /// Run `enqueue` unless the queue is closed.
+    pub(crate) fn enqueue_open<V, U, F>(&self, value: V, enqueue: F) -> Result<U, SendError<V>>
+    where F: FnOnce(V) -> Result<U, SendError<V>>,
+    {
+        // Announce the producer while checking, so that a consumer which sees the queue closed
+        // and no producer in progress also sees its element.
+        let state = self.state.fetch_add(1, Ordering::SeqCst);
+        let result =
+            if state & CLOSED != 0 {
+                Err(SendError::Closed(value))
+            }
+            else {
+                enqueue(value)
+            };
+        self.state.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    /// Whether the queue is closed and no element is left nor about to be enqueued.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == CLOSED && self.is_drained()
+    }
+
+    /// Add the elements of an iterator, which become visible to the consumers all at once.
+    ///
+    /// The nodes are chained privately before being attached to the tail with a single
+    /// compare-and-swap, so the batch is contiguous even with concurrent producers.
+    ///
+    /// If the queue is closed, the iterator is given back without taking any element from it.
+    pub fn enqueue_batch<I: IntoIterator<Item = T>>(&self, items: I) -> Result<(), SendError<I::IntoIter>> {
+        self.enqueue_open(items.into_iter(), |mut items| {
+            if Self::is_counted() {
+                for value in items {
+                    self.enqueue_counted(value);
+                }
+                return Ok(());
+            }
+            let first = match items.next() {
+                Some(value) => self.new_node(Node::new(value, None)),
+                None => return Ok(()),
+            };
+            let mut last = first;
+            let mut count = 1;
+            for value in items {
+                let node = self.new_node(Node::new(value, None));
+                // The chain is not shared yet, and the CAS linking it publishes these stores.
+                unsafe { (*last).next.store(node, Ordering::Relaxed) };
+                last = node;
+                count += 1;
+            }
+            let guard = self.pin();
+            self.enqueue_chain(first, last, count, &guard);
+            Ok(())
+        })
+    }
+
+    fn enqueue_node(&self, new_tail: *mut Node<T>, guard: &R::Guard) {
+        self.enqueue_chain(new_tail, new_tail, 1, guard);
+    }
+
+    /// Attach the `count` nodes from `first` to `last`, already linked together, after the tail.
+    fn enqueue_chain(&self, first: *mut Node<T>, last: *mut Node<T>, count: usize, guard: &R::Guard) {
+        // Recorded first, so that the consumers cannot remove the elements before they are counted.
+        self.record_enqueue(count);
+        self.link(first, last, guard);
+        self.notifier.notify();
     }
 
-    pub fn enqueue(&self, value: T) {
-        let new_tail = Box::into_raw(Box::new(Node::new(value)));
+    /// Link `first` after the tail, then try to move the tail to `last`. If other threads help
+    /// first, the tail moves one node at a time along the chain.
+    fn link(&self, first: *mut Node<T>, last: *mut Node<T>, guard: &R::Guard) {
         let mut tail;
+        let mut step = 0;
         loop {
-            //println!("Enqueue");
-            tail = self.tail.load(Ordering::SeqCst);
+            fail_point!("enqueue.loop");
+            tail = self.reclaim.protect(guard, 0, &self.tail);
             unsafe {
-                let true_tail = (*tail).next.load(Ordering::SeqCst);
+                // Acquire the node to give it to the other threads through the tail. A stale null
+                // only makes the CAS below fail.
+                let true_tail = (*tail).next.load(Ordering::Acquire);
                 if !true_tail.is_null() {
                     // If the tail field has not yet been updated by another thread, help it to do
-                    // so.
-                    self.tail.compare_and_swap(tail, true_tail, Ordering::SeqCst);
+                    // so and start again from the new tail.
+                    // A spurious failure only makes us help again.
+                    let _ = self.tail.compare_exchange_weak(tail, true_tail, Ordering::SeqCst, Ordering::Relaxed);
+                    continue;
                 }
-                if (*tail).next.compare_and_swap(ptr::null_mut(), new_tail, Ordering::SeqCst) != ptr::null_mut() {
-                    // We were unable to add the element to the queue.
-                    // We need to start the whole process again because the queue could have been
-                    // cleared meanwhile.
+                if (*tail).next.compare_exchange_weak(ptr::null_mut(), first, Ordering::SeqCst, Ordering::Relaxed).is_err() {
+                    // We were unable to add the element to the queue because another thread
+                    // added one first.
+                    self.back_off(&mut step);
                     continue;
                 }
             }
             break;
         }
+        fail_point!("enqueue.linked");
         // We don't know whether another thread added an element before of after the one we are
         // currently adding, so there's no point in trying to set the tail multiple times.
-        self.tail.compare_and_swap(tail, new_tail, Ordering::SeqCst);
+        let _ = self.tail.compare_exchange(tail, last, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    /// Number of elements in the queue, for instance to apply backpressure.
+    ///
+    /// The elements are counted from just before they can be dequeued until they are removed, or
+    /// until their claim is committed. With concurrent operations, the result is only a snapshot
+    /// which can include elements being enqueued or dequeued, but it is exact once they return.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Whether `len()` is 0, with the same consistency.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn dequeue(&self) -> Option<T> {
+        let guard = self.pin();
+        self.dequeue_node(&guard)
+    }
+
+    /// Dequeue an element, telling apart an empty queue from a closed one which will never have
+    /// elements again.
+    pub fn try_dequeue(&self) -> Result<T, DequeueError> {
+        if let Some(value) = self.dequeue() {
+            return Ok(value);
+        }
+        if self.is_finished() {
+            return Err(DequeueError::Closed);
+        }
+        Err(DequeueError::Empty)
+    }
+
+    /// Dequeue an element, parking the thread while the queue is empty (or paused) until an
+    /// element is enqueued.
+    ///
+    /// This waits forever on a queue which is closed and drained: use `iter()` or
+    /// `dequeue_timeout()` to stop when it is closed.
+    #[cfg(feature = "std")]
+    pub fn dequeue_blocking(&self) -> T {
+        self.dequeue_until(None, || false).expect("element, since nothing interrupts the wait")
+    }
+
+    /// Dequeue an element, blocking while the queue is empty until the timeout expires or the
+    /// queue is closed.
+    ///
+    /// A worker can call it in a loop to check a shutdown flag periodically even when no element
+    /// arrives; `.ok()` gives an `Option` when the reason does not matter.
+    #[cfg(feature = "std")]
+    pub fn dequeue_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let closed = || self.is_finished();
+        match self.dequeue_until(Some(deadline), closed) {
+            Some(value) => Ok(value),
+            None if closed() => Err(RecvTimeoutError::Closed),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Dequeue elements in the uninitialized buffer until it is full or the queue is observed
+    /// empty, returning how many elements were written at the start of the buffer.
+    pub fn dequeue_into_uninit(&self, buffer: &mut [MaybeUninit<T>]) -> usize {
+        let guard = self.pin();
+        let mut count = 0;
+        for slot in buffer {
+            match self.dequeue_node(&guard) {
+                Some(value) => {
+                    slot.write(value);
+                    count += 1;
+                },
+                None => break,
+            }
+        }
+        count
+    }
+
+    /// Call `f` on the dequeued elements until the queue is observed empty or the time budget is
+    /// spent, returning how many elements were handled.
+    ///
+    /// The budget is checked before each element, so the time spent in the last call of `f` can
+    /// overrun it.
+    #[cfg(feature = "std")]
+    pub fn dequeue_for<F: FnMut(T)>(&self, budget: Duration, mut f: F) -> usize {
+        let deadline = Instant::now() + budget;
+        let mut count = 0;
+        while Instant::now() < deadline {
+            match self.dequeue() {
+                Some(value) => {
+                    f(value);
+                    count += 1;
+                },
+                None => break,
+            }
+        }
+        count
+    }
+
+    fn dequeue_node(&self, guard: &R::Guard) -> Option<T> {
+        self.pop(guard).map(|(value, tracker, stamp)| {
+            self.record_dequeue(stamp);
+            if let Some(tracker) = tracker {
+                tracker.complete();
+            }
+            value
+        })
+    }
+
+    /// Remove the first element along with its tracker, without completing it, and its stamp.
+    fn pop(&self, guard: &R::Guard) -> Option<(T, Option<Tracker>, Stamp)> {
+        if self.paused.load(Ordering::SeqCst) {
+            return None;
+        }
+        if Self::is_counted() {
+            if let Some(value) = self.pop_counted() {
+                return Some((value, None, Stamp::now()));
+            }
+        }
+        let mut step = 0;
         loop {
-            //println!("Dequeue");
-            let head = self.head.load(Ordering::SeqCst);
+            fail_point!("dequeue.loop");
+            let head = self.reclaim.protect(guard, 0, &self.head);
             let tail = self.tail.load(Ordering::SeqCst);
             unsafe {
-                let first_node = (*head).next.load(Ordering::SeqCst);
+                let first_node = self.reclaim.protect(guard, 1, &(*head).next);
+                fail_point!("dequeue.loaded");
+                if self.head.load(Ordering::SeqCst) != head {
+                    // The first node could have been removed before it was protected.
+                    continue;
+                }
                 if head == tail {
                     if first_node.is_null() {
                         // The list is observed to be empty.
                         break;
                     }
-                    self.tail.compare_and_swap(tail, first_node, Ordering::SeqCst);
+                    let _ = self.tail.compare_exchange_weak(tail, first_node, Ordering::SeqCst, Ordering::Relaxed);
                 }
-                else {
-                    assert!(!first_node.is_null());
-                    let new_first_node = (*first_node).next.load(Ordering::SeqCst);
-                    if (*head).next.compare_and_swap(first_node, new_first_node, Ordering::SeqCst) == first_node {
-                        // We were able to remove the first element.
-                        if new_first_node.is_null() {
-                            // If we removed the last element, set the tail to be equal to the head.
-                            self.tail.compare_and_swap(tail, head, Ordering::SeqCst);
-                        }
-                        // TODO: add the node to the free list.
-                        return (*first_node).value.take();
+                else if self.head.compare_exchange_weak(head, first_node, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                    if let Some(element) = self.unlinked(guard, head, first_node) {
+                        return Some(element);
                     }
                 }
+                else {
+                    self.back_off(&mut step);
+                }
             }
         }
         None
     }
+
+    /// Finish the removal of the first element, after the head was moved from `head` to
+    /// `first_node`: its node is now the sentinel and nobody else will touch its value.
+    ///
+    /// Return `None` if the node was a sentinel left in the list by `push_front()`.
+    unsafe fn unlinked(&self, guard: &R::Guard, head: *mut Node<T>, first_node: *mut Node<T>) -> Option<(T, Option<Tracker>, Stamp)> {
+        self.retire(guard, head);
+        // The next node never goes back to null, so a stale value only causes a spurious
+        // notification.
+        if (*first_node).next.load(Ordering::Acquire).is_null() {
+            self.drained.notify();
+        }
+        let value = Node::take_value(first_node)?;
+        Some((value, (*first_node).tracker.take(), (*first_node).stamp))
+    }
+
+    /// Insert a value in front of every other element, so that it is the next one to be
+    /// dequeued.
+    fn push_front(&self, value: T, tracker: Option<Tracker>, stamp: Stamp, _guard: &R::Guard) {
+        let node = self.new_node(Node {
+            stamp,
+            ..Node::new(value, tracker)
+        });
+        let new_head = self.new_node(Node::sentinel());
+        unsafe {
+            // The new nodes are published by the CAS of the head.
+            (*new_head).next.store(node, Ordering::Relaxed);
+            let mut step = 0;
+            loop {
+                fail_point!("push_front.loop");
+                // The old sentinel stays in the list behind the new element, so that the tail,
+                // which could point to it, is still reachable from the head.
+                let head = self.head.load(Ordering::SeqCst);
+                (*node).next.store(head, Ordering::Relaxed);
+                if self.head.compare_exchange_weak(head, new_head, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                    break;
+                }
+                self.back_off(&mut step);
+            }
+        }
+        self.notifier.notify();
+    }
+
+    /// Stop the consumption: until `resume()` is called, the dequeue operations act as if the
+    /// queue was empty and the blocking ones wait.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.notifier.notify();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Refuse the new elements sent with `enqueue()` or `try_enqueue()`. The elements already in
+    /// the queue can still be dequeued.
+    ///
+    /// The queue stays closed until `reopen()` is called explicitly.
+    ///
+    /// This is enough to shut down without a separate flag or sentinel elements: the producers
+    /// get `SendError::Closed` back from `enqueue()` and `try_enqueue()`, and the consumers get
+    /// the remaining elements then `DequeueError::Closed` from `try_dequeue()`,
+    /// `RecvTimeoutError::Closed` from `dequeue_timeout()` or the end of `iter()`.
+    pub fn close(&self) {
+        self.state.fetch_or(CLOSED, Ordering::SeqCst);
+        // Wake up the consumers waiting for an element, which will never come if it's empty.
+        self.notifier.notify();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.load(Ordering::SeqCst) & CLOSED != 0
+    }
+
+    /// Accept the new elements sent with `enqueue()` and `try_enqueue()` again.
+    pub fn reopen(&self) {
+        self.state.fetch_and(!CLOSED, Ordering::SeqCst);
+    }
+
+    /// Drop every element still in the queue and reopen it, so that it can be reused instead of
+    /// being reallocated.
+    ///
+    /// The completions of the dropped elements resolve as not dequeued.
+    pub fn reset(&mut self) {
+        let head = self.head.load_mut();
+        let mut discarded = 0;
+        unsafe {
+            // The nodes are kept for the next elements.
+            let mut node = (*head).next.load_mut();
+            while !node.is_null() {
+                if (*node).value.is_some() {
+                    discarded += 1;
+                }
+                let next = (*node).next.load_mut();
+                self.free_list.recycle(node);
+                node = next;
+            }
+            (*head).next.store_mut(ptr::null_mut());
+        }
+        self.tail.store_mut(head);
+        // No producer can be in progress.
+        self.state.store_mut(0);
+        discarded += self.drop_counted();
+        self.record_discard(discarded);
+    }
+
+    /// Exchange the elements of the two queues in constant time, for double-buffering: producers
+    /// fill one queue while the elements of the other one are consumed.
+    ///
+    /// The other properties of the queues (name, closed or paused state) are not exchanged. With
+    /// the `metrics` feature, the depth gauges keep counting the elements of their own queue
+    /// until it is emptied.
+    pub fn swap_contents(&mut self, other: &mut Self) {
+        fn swap<A: Exclusive>(first: &mut A, second: &mut A) {
+            let value = first.load_mut();
+            first.store_mut(second.load_mut());
+            second.store_mut(value);
+        }
+
+        swap(&mut self.head, &mut other.head);
+        swap(&mut self.tail, &mut other.tail);
+        swap(&mut self.counted, &mut other.counted);
+        swap(&mut self.len, &mut other.len);
+        // The nodes are freed by the allocator which allocated them.
+        mem::swap(&mut self.free_list, &mut other.free_list);
+    }
+
+    /// Dequeue an element, blocking while the queue is empty (or paused) until the deadline
+    /// passes or `interrupted()` returns true. The interruption must be followed by a
+    /// notification.
+    #[cfg(feature = "std")]
+    pub(crate) fn dequeue_until<F: Fn() -> bool>(&self, deadline: Option<Instant>, interrupted: F) -> Option<T> {
+        loop {
+            if let Some(value) = self.dequeue() {
+                return Some(value);
+            }
+            let listener = self.notifier.listen();
+            // Check again now that an enqueue is guaranteed to wake us up.
+            if let Some(value) = self.dequeue() {
+                return Some(value);
+            }
+            if interrupted() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
+            }
+            listener.wait(deadline);
+        }
+    }
+}
+
+impl<T, R: Reclaim> Drop for Queue<T, R> {
+    fn drop(&mut self) {
+        // Free the nodes in a loop instead of recursively through their next field, so that a
+        // long queue cannot overflow the stack.
+        let mut node = self.head.load_mut();
+        while !node.is_null() {
+            unsafe {
+                let next = (*node).next.load_mut();
+                ptr::drop_in_place(node);
+                self.free_list.deallocate(node);
+                node = next;
+            }
+        }
+        self.drop_counted();
+    }
+}
+
+impl<T, R: Reclaim + Default> Default for Queue<T, R> {
+    fn default() -> Self {
+        Self::with_reclaim(R::default())
+    }
+}
+
+impl<T, R: Reclaim + Default> From<Vec<T>> for Queue<T, R> {
+    fn from(elements: Vec<T>) -> Self {
+        elements.into_iter().collect()
+    }
+}
+
+impl<T, R: Reclaim + Default> From<VecDeque<T>> for Queue<T, R> {
+    fn from(elements: VecDeque<T>) -> Self {
+        elements.into_iter().collect()
+    }
+}
+
+impl<T, R: Reclaim> fmt::Debug for Queue<T, R> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("Queue")
+            .field("name", &self.name)
+            .field("len", &self.len())
+            .field("paused", &self.is_paused())
+            .field("closed", &self.is_closed())
+            .finish_non_exhaustive()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::VecDeque;
+    use std::mem::MaybeUninit;
     use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::thread;
+    use std::time::Duration;
 
+    use DequeueError;
+    #[cfg(feature = "std")]
+    use RecvTimeoutError;
     use super::Queue;
 
     #[test]
     fn test_single_thread() {
         let queue = Queue::new();
-        queue.enqueue(10);
+        queue.enqueue(10).expect("enqueue");
         assert_eq!(queue.dequeue(), Some(10));
         assert_eq!(queue.dequeue(), None);
 
-        queue.enqueue(11);
-        queue.enqueue(12);
-        queue.enqueue(13);
+        queue.enqueue(11).expect("enqueue");
+        queue.enqueue(12).expect("enqueue");
+        queue.enqueue(13).expect("enqueue");
         assert_eq!(queue.dequeue(), Some(11));
         assert_eq!(queue.dequeue(), Some(12));
         assert_eq!(queue.dequeue(), Some(13));
         assert_eq!(queue.dequeue(), None);
 
-        queue.enqueue(14);
-        queue.enqueue(15);
+        queue.enqueue(14).expect("enqueue");
+        queue.enqueue(15).expect("enqueue");
         assert_eq!(queue.dequeue(), Some(14));
-        queue.enqueue(16);
+        queue.enqueue(16).expect("enqueue");
         assert_eq!(queue.dequeue(), Some(15));
         assert_eq!(queue.dequeue(), Some(16));
         assert_eq!(queue.dequeue(), None);
     }
 
     #[test]
+    fn test_named() {
+        let queue: Queue<u32> = Queue::named("orders");
+        assert_eq!(queue.name(), Some("orders"));
+        assert_eq!(format!("{:?}", queue), r#"Queue { name: Some("orders"), len: 0, paused: false, closed: false, .. }"#);
+        assert_eq!(Queue::<u32>::new().name(), None);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_drop_many() {
+        let queue = Queue::new();
+        for i in 0..3_000_000 {
+            queue.enqueue(Box::new(i)).expect("enqueue");
+        }
+        queue.dequeue();
+        drop(queue);
+    }
+
+    #[test]
+    fn test_drop_values() {
+        struct Counted(Arc<AtomicUsize>);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let queue = Queue::new();
+        for _ in 0..10 {
+            queue.enqueue(Counted(dropped.clone())).expect("enqueue");
+        }
+        drop(queue.dequeue());
+        // The aborted claim leaves a sentinel in the middle of the list.
+        drop(queue.claim());
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        drop(queue);
+        assert_eq!(dropped.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_len() {
+        let mut queue = Queue::new();
+        assert!(queue.is_empty());
+        for i in 0..5 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        assert_eq!(queue.len(), 5);
+        queue.dequeue();
+        let claim = queue.claim().expect("claim");
+        // The claimed element is counted until it is committed.
+        assert_eq!(queue.len(), 4);
+        drop(claim);
+        assert_eq!(queue.len(), 4);
+        queue.claim().expect("claim").commit();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.into_raw_list().len(), 3);
+        assert!(queue.is_empty());
+
+        queue.enqueue(5).expect("enqueue");
+        queue.enqueue(6).expect("enqueue");
+        let mut other = Queue::new();
+        queue.swap_contents(&mut other);
+        assert!(queue.is_empty());
+        assert_eq!(other.len(), 2);
+        other.reset();
+        assert!(other.is_empty());
+    }
+
+    #[test]
+    fn test_enqueue_batch() {
+        let queue = Queue::new();
+        queue.enqueue_batch(Vec::new()).expect("enqueue");
+        assert!(queue.is_empty());
+        queue.enqueue(0).expect("enqueue");
+        queue.enqueue_batch(1..4).expect("enqueue");
+        queue.enqueue(4).expect("enqueue");
+        assert_eq!(queue.len(), 5);
+        assert_eq!((0..5).map(|_| queue.dequeue()).collect::<Vec<_>>(), (0..5).map(Some).collect::<Vec<_>>());
+        assert_eq!(queue.dequeue(), None);
+
+        // The batches of concurrent producers are not interleaved.
+        let count = if cfg!(miri) { 20 } else { 1000 };
+        let queue = Queue::new();
+        thread::scope(|scope| {
+            for thread in 0..4 {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for batch in 0..count {
+                        queue.enqueue_batch((0..3).map(|i| (thread, batch, i))).expect("enqueue");
+                    }
+                });
+            }
+        });
+        assert_eq!(queue.len(), 4 * count * 3);
+        while let Some(first) = queue.dequeue() {
+            assert_eq!(first.2, 0);
+            assert_eq!(queue.dequeue(), Some((first.0, first.1, 1)));
+            assert_eq!(queue.dequeue(), Some((first.0, first.1, 2)));
+        }
+
+        let queue = Queue::new();
+        queue.enqueue_batch(vec![(); 3]).expect("enqueue");
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn test_from() {
+        let queue: Queue<_> = vec![1, 2].into();
+        assert_eq!(queue.into_iter().collect::<Vec<_>>(), [1, 2]);
+        let queue = Queue::<_>::from(VecDeque::from(vec![3, 4]));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.dequeue(), Some(3));
+    }
+
+    #[test]
+    fn test_pause() {
+        let queue = Queue::new();
+        queue.enqueue(1).expect("enqueue");
+        queue.enqueue(2).expect("enqueue");
+        queue.pause();
+        assert!(queue.is_paused());
+        assert_eq!(queue.dequeue(), None);
+        assert!(queue.claim().is_none());
+        queue.enqueue(3).expect("enqueue");
+        queue.resume();
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue(), Some(3));
+    }
+
+    #[test]
+    fn test_try_enqueue_closed() {
+        let queue = Queue::new();
+        assert_eq!(queue.try_enqueue(1), Ok(()));
+        queue.close();
+        assert!(queue.is_closed());
+        let error = queue.try_enqueue(2).expect_err("closed");
+        assert!(error.is_closed());
+        assert_eq!(error.into_inner(), 2);
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_try_dequeue() {
+        let queue = Queue::new();
+        assert_eq!(queue.try_dequeue(), Err(DequeueError::Empty));
+        queue.enqueue(1).expect("enqueue");
+        queue.close();
+        assert_eq!(queue.try_dequeue(), Ok(1));
+        assert_eq!(queue.try_dequeue(), Err(DequeueError::Closed));
+    }
+
+    #[test]
+    fn test_enqueue_closed() {
+        let queue = Queue::new();
+        queue.enqueue(1).expect("enqueue");
+        queue.close();
+        assert_eq!(queue.try_dequeue(), Ok(1));
+        assert_eq!(queue.try_dequeue(), Err(DequeueError::Closed));
+        // None of the ways to enqueue undoes the close.
+        assert_eq!(queue.enqueue(2).expect_err("closed").into_inner(), 2);
+        assert_eq!(queue.enqueue_batch(vec![3, 4]).expect_err("closed").into_inner().collect::<Vec<_>>(), [3, 4]);
+        #[cfg(feature = "std")]
+        assert_eq!(queue.enqueue_tracked(5).err().map(|error| error.into_inner()), Some(5));
+        assert!(queue.is_closed());
+        assert_eq!(queue.try_dequeue(), Err(DequeueError::Closed));
+        queue.reopen();
+        queue.enqueue(6).expect("enqueue");
+        assert_eq!(queue.try_dequeue(), Ok(6));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_dequeue_blocking() {
+        let queue = Arc::new(Queue::new());
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                queue.enqueue(1).expect("enqueue");
+                // Closing does not stop the wait for the next element.
+                queue.close();
+                thread::sleep(Duration::from_millis(10));
+                queue.reopen();
+                queue.enqueue(2).expect("enqueue");
+            })
+        };
+        assert_eq!(queue.dequeue_blocking(), 1);
+        assert_eq!(queue.dequeue_blocking(), 2);
+        producer.join().expect("join");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_dequeue_timeout() {
+        let queue = Arc::new(Queue::new());
+        assert_eq!(queue.dequeue_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Timeout));
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                queue.enqueue(1).expect("enqueue");
+                thread::sleep(Duration::from_millis(10));
+                queue.close();
+            })
+        };
+        assert_eq!(queue.dequeue_timeout(Duration::from_secs(10)), Ok(1));
+        assert_eq!(queue.dequeue_timeout(Duration::from_secs(10)), Err(RecvTimeoutError::Closed));
+        producer.join().expect("join");
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut queue = Queue::new();
+        for i in 0..10 {
+            queue.enqueue(i.to_string()).expect("enqueue");
+        }
+        assert_eq!(queue.dequeue(), Some("0".to_string()));
+        drop(queue.claim());
+        queue.close();
+        queue.reset();
+        assert!(!queue.is_closed());
+        assert_eq!(queue.dequeue(), None);
+        queue.try_enqueue("10".to_string()).expect("enqueue");
+        queue.enqueue("11".to_string()).expect("enqueue");
+        assert_eq!(queue.dequeue(), Some("10".to_string()));
+        assert_eq!(queue.dequeue(), Some("11".to_string()));
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_dequeue_into_uninit() {
+        let queue = Queue::new();
+        for i in 0..5 {
+            queue.enqueue(i.to_string()).expect("enqueue");
+        }
+        let mut buffer: [MaybeUninit<String>; 3] = [const { MaybeUninit::uninit() }; 3];
+        assert_eq!(queue.dequeue_into_uninit(&mut buffer), 3);
+        let received: Vec<_> = buffer.iter().map(|slot| unsafe { slot.assume_init_read() }).collect();
+        assert_eq!(received, ["0", "1", "2"]);
+        assert_eq!(queue.dequeue_into_uninit(&mut buffer), 2);
+        let received: Vec<_> = buffer[..2].iter().map(|slot| unsafe { slot.assume_init_read() }).collect();
+        assert_eq!(received, ["3", "4"]);
+        assert_eq!(queue.dequeue_into_uninit(&mut buffer), 0);
+    }
+
+    #[test]
+    fn test_swap_contents() {
+        let mut front = Queue::new();
+        let mut back = Queue::new();
+        front.enqueue(1).expect("enqueue");
+        front.enqueue(2).expect("enqueue");
+        back.close();
+        front.swap_contents(&mut back);
+        assert_eq!(front.dequeue(), None);
+        assert!(!front.is_closed());
+        front.enqueue(3).expect("enqueue");
+
+        assert_eq!(back.dequeue(), Some(1));
+        back.swap_contents(&mut front);
+        assert_eq!(back.dequeue(), Some(3));
+        assert_eq!(back.dequeue(), None);
+        assert_eq!(front.dequeue(), Some(2));
+        assert_eq!(front.dequeue(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_dequeue_for() {
+        let queue = Queue::new();
+        for i in 0..10 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        let mut received = vec![];
+        assert_eq!(queue.dequeue_for(Duration::from_secs(10), |value| received.push(value)), 10);
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+
+        queue.enqueue(10).expect("enqueue");
+        queue.enqueue(11).expect("enqueue");
+        // The budget is spent by the first element.
+        let handled = queue.dequeue_for(Duration::from_millis(10), |_| thread::sleep(Duration::from_millis(20)));
+        assert_eq!(handled, 1);
+        assert_eq!(queue.dequeue(), Some(11));
+        assert_eq!(queue.dequeue_for(Duration::ZERO, |_| ()), 0);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
     fn test_multithread() {
         let queue = Arc::new(Queue::new());
 
@@ -160,14 +1287,14 @@ mod tests {
                         //i += 1;
                     }
                 }
-                thread::sleep_ms(1000);
-                //i = 0;
-                //while i < 950_000 {
-                for _ in 0..950_000 {
+                thread::sleep(Duration::from_millis(1000));
+                // Count the elements rather than the attempts: 950_000 attempts are only enough if
+                // none of the 50_000 above found the queue empty, which happens when the producers
+                // are not started yet.
+                while elements.len() < 1_000_000 {
                     //sender.send("Thread dequeue");
                     if let Some(element) = queue.dequeue() {
                         elements.push(element);
-                        //i += 1;
                     }
                 }
                 *results.lock().expect("lock") = elements;
@@ -178,7 +1305,7 @@ mod tests {
             let queue = queue.clone();
             thread::spawn(move || {
                 for i in 0..100_000 {
-                    queue.enqueue(i);
+                    queue.enqueue(i).expect("enqueue");
                 }
             });
         }
@@ -187,7 +1314,7 @@ mod tests {
             let queue = queue.clone();
             thread::spawn(move || {
                 for i in 100_000..1_000_000 {
-                    queue.enqueue(i);
+                    queue.enqueue(i).expect("enqueue");
                 }
             });
         }
@@ -0,0 +1,198 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use {Queue, QueueStats, SendError};
+use stats::Losses;
+
+/// A bounded queue which drops the new elements when it is full, while counting them.
+///
+/// This is intended for telemetry pipelines, where losing data is acceptable as long as it can
+/// be measured.
+pub struct LossyQueue<T> {
+    queue: Queue<T>,
+    capacity: usize,
+    len: AtomicUsize,
+    dropped: AtomicUsize,
+    losses: Losses,
+    overflows: AtomicUsize,
+    sample_rate: usize,
+}
+
+impl<T> LossyQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self::sampled(capacity, 0)
+    }
+
+    /// Create a lossy queue which, when full, keeps one new element out of `sample_rate` by
+    /// dropping the oldest element instead, so that the queue does not only contain stale data.
+    /// A rate of 0 drops every new element.
+    pub fn sampled(capacity: usize, sample_rate: usize) -> Self {
+        Self {
+            queue: Queue::new(),
+            capacity,
+            len: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            losses: Losses::default(),
+            overflows: AtomicUsize::new(0),
+            sample_rate,
+        }
+    }
+
+    /// Give a name to the queue. See `Queue::named()`.
+    pub fn named(mut self, name: &str) -> Self {
+        self.queue.set_name(name);
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.queue.name()
+    }
+
+    /// Add an element to the queue, returning `false` if it was dropped because the queue is full.
+    pub fn enqueue(&self, value: T) -> bool {
+        let value =
+            match self.try_enqueue(value) {
+                Ok(()) => return true,
+                Err(SendError::Full(value)) => value,
+                Err(error) => {
+                    self.record_drop(&error);
+                    return false;
+                },
+            };
+
+        let sampled = self.sample_rate != 0 &&
+            self.overflows.fetch_add(1, Ordering::SeqCst).is_multiple_of(self.sample_rate);
+        // Replace the oldest element: the length stays the same.
+        if sampled && self.queue.dequeue().is_some() {
+            self.record_drop(&SendError::Full(()));
+            self.queue.enqueue(value).expect("enqueue, since the queue is never closed");
+            return true;
+        }
+        self.record_drop(&SendError::Full(()));
+        false
+    }
+
+    fn record_drop<U>(&self, error: &SendError<U>) {
+        self.dropped.fetch_add(1, Ordering::SeqCst);
+        self.record_loss(error);
+    }
+
+    /// Count an element lost on its way to the queue, like the ones a `Tee` could not mirror.
+    pub(crate) fn record_loss<U>(&self, error: &SendError<U>) {
+        self.losses.record(error);
+        self.queue.record_loss(error);
+    }
+
+    /// Add an element to the queue if it is not full, otherwise give it back without counting
+    /// it as dropped.
+    pub fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        let error =
+            if self.len.fetch_add(1, Ordering::SeqCst) < self.capacity {
+                match self.queue.try_enqueue(value) {
+                    Ok(()) => return Ok(()),
+                    Err(error) => error,
+                }
+            }
+            else {
+                SendError::Full(value)
+            };
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        Err(error)
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        let value = self.queue.dequeue();
+        if value.is_some() {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+        }
+        value
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of elements which can be enqueued before new ones are dropped. This is only a hint
+    /// with concurrent producers.
+    pub fn remaining(&self) -> usize {
+        // The length exceeds the capacity while a producer which found the queue full gives its
+        // reservation back.
+        self.capacity.saturating_sub(self.len.load(Ordering::SeqCst))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Number of elements dropped by `enqueue()`.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    /// The elements dropped by `enqueue()` and the ones a `Tee` could not mirror in this queue
+    /// are counted as lost. The elements given back by `try_enqueue()` are not.
+    pub fn stats(&self) -> QueueStats {
+        self.losses.stats(self.queue.len(), self.capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::LossyQueue;
+
+    #[test]
+    fn test_drop_new_elements() {
+        let queue = LossyQueue::new(3);
+        for i in 0..5 {
+            assert_eq!(queue.remaining(), 3 - i.min(3));
+            assert_eq!(queue.enqueue(i), i < 3);
+        }
+        assert!(queue.is_full());
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(queue.dequeue(), Some(0));
+        assert_eq!(queue.remaining(), 1);
+        assert!(queue.enqueue(5));
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue(), Some(5));
+        assert_eq!(queue.dequeue(), None);
+        assert_eq!(queue.dropped(), 2);
+    }
+
+    #[test]
+    fn test_sampling() {
+        let queue = LossyQueue::sampled(2, 2);
+        for i in 0..6 {
+            queue.enqueue(i);
+        }
+        // 2 and 4 were sampled and replaced the oldest elements, 3 and 5 were dropped.
+        assert_eq!(queue.dropped(), 4);
+        assert_eq!(queue.stats().full, 4);
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue(), Some(4));
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_multithread_accounting() {
+        let queue = Arc::new(LossyQueue::new(1_000));
+        let handles: Vec<_> = (0..4).map(|_| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                (0..10_000).filter(|&i| queue.enqueue(i)).count()
+            })
+        }).collect();
+        let enqueued: usize = handles.into_iter().map(|handle| handle.join().expect("join")).sum();
+
+        let mut received = 0;
+        while queue.dequeue().is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 1_000);
+        assert_eq!(enqueued, received);
+        assert_eq!(queue.dropped(), 40_000 - received);
+    }
+}
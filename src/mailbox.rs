@@ -0,0 +1,186 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use {BoundedQueue, DequeueError, OverflowPolicy, Queue, QueueStats, SendError};
+use wake::{Notifier, Registration};
+
+/// The mailbox of an actor: many senders and a single receiver, with a lane for the system
+/// messages (shutdown, supervision) which are received before the user messages.
+///
+/// The user lane is bounded and applies its overflow policy when it is full, while the system
+/// lane is unbounded so that a system message is never dropped.
+pub struct Mailbox<T> {
+    system: Queue<T>,
+    user: BoundedQueue<T>,
+    arrived: Notifier,
+}
+
+impl<T> Mailbox<T> {
+    /// Panics if `capacity` is 0.
+    pub fn new<P: OverflowPolicy<T> + 'static>(capacity: usize, policy: P) -> Self {
+        Self {
+            system: Queue::new(),
+            user: BoundedQueue::new(capacity, policy),
+            arrived: Notifier::new(None),
+        }
+    }
+
+    /// Give a name to the user lane, which reports its metrics. See `Queue::named()`.
+    pub fn named(mut self, name: &str) -> Self {
+        self.user = self.user.named(name);
+        self
+    }
+
+    /// Send a user message, applying the overflow policy if the mailbox is full.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.user.enqueue(message)?;
+        self.arrived.notify();
+        Ok(())
+    }
+
+    /// Send a system message, which is received before every user message.
+    pub fn send_system(&self, message: T) -> Result<(), SendError<T>> {
+        self.system.try_enqueue(message)?;
+        self.arrived.notify();
+        Ok(())
+    }
+
+    /// Receive the next message without waiting: the system messages come first.
+    pub fn try_recv(&self) -> Result<T, DequeueError> {
+        if let Some(message) = self.system.dequeue() {
+            return Ok(message);
+        }
+        if let Some(message) = self.user.dequeue() {
+            return Ok(message);
+        }
+        // A sender which saw the mailbox open can still be enqueuing its message.
+        if self.system.is_finished() && self.user.is_finished() {
+            return Err(DequeueError::Closed);
+        }
+        Err(DequeueError::Empty)
+    }
+
+    /// Receive the next message, resolving to `None` once the mailbox is closed and empty.
+    pub fn recv_async(&self) -> Recv<'_, T> {
+        Recv {
+            mailbox: self,
+            registration: Registration::default(),
+        }
+    }
+
+    /// Refuse the new messages of both lanes. The messages already sent can still be received.
+    pub fn close(&self) {
+        self.system.close();
+        self.user.close();
+        self.arrived.notify();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.system.is_closed()
+    }
+
+    /// Number of user messages waiting.
+    pub fn len(&self) -> usize {
+        self.user.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0 && self.system.is_drained()
+    }
+
+    /// Number of user messages dropped by the overflow policy.
+    pub fn dropped(&self) -> usize {
+        self.user.dropped()
+    }
+
+    /// The state of the user lane, with the messages it lost, see `BoundedQueue::stats()`.
+    pub fn stats(&self) -> QueueStats {
+        self.user.stats()
+    }
+}
+
+/// Future returned by `Mailbox::recv_async()`.
+pub struct Recv<'a, T: 'a> {
+    mailbox: &'a Mailbox<T>,
+    registration: Registration,
+}
+
+impl<'a, T> Future for Recv<'a, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        match self.mailbox.try_recv() {
+            Ok(message) => return Poll::Ready(Some(message)),
+            Err(DequeueError::Closed) => return Poll::Ready(None),
+            Err(DequeueError::Empty) => (),
+        }
+        let this = &mut *self;
+        this.mailbox.arrived.register(&mut this.registration, context.waker());
+        // Check again in case a message arrived before the registration.
+        match self.mailbox.try_recv() {
+            Ok(message) => Poll::Ready(Some(message)),
+            Err(DequeueError::Closed) => Poll::Ready(None),
+            Err(DequeueError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+
+    use {DequeueError, SendError};
+    use bounded::{DropOldest, Reject};
+    use super::Mailbox;
+
+    #[derive(Debug, PartialEq)]
+    enum Message {
+        Work(u32),
+        Shutdown,
+    }
+
+    #[test]
+    fn test_system_priority() {
+        let mailbox = Mailbox::new(2, Reject);
+        mailbox.send(Message::Work(1)).expect("send");
+        mailbox.send(Message::Work(2)).expect("send");
+        assert_eq!(mailbox.send(Message::Work(3)), Err(SendError::Full(Message::Work(3))));
+        // The system lane has room even when the user lane is full.
+        mailbox.send_system(Message::Shutdown).expect("send");
+        assert_eq!(mailbox.try_recv(), Ok(Message::Shutdown));
+        assert_eq!(mailbox.try_recv(), Ok(Message::Work(1)));
+        mailbox.close();
+        assert_eq!(mailbox.send_system(Message::Shutdown), Err(SendError::Closed(Message::Shutdown)));
+        assert_eq!(mailbox.try_recv(), Ok(Message::Work(2)));
+        assert_eq!(mailbox.try_recv(), Err(DequeueError::Closed));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity of a BoundedQueue must be positive")]
+    fn test_zero_capacity() {
+        Mailbox::<Message>::new(0, DropOldest);
+    }
+
+    #[test]
+    fn test_recv_async() {
+        let mailbox = Mailbox::new(2, DropOldest);
+        let mut context = Context::from_waker(Waker::noop());
+        let mut recv = mailbox.recv_async();
+        assert!(Pin::new(&mut recv).poll(&mut context).is_pending());
+
+        for i in 0..3 {
+            mailbox.send(Message::Work(i)).expect("send");
+        }
+        assert_eq!(mailbox.dropped(), 1);
+        assert_eq!(mailbox.stats().full, 1);
+        assert_eq!(Pin::new(&mut recv).poll(&mut context), Poll::Ready(Some(Message::Work(1))));
+
+        mailbox.close();
+        let mut recv = mailbox.recv_async();
+        assert_eq!(Pin::new(&mut recv).poll(&mut context), Poll::Ready(Some(Message::Work(2))));
+        assert_eq!(Pin::new(&mut recv).poll(&mut context), Poll::Ready(None));
+    }
+}
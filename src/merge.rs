@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use Queue;
+
+/// A consumer merging several per-producer queues in the order of a key (e.g. a timestamp),
+/// assuming each queue is already sorted by this key.
+///
+/// At most one element per queue is buffered.
+pub struct MergeByKey<T, F> {
+    queues: Vec<Arc<Queue<T>>>,
+    heads: Vec<Option<T>>,
+    key: F,
+}
+
+impl<T, K: Ord, F: Fn(&T) -> K> MergeByKey<T, F> {
+    pub fn new(queues: Vec<Arc<Queue<T>>>, key: F) -> Self {
+        let heads = queues.iter().map(|_| None).collect();
+        Self {
+            queues,
+            heads,
+            key,
+        }
+    }
+
+    /// Return the element with the smallest key, but only if every queue has an element
+    /// available: otherwise a smaller one could still arrive in an empty queue.
+    pub fn try_next(&mut self) -> Option<T> {
+        self.fill();
+        if self.heads.iter().any(Option::is_none) {
+            return None;
+        }
+        self.take_smallest()
+    }
+
+    /// Return the available element with the smallest key, even if some queues are empty.
+    ///
+    /// This is useful when the empty queues are known to be idle, for instance to flush
+    /// everything at shutdown.
+    pub fn next_available(&mut self) -> Option<T> {
+        self.fill();
+        self.take_smallest()
+    }
+
+    fn fill(&mut self) {
+        for (head, queue) in self.heads.iter_mut().zip(&self.queues) {
+            if head.is_none() {
+                *head = queue.dequeue();
+            }
+        }
+    }
+
+    fn take_smallest(&mut self) -> Option<T> {
+        let key = &self.key;
+        // min_by() returns the first minimum, so the ties are broken by queue index.
+        let index = self.heads.iter()
+            .enumerate()
+            .filter_map(|(index, head)| head.as_ref().map(|head| (index, key(head))))
+            .min_by(|(_, key1), (_, key2)| key1.cmp(key2))
+            .map(|(index, _)| index)?;
+        self.heads[index].take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use Queue;
+    use super::MergeByKey;
+
+    #[test]
+    fn test_merge() {
+        let queues: Vec<_> = (0..3).map(|_| Arc::new(Queue::new())).collect();
+        for &(source, timestamp) in &[(0, 1), (1, 2), (2, 3), (0, 4), (0, 5), (1, 5), (2, 6)] {
+            queues[source].enqueue((timestamp, source)).expect("enqueue");
+        }
+        let mut merge = MergeByKey::new(queues.clone(), |&(timestamp, _)| timestamp);
+        let mut merged = vec![];
+        while let Some(element) = merge.try_next() {
+            merged.push(element);
+        }
+        // Source 0 is empty after 5: the other elements must wait.
+        assert_eq!(merged, [(1, 0), (2, 1), (3, 2), (4, 0), (5, 0)]);
+
+        queues[0].enqueue((8, 0)).expect("enqueue");
+        assert_eq!(merge.try_next(), Some((5, 1)));
+        assert_eq!(merge.try_next(), None);
+        assert_eq!(merge.next_available(), Some((6, 2)));
+        assert_eq!(merge.next_available(), Some((8, 0)));
+        assert_eq!(merge.next_available(), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! A multi-producer single-consumer queue, the shape of an actor mailbox, where the consumer owns
+//! the head of the list.
+//!
+//! This is the algorithm of Dmitry Vyukov: a producer exchanges the tail with its node, then links
+//! the previous tail to it. Only the consumer reads the head, without any atomic read-modify-write,
+//! and it can free the nodes directly since the producers never read them.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::ptr;
+
+use array_queue::CachePadded;
+use sync::atomic::{AtomicPtr, Ordering};
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    /// None in the sentinel.
+    value: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn new(value: Option<T>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            value,
+        }))
+    }
+}
+
+/// An unbounded lock-free MPSC queue. Use `split()` to get the consumer and a producer, which can
+/// be cloned.
+pub struct Queue<T> {
+    /// The sentinel, whose next node holds the first element. Only accessed by the consumer.
+    head: UnsafeCell<*mut Node<T>>,
+    /// The last node, exchanged by the producers.
+    tail: CachePadded<AtomicPtr<Node<T>>>,
+}
+
+// The values are moved in by the producers and out by the unique consumer.
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    pub fn new() -> Self {
+        let sentinel = Node::new(None);
+        Self {
+            head: UnsafeCell::new(sentinel),
+            tail: CachePadded(AtomicPtr::new(sentinel)),
+        }
+    }
+
+    pub fn split(&mut self) -> (Producer<'_, T>, Consumer<'_, T>) {
+        (Producer { queue: self }, Consumer { queue: self })
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while !node.is_null() {
+            let mut boxed = unsafe { Box::from_raw(node) };
+            node = *boxed.next.get_mut();
+        }
+    }
+}
+
+/// A producing end of a `mpsc::Queue`.
+pub struct Producer<'a, T: 'a> {
+    queue: &'a Queue<T>,
+}
+
+impl<'a, T> Clone for Producer<'a, T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue,
+        }
+    }
+}
+
+impl<'a, T> Producer<'a, T> {
+    pub fn enqueue(&self, value: T) {
+        let node = Node::new(Some(value));
+        // AcqRel to order the initialization of our node before the next producer links to it.
+        let previous = self.queue.tail.swap(node, Ordering::AcqRel);
+        // Until this store, the elements enqueued after ours are invisible to the consumer.
+        unsafe { (*previous).next.store(node, Ordering::Release) };
+    }
+}
+
+/// The consuming end of a `mpsc::Queue`.
+pub struct Consumer<'a, T: 'a> {
+    queue: &'a Queue<T>,
+}
+
+impl<'a, T> Consumer<'a, T> {
+    /// Remove the first element, or return `None` if the queue is empty or if the producer of the
+    /// first element is between its two steps.
+    pub fn dequeue(&mut self) -> Option<T> {
+        // The consumer is unique, since `split()` borrows the queue mutably.
+        let head = unsafe { &mut *self.queue.head.get() };
+        let next = unsafe { (**head).next.load(Ordering::Acquire) };
+        if next.is_null() {
+            return None;
+        }
+        unsafe {
+            // The producers are done with the old sentinel once it is linked.
+            drop(Box::from_raw(*head));
+            *head = next;
+            (*next).value.take()
+        }
+    }
+
+    /// Whether no element was enqueued, including by a producer between its two steps.
+    pub fn is_empty(&self) -> bool {
+        let head = unsafe { *self.queue.head.get() };
+        self.queue.tail.load(Ordering::Acquire) == head
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::Queue;
+
+    #[test]
+    fn test_single_thread() {
+        let mut queue = Queue::new();
+        let (producer, mut consumer) = queue.split();
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.dequeue(), None);
+        producer.enqueue(1);
+        producer.clone().enqueue(2);
+        assert!(!consumer.is_empty());
+        assert_eq!(consumer.dequeue(), Some(1));
+        assert_eq!(consumer.dequeue(), Some(2));
+        assert_eq!(consumer.dequeue(), None);
+        assert!(consumer.is_empty());
+
+        // The elements left are dropped with the queue.
+        let value = Arc::new(());
+        let mut queue = Queue::new();
+        queue.split().0.enqueue(value.clone());
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 200 } else { 100_000 };
+        let mut queue = Queue::new();
+        let (producer, mut consumer) = queue.split();
+        thread::scope(|scope| {
+            for thread in 0..4 {
+                let producer = producer.clone();
+                scope.spawn(move || {
+                    for i in 0..count {
+                        producer.enqueue((thread, i));
+                    }
+                });
+            }
+
+            // The elements of each producer are received in order.
+            let mut next = [0; 4];
+            for _ in 0..4 * count {
+                loop {
+                    if let Some((thread, i)) = consumer.dequeue() {
+                        assert_eq!(i, next[thread]);
+                        next[thread] += 1;
+                        break;
+                    }
+                    thread::yield_now();
+                }
+            }
+            assert_eq!(next, [count; 4]);
+        });
+    }
+}
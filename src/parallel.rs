@@ -0,0 +1,147 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+use Queue;
+
+/// Handle on the worker threads started by `consume_parallel()`.
+///
+/// Dropping it without calling `shutdown()` detaches the workers.
+pub struct ParallelConsumers<T> {
+    queue: Arc<Queue<T>>,
+    stopping: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T> ParallelConsumers<T> {
+    /// Let the workers process every element still in the queue, then stop them and wait for
+    /// them to exit.
+    ///
+    /// Returns the payload of the first panic of a worker, if any.
+    pub fn shutdown(mut self) -> Result<(), Box<dyn Any + Send>> {
+        self.stopping.store(true, Ordering::SeqCst);
+        // Wake up the workers blocked on an empty queue.
+        self.queue.notifier.notify();
+        let mut result = Ok(());
+        for worker in self.workers.drain(..) {
+            if let Err(error) = worker.join() {
+                if result.is_ok() {
+                    result = Err(error);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.workers.len()
+    }
+}
+
+/// Spawn `thread_count` threads calling `function` on the elements of the queue, blocking while
+/// it is empty.
+pub fn consume_parallel<T, F>(queue: Arc<Queue<T>>, thread_count: usize, function: F) -> ParallelConsumers<T>
+where T: Send + 'static,
+      F: Fn(T) + Send + Sync + 'static,
+{
+    let function = Arc::new(function);
+    let stopping = Arc::new(AtomicBool::new(false));
+    let workers = (0..thread_count).map(|index| {
+        let queue = queue.clone();
+        let function = function.clone();
+        let stopping = stopping.clone();
+        thread::Builder::new()
+            .name(format!("queue-consumer-{}", index))
+            .spawn(move || {
+                while let Some(value) = queue.dequeue_until(None, || stopping.load(Ordering::SeqCst)) {
+                    function(value);
+                }
+            })
+            .expect("spawn consumer thread")
+    }).collect();
+    ParallelConsumers {
+        queue,
+        stopping,
+        workers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use Queue;
+    use super::consume_parallel;
+
+    #[test]
+    fn test_consume_everything() {
+        let count = if cfg!(miri) { 50 } else { 1_000 };
+        let queue = Arc::new(Queue::new());
+        let results = Arc::new(Mutex::new(vec![]));
+        let consumers = {
+            let results = results.clone();
+            consume_parallel(queue.clone(), 4, move |value| {
+                results.lock().expect("lock").push(value);
+            })
+        };
+        assert_eq!(consumers.thread_count(), 4);
+
+        for i in 0..count {
+            queue.enqueue(i).expect("enqueue");
+        }
+        // Let the workers go to sleep on the empty queue.
+        thread::sleep(Duration::from_millis(50));
+        for i in count..2 * count {
+            queue.enqueue(i).expect("enqueue");
+        }
+        consumers.shutdown().expect("shutdown");
+
+        let mut results = results.lock().expect("lock");
+        results.sort();
+        assert_eq!(*results, (0..2 * count).collect::<Vec<_>>());
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_panicking_worker() {
+        let queue = Arc::new(Queue::new());
+        let consumers = consume_parallel(queue.clone(), 2, |value: i32| {
+            if value == 3 {
+                panic!("invalid value");
+            }
+        });
+        for i in 0..10 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        assert!(consumers.shutdown().is_err());
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let queue = Arc::new(Queue::new());
+        let processed = Arc::new(AtomicUsize::new(0));
+        let consumers = {
+            let processed = processed.clone();
+            consume_parallel(queue.clone(), 2, move |_: i32| {
+                processed.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        queue.pause();
+        for i in 0..100 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(processed.load(Ordering::SeqCst), 0);
+
+        queue.resume();
+        while processed.load(Ordering::SeqCst) < 100 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        consumers.shutdown().expect("shutdown");
+    }
+}
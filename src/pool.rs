@@ -0,0 +1,177 @@
+//! A fixed set of nodes allocated up front, so that a queue can be used where allocating is
+//! forbidden, like interrupt handlers or hot paths.
+//!
+//! The free nodes form a stack linked by their indices, whose head is tagged with a counter
+//! incremented by every change so that a node taken and given back between the load and the
+//! compare-and-swap of another thread is noticed (ABA). The nodes which were never used are
+//! handed out by a bump counter first, so that the pool can be created in a `static`.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use {Node, Queue};
+use free_list::FreeList;
+use reclaim::DefaultReclaim;
+use sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of bits of the head holding the index of the first free node, plus one.
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+/// Source of the nodes of a queue, instead of the allocator.
+pub(crate) trait NodeSource<T> {
+    /// A free node, or null if there is none.
+    fn allocate(&self) -> *mut Node<T>;
+
+    /// Give back a node whose content was already dropped.
+    unsafe fn deallocate(&self, node: *mut Node<T>);
+}
+
+/// `N` nodes for the elements of type `T`, shared by the queues created with
+/// `Queue::with_pool()`.
+///
+/// Each queue takes one node for itself, and one more per element. When they are all in use,
+/// `enqueue()` and `try_enqueue()` return `SendError::PoolExhausted`.
+///
+/// Only enqueuing is free of allocations: creating the queue allocates its shared state, and the
+/// default reclamation strategy allocates a record for each dequeued node that it retires, before
+/// giving the node back to the pool.
+///
+/// ```
+/// # extern crate lock_free_queue;
+/// # use lock_free_queue::{NodePool, Queue};
+/// # fn main() {
+/// static POOL: NodePool<u32, 16> = NodePool::new();
+///
+/// let queue = Queue::with_pool(&POOL);
+/// assert!(queue.try_enqueue(1).is_ok());
+/// # }
+/// ```
+pub struct NodePool<T, const N: usize> {
+    nodes: [UnsafeCell<MaybeUninit<Node<T>>>; N],
+    /// Index of the next free node of each free node, plus one, or 0 for the last one.
+    next: [AtomicUsize; N],
+    /// Index of the first free node plus one (0 if the stack is empty) in the low bits, and the
+    /// tag in the high bits.
+    head: AtomicUsize,
+    /// Number of nodes handed out at least once.
+    used: AtomicUsize,
+}
+
+// The nodes are only accessed by the queue owning them, which can send its elements to other
+// threads.
+unsafe impl<T: Send, const N: usize> Sync for NodePool<T, N> {}
+
+impl<T, const N: usize> NodePool<T, N> {
+    pub const fn new() -> Self {
+        assert!(N < INDEX_MASK, "too many nodes in the pool");
+        Self {
+            nodes: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            next: [const { AtomicUsize::new(0) }; N],
+            head: AtomicUsize::new(0),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of nodes which can still be taken.
+    pub fn available(&self) -> usize {
+        let mut count = N - self.used.load(Ordering::SeqCst);
+        let mut index = self.head.load(Ordering::SeqCst) & INDEX_MASK;
+        // Only exact when the pool is not used concurrently: bounded so that a changing stack
+        // cannot be followed forever.
+        while index != 0 && count < N {
+            count += 1;
+            index = self.next[index - 1].load(Ordering::SeqCst);
+        }
+        count
+    }
+
+    fn node(&self, index: usize) -> *mut Node<T> {
+        self.nodes[index].get().cast()
+    }
+}
+
+impl<T, const N: usize> Default for NodePool<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> NodeSource<T> for NodePool<T, N> {
+    fn allocate(&self) -> *mut Node<T> {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            let index = head & INDEX_MASK;
+            if index == 0 {
+                break;
+            }
+            // The node could be taken by another thread meanwhile, in which case the tag has
+            // changed and the compare-and-swap fails.
+            let next = self.next[index - 1].load(Ordering::Relaxed);
+            let tag = (head >> INDEX_BITS).wrapping_add(1);
+            match self.head.compare_exchange_weak(head, next | tag << INDEX_BITS, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => return self.node(index - 1),
+                Err(current) => head = current,
+            }
+        }
+        match self.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| if used < N { Some(used + 1) } else { None }) {
+            Ok(index) => self.node(index),
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn deallocate(&self, node: *mut Node<T>) {
+        let index = node.offset_from(self.node(0)) as usize;
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            self.next[index].store(head & INDEX_MASK, Ordering::Relaxed);
+            let tag = (head >> INDEX_BITS).wrapping_add(1);
+            // Release the link to the next node, and the drop of the content of the node, to the
+            // thread taking it.
+            match self.head.compare_exchange_weak(head, (index + 1) | tag << INDEX_BITS, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+impl<T: Send + 'static> Queue<T> {
+    /// Create a queue taking its nodes from `pool` instead of allocating them, so that
+    /// `enqueue()` fails when the pool is exhausted.
+    pub fn with_pool<const N: usize>(pool: &'static NodePool<T, N>) -> Self {
+        Self::with_free_list(DefaultReclaim::default(), FreeList::with_pool(pool))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Queue, SendError};
+    use super::NodePool;
+
+    #[test]
+    fn test_exhausted() {
+        static POOL: NodePool<u32, 4> = NodePool::new();
+
+        let queue = Queue::with_pool(&POOL);
+        // The sentinel takes a node.
+        assert_eq!(POOL.available(), 3);
+        for i in 0..3 {
+            assert_eq!(queue.try_enqueue(i), Ok(()));
+        }
+        assert_eq!(queue.try_enqueue(3), Err(SendError::PoolExhausted(3)));
+        assert_eq!(queue.dequeue(), Some(0));
+        assert_eq!(queue.dequeue(), Some(1));
+        // The removed nodes go back to the pool once they are reclaimed.
+        for _ in 0..3 {
+            queue.collect_garbage();
+        }
+        assert_eq!(POOL.available(), 2);
+        assert_eq!(queue.try_enqueue(3), Ok(()));
+        assert_eq!(queue.enqueue(4), Ok(()));
+        assert_eq!(queue.enqueue(5), Err(SendError::PoolExhausted(5)));
+        drop(queue);
+        assert_eq!(POOL.available(), 4);
+    }
+}
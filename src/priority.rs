@@ -0,0 +1,160 @@
+use std::array;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use {Queue, SendError};
+
+/// `N` queues of decreasing priority, the lane 0 being the most urgent.
+///
+/// By default, `dequeue()` scans the lanes from the most urgent to the least, so a busy lane
+/// starves the ones after it. With `with_weights()`, the dequeues instead start their scan at the
+/// lanes in a weighted round-robin, so that each busy lane gets its share of the dequeues.
+pub struct PriorityLanes<T, const N: usize> {
+    lanes: [Queue<T>; N],
+    /// The weight of each lane, or None to always start the scan at the most urgent lane.
+    weights: Option<[usize; N]>,
+    /// Counter of the dequeues, whose position in the round of the weights selects the first
+    /// lane to scan.
+    turn: AtomicUsize,
+}
+
+impl<T, const N: usize> PriorityLanes<T, N> {
+    pub fn new() -> Self {
+        Self {
+            lanes: array::from_fn(|_| Queue::new()),
+            weights: None,
+            turn: AtomicUsize::new(0),
+        }
+    }
+
+    /// Create lanes dequeued in a weighted round-robin: out of every `sum(weights)` dequeues,
+    /// `weights[i]` start with the lane `i`, then go on from the most urgent lane if it is empty.
+    ///
+    /// Panics if every weight is 0.
+    pub fn with_weights(weights: [usize; N]) -> Self {
+        assert!(weights.iter().any(|&weight| weight > 0), "every lane has a weight of 0");
+        let mut lanes = Self::new();
+        lanes.weights = Some(weights);
+        lanes
+    }
+
+    /// Enqueue the element in the lane `priority`, 0 being the most urgent.
+    ///
+    /// Gives the element back if the lane was closed with `lane(priority).close()`.
+    ///
+    /// Panics if `priority` is not lower than `N`.
+    pub fn enqueue(&self, priority: usize, value: T) -> Result<(), SendError<T>> {
+        self.lanes[priority].enqueue(value)
+    }
+
+    /// Dequeue the first element of the most urgent lane which is not empty, or of the lane whose
+    /// turn it is with weights.
+    pub fn dequeue(&self) -> Option<T> {
+        let first = self.first_lane();
+        if let Some(first) = first {
+            if let Some(value) = self.lanes[first].dequeue() {
+                return Some(value);
+            }
+        }
+        self.lanes.iter()
+            .enumerate()
+            .filter(|&(index, _)| Some(index) != first)
+            .find_map(|(_, lane)| lane.dequeue())
+    }
+
+    /// The lane `priority`, to dequeue only its elements for instance.
+    ///
+    /// Panics if `priority` is not lower than `N`.
+    pub fn lane(&self, priority: usize) -> &Queue<T> {
+        &self.lanes[priority]
+    }
+
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(Queue::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(Queue::is_empty)
+    }
+
+    /// The lane whose turn it is in the round-robin, if there are weights.
+    fn first_lane(&self) -> Option<usize> {
+        let weights = self.weights.as_ref()?;
+        let round: usize = weights.iter().sum();
+        let mut position = self.turn.fetch_add(1, Ordering::Relaxed) % round;
+        weights.iter().position(|&weight| {
+            if position < weight {
+                return true;
+            }
+            position -= weight;
+            false
+        })
+    }
+}
+
+impl<T, const N: usize> Default for PriorityLanes<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::PriorityLanes;
+
+    #[test]
+    fn test_strict() {
+        let lanes = PriorityLanes::<_, 3>::new();
+        lanes.enqueue(2, "bulk").expect("enqueue");
+        lanes.enqueue(1, "normal").expect("enqueue");
+        lanes.enqueue(0, "urgent 1").expect("enqueue");
+        lanes.enqueue(0, "urgent 2").expect("enqueue");
+        assert_eq!(lanes.len(), 4);
+        assert_eq!(lanes.dequeue(), Some("urgent 1"));
+        assert_eq!(lanes.dequeue(), Some("urgent 2"));
+        assert_eq!(lanes.lane(2).dequeue(), Some("bulk"));
+        assert_eq!(lanes.dequeue(), Some("normal"));
+        assert_eq!(lanes.dequeue(), None);
+        assert!(lanes.is_empty());
+    }
+
+    #[test]
+    fn test_weights() {
+        let lanes = PriorityLanes::with_weights([3, 1, 0]);
+        for i in 0..8 {
+            lanes.enqueue(0, (0, i)).expect("enqueue");
+            lanes.enqueue(1, (1, i)).expect("enqueue");
+            lanes.enqueue(2, (2, i)).expect("enqueue");
+        }
+        // The lane 1 gets one dequeue out of four while both are busy, and the lane 2 only gets
+        // the dequeues once the others are empty.
+        let first: Vec<_> = (0..8).filter_map(|_| lanes.dequeue()).map(|(lane, _)| lane).collect();
+        assert_eq!(first, [0, 0, 0, 1, 0, 0, 0, 1]);
+        let rest: Vec<_> = (0..16).filter_map(|_| lanes.dequeue()).map(|(lane, _)| lane).collect();
+        assert_eq!(rest, [0, 0, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_multithread() {
+        let lanes = Arc::new(PriorityLanes::<_, 2>::with_weights([2, 1]));
+        let producers: Vec<_> = (0..2).map(|priority| {
+            let lanes = lanes.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    lanes.enqueue(priority, priority * 1000 + i).expect("enqueue");
+                }
+            })
+        }).collect();
+        let mut received = vec![];
+        while received.len() < 2000 {
+            received.extend(lanes.dequeue());
+        }
+        for producer in producers {
+            producer.join().expect("join");
+        }
+        received.sort();
+        assert_eq!(received, (0..2000).collect::<Vec<_>>());
+    }
+}
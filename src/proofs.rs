@@ -0,0 +1,90 @@
+//! Proofs that the operations of a single thread keep the queue memory safe and behave like a
+//! `VecDeque`, for every sequence of at most a few operations:
+//!
+//! cargo kani
+//!
+//! Kani does not model threads, so the interleavings are checked by the loom models of the `sync`
+//! module instead. The proofs use the `Leak` strategy, whose guard needs no thread local.
+
+use std::collections::VecDeque;
+
+use Queue;
+use reclaim::Leak;
+
+/// Number of operations of the sequences.
+const OPERATIONS: usize = 4;
+
+#[kani::proof]
+#[kani::unwind(6)]
+fn check_fifo() {
+    let queue = Queue::with_reclaim(Leak);
+    let mut model = VecDeque::new();
+    for _ in 0..OPERATIONS {
+        if kani::any() {
+            let value: u8 = kani::any();
+            queue.enqueue(value).expect("enqueue");
+            model.push_back(value);
+        }
+        else {
+            assert_eq!(queue.dequeue(), model.pop_front());
+        }
+        assert_eq!(queue.len(), model.len());
+    }
+}
+
+#[kani::proof]
+#[kani::unwind(6)]
+fn check_batch() {
+    let queue = Queue::with_reclaim(Leak);
+    let first: u8 = kani::any();
+    queue.enqueue(first).expect("enqueue");
+    let len: usize = kani::any();
+    kani::assume(len < OPERATIONS);
+    let batch: [u8; OPERATIONS] = kani::any();
+    queue.enqueue_batch(batch[..len].iter().cloned()).expect("enqueue");
+    assert_eq!(queue.len(), len + 1);
+    assert_eq!(queue.dequeue(), Some(first));
+    for &value in &batch[..len] {
+        assert_eq!(queue.dequeue(), Some(value));
+    }
+    assert_eq!(queue.dequeue(), None);
+}
+
+#[kani::proof]
+#[kani::unwind(6)]
+fn check_claim() {
+    let queue = Queue::with_reclaim(Leak);
+    let values: [u8; 2] = kani::any();
+    queue.enqueue(values[0]).expect("enqueue");
+    queue.enqueue(values[1]).expect("enqueue");
+    let claim = queue.claim().expect("claim");
+    assert_eq!(*claim, values[0]);
+    // An aborted claim gives the element back in front of the others.
+    let first = if kani::any() {
+        claim.abort();
+        queue.dequeue()
+    }
+    else {
+        Some(claim.commit())
+    };
+    assert_eq!(first, Some(values[0]));
+    assert_eq!(queue.dequeue(), Some(values[1]));
+    assert!(queue.is_empty());
+}
+
+#[kani::proof]
+#[kani::unwind(6)]
+fn check_reset() {
+    let mut queue = Queue::with_reclaim(Leak);
+    let count: usize = kani::any();
+    kani::assume(count < OPERATIONS);
+    for i in 0..count {
+        queue.enqueue(i).expect("enqueue");
+    }
+    queue.reset();
+    assert!(queue.is_empty());
+    // The recycled nodes hold the next elements.
+    queue.enqueue(count).expect("enqueue");
+    assert_eq!(queue.dequeue(), Some(count));
+    assert_eq!(queue.dequeue(), None);
+}
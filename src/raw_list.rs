@@ -0,0 +1,336 @@
+use alloc::boxed::Box;
+use core::iter::FromIterator;
+use core::sync::atomic::Ordering;
+
+use {Node, Queue};
+use reclaim::Reclaim;
+use sojourn::Stamp;
+
+struct RawNode<T> {
+    value: T,
+    next: Option<Box<RawNode<T>>>,
+}
+
+/// An owned singly linked list of the elements detached from a queue by `into_raw_list()`, in
+/// the order they would have been dequeued.
+pub struct RawList<T> {
+    head: Option<Box<RawNode<T>>>,
+    len: usize,
+}
+
+impl<T> RawList<T> {
+    pub fn new() -> Self {
+        Self {
+            head: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        let next = self.head.take();
+        self.head = Some(Box::new(RawNode {
+            value,
+            next,
+        }));
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.head.take().map(|node| {
+            let node = *node;
+            self.head = node.next;
+            self.len -= 1;
+            node.value
+        })
+    }
+
+    /// Split the list in two at the given index: the returned list contains the elements from
+    /// `at` onwards.
+    pub fn split_off(&mut self, at: usize) -> RawList<T> {
+        assert!(at <= self.len, "split index out of bounds");
+        let mut link = &mut self.head;
+        for _ in 0..at {
+            link = &mut link.as_mut().expect("node").next;
+        }
+        let tail = RawList {
+            head: link.take(),
+            len: self.len - at,
+        };
+        self.len = at;
+        tail
+    }
+
+    /// Reverse the order of the elements.
+    pub fn reverse(&mut self) {
+        let mut reversed = None;
+        let mut current = self.head.take();
+        while let Some(mut node) = current {
+            current = node.next.take();
+            node.next = reversed;
+            reversed = Some(node);
+        }
+        self.head = reversed;
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            next: self.head.as_deref(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            next: self.head.as_deref_mut(),
+        }
+    }
+}
+
+impl<T> Default for RawList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for RawList<T> {
+    fn drop(&mut self) {
+        // Drop the nodes in a loop to avoid a recursion as deep as the list.
+        let mut current = self.head.take();
+        while let Some(mut node) = current {
+            current = node.next.take();
+        }
+    }
+}
+
+impl<T> FromIterator<T> for RawList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = RawList::new();
+        for value in iter {
+            list.push_front(value);
+        }
+        list.reverse();
+        list
+    }
+}
+
+pub struct Iter<'a, T: 'a> {
+    next: Option<&'a RawNode<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.next.map(|node| {
+            self.next = node.next.as_deref();
+            &node.value
+        })
+    }
+}
+
+pub struct IterMut<'a, T: 'a> {
+    next: Option<&'a mut RawNode<T>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<&'a mut T> {
+        self.next.take().map(|node| {
+            self.next = node.next.as_deref_mut();
+            &mut node.value
+        })
+    }
+}
+
+pub struct IntoIter<T> {
+    list: RawList<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.list.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.list.len, Some(self.list.len))
+    }
+}
+
+impl<T> IntoIterator for RawList<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter {
+            list: self,
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a RawList<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Atomically detach every element in the queue, as an owned list.
+    ///
+    /// The head of the queue is moved directly to the last node, so this only takes one
+    /// successful CAS however many elements are detached.
+    ///
+    /// This is how an actor grabs its whole mailbox at once, leaving the queue open to the
+    /// producers:
+    ///
+    /// ```
+    /// # use lock_free_queue::Queue;
+    /// let mailbox = Queue::new();
+    /// mailbox.enqueue("start");
+    /// mailbox.enqueue("stop");
+    /// let messages: Vec<_> = mailbox.into_raw_list().into_iter().collect();
+    /// assert_eq!(messages, ["start", "stop"]);
+    /// assert!(mailbox.is_empty());
+    /// ```
+    pub fn into_raw_list(&self) -> RawList<T> {
+        let mut list = RawList::new();
+        if self.paused.load(Ordering::SeqCst) {
+            return list;
+        }
+        let guard = self.pin();
+        let mut step = 0;
+        loop {
+            // The head is protected so that it cannot be recycled before the CAS (ABA).
+            let head = self.reclaim.protect(&guard, 0, &self.head);
+            let tail = self.reclaim.protect(&guard, 1, &self.tail);
+            unsafe {
+                let true_tail = (*tail).next.load(Ordering::Acquire);
+                if !true_tail.is_null() {
+                    // Make sure the tail is the last node, so that we detach everything.
+                    let _ = self.tail.compare_exchange_weak(tail, true_tail, Ordering::SeqCst, Ordering::Relaxed);
+                    continue;
+                }
+                if head == tail {
+                    break;
+                }
+                if self.head.compare_exchange_weak(head, tail, Ordering::SeqCst, Ordering::Relaxed).is_err() {
+                    self.back_off(&mut step);
+                    continue;
+                }
+                // Like in pop(), we now own the values of all the nodes up to the new sentinel.
+                // The removed nodes could still be read by other threads, so they are retired
+                // instead of being reused in the list.
+                let mut node = head;
+                while node != tail {
+                    let next = (*node).next.load(Ordering::Acquire);
+                    self.retire(&guard, node);
+                    if let Some(value) = Node::take_value(next) {
+                        if let Some(tracker) = (*next).tracker.take() {
+                            tracker.complete();
+                        }
+                        self.record_dequeue((*next).stamp);
+                        list.push_front(value);
+                    }
+                    node = next;
+                }
+                list.reverse();
+                break;
+            }
+        }
+        // The counted elements are dequeued before the ones in the list.
+        for _ in 0..self.take_counted() {
+            self.record_dequeue(Stamp::now());
+            list.push_front(unsafe { Self::recreate() });
+        }
+        self.drained.notify();
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use Queue;
+    use super::RawList;
+
+    #[test]
+    fn test_list() {
+        let mut list: RawList<_> = (0..6).collect();
+        assert_eq!(list.len(), 6);
+        assert_eq!(list.front(), Some(&0));
+        for value in list.iter_mut() {
+            *value *= 10;
+        }
+        let tail = list.split_off(4);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), [0, 10, 20, 30]);
+        assert_eq!(tail.into_iter().collect::<Vec<_>>(), [40, 50]);
+        list.reverse();
+        assert_eq!(list.pop_front(), Some(30));
+        assert_eq!(list.len(), 3);
+        let empty = list.split_off(3);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_into_raw_list() {
+        let queue = Queue::new();
+        assert!(queue.into_raw_list().is_empty());
+        for i in 0..5 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        queue.claim().expect("claim").abort();
+        let list = queue.into_raw_list();
+        assert_eq!(list.into_iter().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert_eq!(queue.dequeue(), None);
+        queue.enqueue(5).expect("enqueue");
+        assert_eq!(queue.dequeue(), Some(5));
+
+        let completion = queue.enqueue_tracked(6).expect("enqueue");
+        assert_eq!(queue.into_raw_list().len(), 1);
+        assert_eq!(completion.try_wait(), Some(true));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_concurrent_detach() {
+        let queue = Arc::new(Queue::new());
+        let producers: Vec<_> = (0..2).map(|thread| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..50_000 {
+                    queue.enqueue(thread * 50_000 + i).expect("enqueue");
+                }
+            })
+        }).collect();
+
+        let mut received = vec![];
+        while received.len() < 100_000 {
+            received.extend(queue.into_raw_list());
+            received.extend(queue.dequeue());
+        }
+        for producer in producers {
+            producer.join().expect("join");
+        }
+        received.sort();
+        assert_eq!(received, (0..100_000).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,147 @@
+use SendError;
+use array_queue::{self, CachePadded, Slot};
+use sync::atomic::AtomicUsize;
+
+/// Maximum number of attempts to reserve a slot before giving up.
+const MAX_ATTEMPTS: usize = 4;
+
+/// A bounded queue for real-time threads, which never allocates nor waits after its creation.
+///
+/// The elements are stored in slots preallocated in a ring, with the algorithm of `ArrayQueue`.
+/// An operation reserves a slot with a compare-and-swap on the position of the producers or the
+/// consumers, and gives up after `MAX_ATTEMPTS` (4) failed reservations, so that its worst-case
+/// cost is bounded: at most 4 compare-and-swaps and 9 loads, plus the move of the element. There
+/// is no helping and no retry loop depending on the other threads. Giving up is reported like a
+/// full queue by `try_enqueue()` and like an empty one by `try_dequeue()`, so a caller on a
+/// deadline-critical path can skip the operation and try again in its next period.
+///
+/// A producer preempted between its reservation and the write of its element makes the next
+/// elements invisible to the consumers until it resumes.
+pub struct RealTimeQueue<T> {
+    slots: Box<[Slot<T>]>,
+    /// Position of the next element to dequeue.
+    head: CachePadded<AtomicUsize>,
+    /// Position of the next element to enqueue.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// The values are only accessed by the thread which reserved their slot.
+unsafe impl<T: Send> Send for RealTimeQueue<T> {}
+unsafe impl<T: Send> Sync for RealTimeQueue<T> {}
+
+impl<T> RealTimeQueue<T> {
+    /// Create a queue holding at least `capacity` elements (rounded up to a power of two).
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        Self {
+            slots: (0..capacity).map(Slot::new).collect(),
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Number of elements in the queue, which is only a snapshot with concurrent operations.
+    pub fn len(&self) -> usize {
+        array_queue::len(&self.slots, &self.head, &self.tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add an element in a bounded number of steps, or give it back if the queue is full or no
+    /// slot could be reserved in `MAX_ATTEMPTS`.
+    pub fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        array_queue::enqueue(&self.slots, &self.tail, value, MAX_ATTEMPTS)
+    }
+
+    /// Remove an element in a bounded number of steps, returning `None` if the queue is empty
+    /// or no slot could be reserved in `MAX_ATTEMPTS`.
+    pub fn try_dequeue(&self) -> Option<T> {
+        array_queue::dequeue(&self.slots, &self.head, MAX_ATTEMPTS)
+    }
+}
+
+impl<T> Drop for RealTimeQueue<T> {
+    fn drop(&mut self) {
+        while self.try_dequeue().is_some() {
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use SendError;
+    use super::RealTimeQueue;
+
+    #[test]
+    fn test_wrap_around() {
+        let queue = RealTimeQueue::new(3);
+        assert_eq!(queue.capacity(), 4);
+        for lap in 0..3 {
+            for i in 0..4 {
+                queue.try_enqueue(lap * 10 + i).expect("enqueue");
+            }
+            assert_eq!(queue.try_enqueue(99), Err(SendError::Full(99)));
+            assert_eq!(queue.len(), 4);
+            for i in 0..4 {
+                assert_eq!(queue.try_dequeue(), Some(lap * 10 + i));
+            }
+            assert_eq!(queue.try_dequeue(), None);
+        }
+
+        // The elements left are dropped with the queue.
+        let queue = RealTimeQueue::new(4);
+        let value = Arc::new(());
+        queue.try_enqueue(value.clone()).expect("enqueue");
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 100 } else { 10_000 };
+        let queue = Arc::new(RealTimeQueue::new(64));
+        let producers: Vec<_> = (0..2).map(|thread| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..count {
+                    let mut value = thread * count + i;
+                    // The real-time thread would do something else before trying again.
+                    while let Err(error) = queue.try_enqueue(value) {
+                        value = error.into_inner();
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect();
+        let consumers: Vec<_> = (0..2).map(|_| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut elements = vec![];
+                while elements.len() < count {
+                    match queue.try_dequeue() {
+                        Some(element) => elements.push(element),
+                        None => thread::yield_now(),
+                    }
+                }
+                elements
+            })
+        }).collect();
+        for producer in producers {
+            producer.join().expect("join");
+        }
+        let mut received: Vec<_> = consumers.into_iter()
+            .flat_map(|consumer| consumer.join().expect("join"))
+            .collect();
+        received.sort();
+        assert_eq!(received, (0..2 * count).collect::<Vec<_>>());
+    }
+}
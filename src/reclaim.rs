@@ -0,0 +1,395 @@
+//! Reclamation of the nodes removed from the queue.
+//!
+//! The removed nodes are recycled, or freed, once no other thread can be reading them. How this
+//! is tracked is chosen per queue with its `Reclaim` strategy:
+//!
+//!  * `Epoch`, the default, uses the epoch-based garbage collector of the `epoch` module.
+//!  * `HazardPointers` uses the `hazard` module, which bounds the garbage even when a thread
+//!    stalls in the middle of an operation, at the cost of publishing each pointer it reads.
+//!  * `Leak` never frees the nodes, which is the cheapest when the queue lives as long as the
+//!    program and only holds a bounded number of elements.
+//!
+//! With the `crossbeam-epoch` feature, the default is `CrossbeamEpoch` instead, so that
+//! applications already pinning crossbeam epochs can share their guards with the queue. Without
+//! the `std` feature, the epochs are not available since they need thread locals, and the default
+//! is `HazardPointers`.
+
+use core::sync::atomic::Ordering;
+
+#[cfg(feature = "crossbeam-epoch")]
+use crossbeam_epoch;
+#[cfg(feature = "crossbeam-epoch")]
+pub use crossbeam_epoch::{Collector, Guard};
+
+use {Node, Queue};
+#[cfg(feature = "crossbeam-epoch")]
+use {Claim, SendError};
+use sync::AtomicPtr;
+use self::hazard::Hazard;
+
+#[cfg(feature = "std")]
+pub mod epoch;
+pub mod hazard;
+
+/// The strategy used by the default queues.
+#[cfg(all(feature = "std", not(feature = "crossbeam-epoch")))]
+pub type DefaultReclaim = Epoch;
+/// The strategy used by the default queues.
+#[cfg(feature = "crossbeam-epoch")]
+pub type DefaultReclaim = CrossbeamEpoch;
+/// The strategy used by the default queues.
+#[cfg(not(feature = "std"))]
+pub type DefaultReclaim = HazardPointers;
+
+/// A strategy deciding when the nodes removed from a queue can be destroyed.
+///
+/// A thread acquires a guard before accessing the queue, and reads the shared pointers it
+/// dereferences through `protect()`.
+pub trait Reclaim {
+    /// Protection of the current thread against the destruction of what it reads.
+    type Guard;
+
+    fn guard(&self) -> Self::Guard;
+
+    /// Load the pointer in `source` and protect it while the guard is alive, or until the slot
+    /// `index` (0 or 1) of the guard protects another pointer.
+    fn protect<P>(&self, guard: &Self::Guard, index: usize, source: &AtomicPtr<P>) -> *mut P;
+
+    /// Call `function`, which destroys `pointer`, once no guard protects it.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must have been removed from the shared memory. The function can be called
+    /// from any thread, after everything it borrows is dropped.
+    unsafe fn retire<P, F: FnOnce()>(&self, guard: &Self::Guard, pointer: *mut P, function: F);
+
+    /// Destroy the retired pointers which are not protected anymore, instead of waiting for the
+    /// strategy to do it periodically.
+    fn collect(&self, guard: &Self::Guard);
+}
+
+/// A strategy whose guard protects everything read while it is alive, not only the pointers
+/// given to `protect()`, which is needed to walk the list.
+///
+/// # Safety
+///
+/// A pointer retired after being read while a guard was alive must not be destroyed before the
+/// guard is dropped.
+pub unsafe trait ProtectsAll: Reclaim {}
+
+/// Never destroy the removed nodes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Leak;
+
+impl Reclaim for Leak {
+    type Guard = ();
+
+    fn guard(&self) {
+    }
+
+    fn protect<P>(&self, _guard: &(), _index: usize, source: &AtomicPtr<P>) -> *mut P {
+        source.load(Ordering::SeqCst)
+    }
+
+    unsafe fn retire<P, F: FnOnce()>(&self, _guard: &(), _pointer: *mut P, _function: F) {
+    }
+
+    fn collect(&self, _guard: &()) {
+    }
+}
+
+unsafe impl ProtectsAll for Leak {}
+
+/// Reclaim the removed nodes with the collector of the `epoch` module.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Epoch;
+
+#[cfg(feature = "std")]
+impl Reclaim for Epoch {
+    type Guard = epoch::Guard;
+
+    fn guard(&self) -> epoch::Guard {
+        epoch::pin()
+    }
+
+    fn protect<P>(&self, _guard: &epoch::Guard, _index: usize, source: &AtomicPtr<P>) -> *mut P {
+        source.load(Ordering::SeqCst)
+    }
+
+    unsafe fn retire<P, F: FnOnce()>(&self, guard: &epoch::Guard, _pointer: *mut P, function: F) {
+        guard.defer_unchecked(function);
+    }
+
+    fn collect(&self, guard: &epoch::Guard) {
+        guard.flush();
+    }
+}
+
+#[cfg(feature = "std")]
+unsafe impl ProtectsAll for Epoch {}
+
+/// Reclaim the removed nodes with the hazard pointers of the `hazard` module.
+///
+/// Each guard takes two of the `hazard::SLOTS` slots of the thread, so a thread can hold at most
+/// four guards at once, counting the ones an operation takes internally.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HazardPointers;
+
+/// The hazards of a guard of `HazardPointers`.
+pub struct HazardGuard {
+    hazards: [Hazard; 2],
+}
+
+impl Reclaim for HazardPointers {
+    type Guard = HazardGuard;
+
+    fn guard(&self) -> HazardGuard {
+        HazardGuard {
+            hazards: [Hazard::new(), Hazard::new()],
+        }
+    }
+
+    fn protect<P>(&self, guard: &HazardGuard, index: usize, source: &AtomicPtr<P>) -> *mut P {
+        guard.hazards[index].protect(source)
+    }
+
+    unsafe fn retire<P, F: FnOnce()>(&self, _guard: &HazardGuard, pointer: *mut P, function: F) {
+        hazard::retire(pointer, function);
+    }
+
+    fn collect(&self, _guard: &HazardGuard) {
+        hazard::flush();
+    }
+}
+
+/// Reclaim the removed nodes with crossbeam's collector.
+#[cfg(feature = "crossbeam-epoch")]
+#[derive(Clone, Debug, Default)]
+pub struct CrossbeamEpoch {
+    /// The collector of the queue, or None for crossbeam's default one.
+    collector: Option<Collector>,
+}
+
+#[cfg(feature = "crossbeam-epoch")]
+impl CrossbeamEpoch {
+    fn collector(&self) -> &Collector {
+        match self.collector {
+            Some(ref collector) => collector,
+            None => crossbeam_epoch::default_collector(),
+        }
+    }
+}
+
+#[cfg(feature = "crossbeam-epoch")]
+impl Reclaim for CrossbeamEpoch {
+    type Guard = Guard;
+
+    /// Operations which don't take a guard register a new participant in a custom collector each
+    /// time, so prefer the `*_with_guard()` methods with such a queue.
+    fn guard(&self) -> Guard {
+        match self.collector {
+            Some(ref collector) => collector.register().pin(),
+            None => crossbeam_epoch::pin(),
+        }
+    }
+
+    fn protect<P>(&self, _guard: &Guard, _index: usize, source: &AtomicPtr<P>) -> *mut P {
+        source.load(Ordering::SeqCst)
+    }
+
+    unsafe fn retire<P, F: FnOnce()>(&self, guard: &Guard, _pointer: *mut P, function: F) {
+        guard.defer_unchecked(function);
+    }
+
+    fn collect(&self, guard: &Guard) {
+        guard.flush();
+    }
+}
+
+#[cfg(feature = "crossbeam-epoch")]
+unsafe impl ProtectsAll for CrossbeamEpoch {}
+
+impl<T, R: Reclaim> Queue<T, R> {
+    pub(crate) fn pin(&self) -> R::Guard {
+        self.reclaim.guard()
+    }
+
+    /// Schedule the recycling of a node removed from the queue, once no guard can access it
+    /// anymore.
+    pub(crate) unsafe fn retire(&self, guard: &R::Guard, node: *mut Node<T>) {
+        // The list is shared so that the nodes can be recycled after the queue is dropped.
+        let free_list = self.free_list.clone();
+        self.reclaim.retire(guard, node, move || free_list.recycle(node));
+    }
+
+    /// Destroy the removed nodes which are not accessed anymore, instead of waiting for the
+    /// reclamation strategy to do it periodically.
+    pub fn collect_garbage(&self) {
+        self.reclaim.collect(&self.pin());
+    }
+}
+
+#[cfg(feature = "crossbeam-epoch")]
+impl<T> Queue<T, CrossbeamEpoch> {
+    /// Create a queue whose removed nodes are reclaimed by `collector` instead of crossbeam's
+    /// default collector.
+    ///
+    /// Operations which don't take a guard register a new participant in the collector each
+    /// time, so prefer the `*_with_guard()` methods with such a queue.
+    pub fn with_collector(collector: Collector) -> Self {
+        Self::with_reclaim(CrossbeamEpoch {
+            collector: Some(collector),
+        })
+    }
+
+    pub fn enqueue_with_guard(&self, value: T, guard: &Guard) -> Result<(), SendError<T>> {
+        self.check_guard(guard);
+        if self.free_list.has_pool() {
+            return self.try_enqueue_guarded(value, guard);
+        }
+        self.enqueue_open(value, |value| {
+            self.enqueue_node(self.new_node(::Node::new(value, None)), guard);
+            Ok(())
+        })
+    }
+
+    pub fn try_enqueue_with_guard(&self, value: T, guard: &Guard) -> Result<(), SendError<T>> {
+        self.check_guard(guard);
+        self.try_enqueue_guarded(value, guard)
+    }
+
+    pub fn dequeue_with_guard(&self, guard: &Guard) -> Option<T> {
+        self.check_guard(guard);
+        self.dequeue_node(guard)
+    }
+
+    /// Claim the first element with the guard of the caller. The claim pins the thread again if
+    /// it is aborted.
+    pub fn claim_with_guard(&self, guard: &Guard) -> Option<Claim<'_, T, CrossbeamEpoch>> {
+        self.check_guard(guard);
+        self.claim_guarded(guard)
+    }
+
+    fn check_guard(&self, guard: &Guard) {
+        assert!(guard.collector() == Some(self.reclaim.collector()), "guard pinned in another collector than the one of the queue");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use Queue;
+    #[cfg(feature = "std")]
+    use super::Epoch;
+    use super::{HazardPointers, Leak, Reclaim};
+
+    /// Exchange elements between threads, with claims given back, on a queue using `reclaim`.
+    fn exchange<R: Reclaim + Send + Sync + 'static>(reclaim: R) {
+        let count = if cfg!(miri) { 100 } else { 10_000 };
+        let queue = Arc::new(Queue::with_reclaim(reclaim));
+        let handles: Vec<_> = (0..4).map(|thread| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut received = vec![];
+                for i in 0..count {
+                    queue.enqueue(thread * count + i).expect("enqueue");
+                    if i % 3 == 0 {
+                        drop(queue.claim());
+                    }
+                    if let Some(element) = queue.dequeue() {
+                        received.push(element);
+                    }
+                }
+                queue.collect_garbage();
+                received
+            })
+        }).collect();
+        let mut received: Vec<_> = handles.into_iter()
+            .flat_map(|handle| handle.join().expect("join"))
+            .collect();
+        received.extend(queue.try_iter());
+        received.sort();
+        assert_eq!(received, (0..4 * count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_strategies() {
+        // Miri reports the nodes leaked on purpose.
+        if !cfg!(miri) {
+            exchange(Leak);
+        }
+        #[cfg(feature = "std")]
+        exchange(Epoch);
+        exchange(HazardPointers);
+    }
+
+    #[test]
+    fn test_hazard_pointers() {
+        // The operations which protect the head and the tail instead of the first node.
+        let queue = Queue::with_reclaim(HazardPointers);
+        queue.enqueue(1).expect("enqueue");
+        let claim = queue.claim().expect("claim");
+        queue.enqueue(2).expect("enqueue");
+        drop(claim);
+        assert_eq!(queue.into_raw_list().into_iter().collect::<Vec<_>>(), [1, 2]);
+        assert!(queue.is_drained());
+    }
+
+    #[test]
+    #[cfg(feature = "crossbeam-epoch")]
+    fn test_default_collector() {
+        let queue = Queue::new();
+        let guard = ::crossbeam_epoch::pin();
+        queue.enqueue_with_guard(1, &guard).expect("enqueue");
+        queue.enqueue(2).expect("enqueue");
+        assert_eq!(queue.dequeue_with_guard(&guard), Some(1));
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue_with_guard(&guard), None);
+
+        queue.close();
+        assert!(queue.try_enqueue_with_guard(3, &guard).is_err());
+        assert!(queue.enqueue_with_guard(4, &guard).is_err());
+        queue.reopen();
+        queue.enqueue_with_guard(4, &guard).expect("enqueue");
+        drop(queue.claim_with_guard(&guard));
+        assert_eq!(queue.claim_with_guard(&guard).map(|claim| claim.commit()), Some(4));
+    }
+
+    #[test]
+    #[cfg(feature = "crossbeam-epoch")]
+    #[cfg_attr(miri, ignore)]
+    fn test_custom_collector() {
+        let collector = ::crossbeam_epoch::Collector::new();
+        let queue = Arc::new(Queue::with_collector(collector.clone()));
+        let handles: Vec<_> = (0..4).map(|thread| {
+            let queue = queue.clone();
+            let collector = collector.clone();
+            thread::spawn(move || {
+                let handle = collector.register();
+                let mut received = 0;
+                for i in 0..10_000 {
+                    let guard = handle.pin();
+                    queue.enqueue_with_guard(thread * 10_000 + i, &guard).expect("enqueue");
+                    if queue.dequeue_with_guard(&guard).is_some() {
+                        received += 1;
+                    }
+                }
+                received
+            })
+        }).collect();
+        let received: usize = handles.into_iter().map(|handle| handle.join().expect("join")).sum();
+        assert_eq!(received, 40_000);
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "crossbeam-epoch")]
+    #[should_panic(expected = "another collector")]
+    fn test_foreign_guard() {
+        let queue = Queue::with_collector(::crossbeam_epoch::Collector::new());
+        queue.enqueue_with_guard(1, &::crossbeam_epoch::pin()).expect("enqueue");
+    }
+}
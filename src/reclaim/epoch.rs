@@ -0,0 +1,334 @@
+//! An epoch-based garbage collector, used to reclaim the removed nodes without the
+//! `crossbeam-epoch` feature.
+//!
+//! A thread pins the current global epoch while it accesses the queue. A removed node is tagged
+//! with the global epoch of its removal, and destroyed once the global epoch advanced twice since:
+//! the epoch only advances when every pinned thread observed the current one, so no thread which
+//! could have read the node before its removal is still pinned by then.
+//!
+//! Each thread has its own participant, registered on first use and reused by another thread
+//! once it exits, and keeps its own garbage. The garbage left by exiting threads is collected by
+//! the others.
+
+use std::cell::{Cell, UnsafeCell};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// Number of pins after which a thread tries to advance the epoch and collect its garbage.
+const PINS_BETWEEN_COLLECTIONS: usize = 128;
+
+/// Number of deferred functions between the attempts of a thread to collect its garbage.
+const MAX_GARBAGE: usize = 64;
+
+/// Epoch of an unpinned participant. The epochs of pinned participants are stored shifted by one
+/// bit, with the lowest bit set.
+const UNPINNED: usize = 0;
+
+static GLOBAL_EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// The participants, in a list which only grows: they are reused instead of being removed.
+static PARTICIPANTS: AtomicPtr<Participant> = AtomicPtr::new(ptr::null_mut());
+
+/// The garbage of the threads which exited before it could be collected.
+static ORPHANS: Mutex<Vec<Deferred>> = Mutex::new(Vec::new());
+
+/// A function called once no pinned thread can access what it destroys.
+struct Deferred {
+    epoch: usize,
+    function: Box<dyn FnOnce()>,
+}
+
+// The functions are only given by Guard::defer_unchecked(), whose caller guarantees that they can
+// be called from any thread.
+unsafe impl Send for Deferred {}
+
+struct Participant {
+    /// Pinned epoch, or UNPINNED.
+    epoch: AtomicUsize,
+    in_use: AtomicBool,
+    next: *mut Participant,
+    /// The fields below are only accessed by the thread using the participant.
+    guards: Cell<usize>,
+    pins: Cell<usize>,
+    /// In the order of the epochs the functions were deferred in.
+    garbage: UnsafeCell<VecDeque<Deferred>>,
+}
+
+// Only the atomics are accessed by the other threads.
+unsafe impl Sync for Participant {}
+
+impl Participant {
+    /// Take an unused participant, or register a new one.
+    fn acquire() -> &'static Participant {
+        let mut current = PARTICIPANTS.load(Ordering::SeqCst);
+        while let Some(participant) = unsafe { current.as_ref() } {
+            if participant.in_use.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return participant;
+            }
+            current = participant.next;
+        }
+        let participant = Box::into_raw(Box::new(Participant {
+            epoch: AtomicUsize::new(UNPINNED),
+            in_use: AtomicBool::new(true),
+            next: ptr::null_mut(),
+            guards: Cell::new(0),
+            pins: Cell::new(0),
+            garbage: UnsafeCell::new(VecDeque::new()),
+        }));
+        loop {
+            let head = PARTICIPANTS.load(Ordering::SeqCst);
+            unsafe { (*participant).next = head };
+            if PARTICIPANTS.compare_exchange(head, participant, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return unsafe { &*participant };
+            }
+        }
+    }
+
+    /// Give the participant back, leaving its garbage to the other threads.
+    fn release(&self) {
+        let garbage = mem::take(unsafe { &mut *self.garbage.get() });
+        lock_orphans().extend(garbage);
+        self.in_use.store(false, Ordering::SeqCst);
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn garbage(&self) -> &mut VecDeque<Deferred> {
+        // Only the thread using the participant calls this, and never while it holds another
+        // reference to the garbage: the deferred functions are called after taking them out, and
+        // can defer other functions.
+        unsafe { &mut *self.garbage.get() }
+    }
+
+    /// Advance the global epoch if every pinned participant observed it, and call the deferred
+    /// functions which became safe.
+    fn collect(&self) {
+        try_advance();
+        let epoch = GLOBAL_EPOCH.load(Ordering::SeqCst);
+        // Only the oldest functions are looked at, so that a thread which cannot advance the
+        // epoch does not go through all its garbage each time.
+        while let Some(deferred) = self.pop_expired(epoch) {
+            (deferred.function)();
+        }
+
+        let ready = {
+            let mut orphans = lock_orphans();
+            let (ready, pending) = mem::take(&mut *orphans).into_iter()
+                .partition(|deferred| is_expired(deferred, epoch));
+            *orphans = pending;
+            ready
+        };
+        call(ready);
+    }
+
+    fn pop_expired(&self, epoch: usize) -> Option<Deferred> {
+        let garbage = self.garbage();
+        if garbage.front().is_some_and(|deferred| is_expired(deferred, epoch)) {
+            garbage.pop_front()
+        }
+        else {
+            None
+        }
+    }
+}
+
+fn call(garbage: Vec<Deferred>) {
+    for deferred in garbage {
+        (deferred.function)();
+    }
+}
+
+fn is_expired(deferred: &Deferred, epoch: usize) -> bool {
+    // The functions deferred by other threads after `epoch` was loaded have a later epoch.
+    epoch.wrapping_sub(deferred.epoch) as isize >= 2
+}
+
+fn lock_orphans() -> MutexGuard<'static, Vec<Deferred>> {
+    ORPHANS.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+fn pinned_epoch(epoch: usize) -> usize {
+    epoch << 1 | 1
+}
+
+fn try_advance() {
+    let epoch = GLOBAL_EPOCH.load(Ordering::SeqCst);
+    let mut current = PARTICIPANTS.load(Ordering::SeqCst);
+    while let Some(participant) = unsafe { current.as_ref() } {
+        let pinned = participant.epoch.load(Ordering::SeqCst);
+        if pinned != UNPINNED && pinned != pinned_epoch(epoch) {
+            return;
+        }
+        current = participant.next;
+    }
+    // Another thread could have advanced it in the meantime.
+    let _ = GLOBAL_EPOCH.compare_exchange(epoch, epoch.wrapping_add(1), Ordering::SeqCst, Ordering::SeqCst);
+}
+
+/// The participant of a thread, given back when it exits.
+struct Handle {
+    participant: &'static Participant,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.participant.release();
+    }
+}
+
+thread_local! {
+    static HANDLE: Handle = Handle {
+        participant: Participant::acquire(),
+    };
+}
+
+/// Protection of the current thread against the reclamation of what it reads, until it is
+/// dropped.
+pub struct Guard {
+    participant: &'static Participant,
+    /// Whether the participant was acquired only for this guard, because the thread is exiting.
+    temporary: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Pin the current thread.
+pub fn pin() -> Guard {
+    let (participant, temporary) =
+        match HANDLE.try_with(|handle| handle.participant) {
+            Ok(participant) => (participant, false),
+            // The thread local is destroyed while the thread exits.
+            Err(_) => (Participant::acquire(), true),
+        };
+    let guards = participant.guards.get();
+    participant.guards.set(guards + 1);
+    if guards == 0 {
+        let epoch = GLOBAL_EPOCH.load(Ordering::SeqCst);
+        // SeqCst so that the epoch is published before the shared memory is read.
+        participant.epoch.store(pinned_epoch(epoch), Ordering::SeqCst);
+        let pins = participant.pins.get().wrapping_add(1);
+        participant.pins.set(pins);
+        if pins.is_multiple_of(PINS_BETWEEN_COLLECTIONS) {
+            participant.collect();
+        }
+    }
+    Guard {
+        participant,
+        temporary,
+        _not_send: PhantomData,
+    }
+}
+
+impl Guard {
+    /// Call `function` once no thread pinned now can still be pinned.
+    ///
+    /// # Safety
+    ///
+    /// The function can be called from any thread, after everything it borrows is dropped: it
+    /// must only destroy memory which is no longer reachable.
+    pub unsafe fn defer_unchecked<F: FnOnce()>(&self, function: F) {
+        let function: Box<dyn FnOnce() + '_> = Box::new(function);
+        let garbage = self.participant.garbage();
+        garbage.push_back(Deferred {
+            epoch: GLOBAL_EPOCH.load(Ordering::SeqCst),
+            function: mem::transmute::<Box<dyn FnOnce() + '_>, Box<dyn FnOnce()>>(function),
+        });
+        if garbage.len().is_multiple_of(MAX_GARBAGE) {
+            self.participant.collect();
+        }
+    }
+
+    /// Try to advance the epoch and call the deferred functions which are ready.
+    pub fn flush(&self) {
+        self.participant.collect();
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let guards = self.participant.guards.get() - 1;
+        self.participant.guards.set(guards);
+        if guards == 0 {
+            self.participant.epoch.store(UNPINNED, Ordering::SeqCst);
+            if self.temporary {
+                self.participant.release();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::pin;
+
+    fn defer_count(count: &Arc<AtomicUsize>) {
+        let count = count.clone();
+        let guard = pin();
+        unsafe { guard.defer_unchecked(move || { count.fetch_add(1, Ordering::SeqCst); }) };
+    }
+
+    /// Flush until the deferred functions ran, or give up.
+    fn flush_until(count: &AtomicUsize, expected: usize) -> bool {
+        for _ in 0..100 {
+            pin().flush();
+            if count.load(Ordering::SeqCst) == expected {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_defer() {
+        let count = Arc::new(AtomicUsize::new(0));
+        {
+            let guard = pin();
+            defer_count(&count);
+            // Still pinned by the outer guard.
+            guard.flush();
+            guard.flush();
+            assert_eq!(count.load(Ordering::SeqCst), 0);
+        }
+        assert!(flush_until(&count, 1));
+    }
+
+    #[test]
+    fn test_pinned_thread() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let pinned = Arc::new(Barrier::new(2));
+        let release = Arc::new(Barrier::new(2));
+        let handle = {
+            let pinned = pinned.clone();
+            let release = release.clone();
+            thread::spawn(move || {
+                let _guard = pin();
+                pinned.wait();
+                release.wait();
+            })
+        };
+        pinned.wait();
+        defer_count(&count);
+        // The other thread blocks the epoch.
+        assert!(!flush_until(&count, 1));
+        release.wait();
+        handle.join().expect("join");
+        assert!(flush_until(&count, 1));
+    }
+
+    #[test]
+    fn test_orphans() {
+        let count = Arc::new(AtomicUsize::new(0));
+        {
+            let count = count.clone();
+            thread::spawn(move || defer_count(&count)).join().expect("join");
+        }
+        // The garbage of the exited thread is collected by this one.
+        assert!(flush_until(&count, 1));
+    }
+}
@@ -0,0 +1,409 @@
+//! Hazard pointers, an alternative to the epoch-based collector which bounds the garbage even
+//! when a thread stalls.
+//!
+//! A thread publishes the pointers it is about to dereference in its hazard slots. A removed
+//! pointer is retired in the list of the current thread, which is scanned once it is long enough:
+//! every pointer which is not in a slot at that time is destroyed. A stalled thread can thus only
+//! keep alive the few pointers it protects, instead of every pointer retired since it was pinned.
+//!
+//! Without the `std` feature, there are no thread locals: each hazard takes a record of its own,
+//! and the pointers are retired in the list shared by all the threads.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr;
+
+use sync;
+#[cfg(not(feature = "std"))]
+use sync::atomic::AtomicUsize;
+use sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+/// Number of hazard slots of a thread.
+pub const SLOTS: usize = 8;
+
+/// Number of retired pointers after which a thread scans the hazard slots.
+const SCAN_THRESHOLD: usize = 64;
+
+/// The records, in a list which only grows: they are reused instead of being removed.
+static RECORDS: AtomicPtr<Record> = AtomicPtr::new(ptr::null_mut());
+
+/// The pointers retired by the threads which exited before they could be destroyed, in a stack
+/// which is only emptied all at once, so that it needs no lock.
+static ORPHANS: AtomicPtr<Orphan> = AtomicPtr::new(ptr::null_mut());
+
+/// Number of pointers retired since the orphans were last scanned, without the `std` feature.
+#[cfg(not(feature = "std"))]
+static ORPHANED: AtomicUsize = AtomicUsize::new(0);
+
+/// A pointer waiting for no slot to protect it, and the function destroying it.
+struct Retired {
+    pointer: *mut u8,
+    function: Box<dyn FnOnce()>,
+}
+
+// The functions are only given by retire(), whose caller guarantees that they can be called from
+// any thread.
+unsafe impl Send for Retired {}
+
+struct Orphan {
+    retired: Retired,
+    next: *mut Orphan,
+}
+
+/// Push retired pointers for any thread to destroy.
+fn adopt<I: IntoIterator<Item = Retired>>(retired: I) {
+    for retired in retired {
+        let orphan = Box::into_raw(Box::new(Orphan {
+            retired,
+            next: ptr::null_mut(),
+        }));
+        loop {
+            let head = ORPHANS.load(Ordering::SeqCst);
+            unsafe { (*orphan).next = head };
+            if ORPHANS.compare_exchange(head, orphan, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+/// Take every orphan, to destroy them.
+fn take_orphans() -> Vec<Retired> {
+    let mut orphans = Vec::new();
+    let mut current = ORPHANS.swap(ptr::null_mut(), Ordering::SeqCst);
+    while !current.is_null() {
+        let orphan = unsafe { Box::from_raw(current) };
+        current = orphan.next;
+        orphans.push(orphan.retired);
+    }
+    orphans
+}
+
+/// Destroy the orphans which are not protected anymore.
+fn scan_orphans() {
+    let pending = destroy_unprotected(take_orphans());
+    adopt(pending);
+}
+
+/// The hazard slots of a thread.
+struct Record {
+    slots: [AtomicPtr<u8>; SLOTS],
+    in_use: AtomicBool,
+    next: *mut Record,
+}
+
+// Only the atomics are accessed by the other threads.
+unsafe impl Sync for Record {}
+
+impl Record {
+    /// Take an unused record, or register a new one.
+    fn acquire() -> &'static Record {
+        let mut current = RECORDS.load(Ordering::SeqCst);
+        while let Some(record) = unsafe { current.as_ref() } {
+            if record.in_use.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return record;
+            }
+            current = record.next;
+        }
+        let record = Box::into_raw(Box::new(Record {
+            slots: [const { AtomicPtr::new(ptr::null_mut()) }; SLOTS],
+            in_use: AtomicBool::new(true),
+            next: ptr::null_mut(),
+        }));
+        loop {
+            let head = RECORDS.load(Ordering::SeqCst);
+            unsafe { (*record).next = head };
+            if RECORDS.compare_exchange(head, record, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return unsafe { &*record };
+            }
+        }
+    }
+
+    fn release(&self) {
+        self.in_use.store(false, Ordering::SeqCst);
+    }
+}
+
+/// The record of a thread, with the slots in use and the pointers it retired.
+#[cfg(feature = "std")]
+struct Handle {
+    record: &'static Record,
+    /// Bit mask of the slots in use.
+    used: Cell<usize>,
+    retired: UnsafeCell<Vec<Retired>>,
+}
+
+#[cfg(feature = "std")]
+impl Handle {
+    /// Destroy the retired pointers which are not protected, including the ones of the threads
+    /// which exited.
+    fn scan(&self) {
+        // Only this thread accesses its list, and the functions are called after taking it out.
+        let retired = mem::take(unsafe { &mut *self.retired.get() });
+        let pending = destroy_unprotected(retired);
+        unsafe { (*self.retired.get()).extend(pending) };
+        scan_orphans();
+    }
+
+    fn retire(&self, retired: Retired) {
+        let len = {
+            let list = unsafe { &mut *self.retired.get() };
+            list.push(retired);
+            list.len()
+        };
+        if len >= SCAN_THRESHOLD {
+            self.scan();
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.scan();
+        adopt(mem::take(self.retired.get_mut()));
+        self.record.release();
+    }
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+    static HANDLE: Handle = Handle {
+        record: Record::acquire(),
+        used: Cell::new(0),
+        retired: UnsafeCell::new(Vec::new()),
+    };
+}
+
+/// Call the functions of the pointers which are in no slot, and return the others.
+fn destroy_unprotected(retired: Vec<Retired>) -> Vec<Retired> {
+    if retired.is_empty() {
+        return retired;
+    }
+    // SeqCst so that a slot set before the pointer was removed is seen.
+    let mut protected = Vec::new();
+    let mut current = RECORDS.load(Ordering::SeqCst);
+    while let Some(record) = unsafe { current.as_ref() } {
+        protected.extend(record.slots.iter()
+            .map(|slot| slot.load(Ordering::SeqCst))
+            .filter(|pointer| !pointer.is_null()));
+        current = record.next;
+    }
+    protected.sort();
+    let (pending, ready): (Vec<_>, Vec<_>) = retired.into_iter()
+        .partition(|retired| protected.binary_search(&retired.pointer).is_ok());
+    for retired in ready {
+        (retired.function)();
+    }
+    pending
+}
+
+/// A hazard slot of the current thread, protecting the pointer it holds from being destroyed.
+pub struct Hazard {
+    record: &'static Record,
+    index: usize,
+    /// Whether the record was acquired only for this hazard, because the thread is exiting.
+    temporary: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Hazard {
+    /// Take a free slot of the current thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the thread already holds `SLOTS` hazards.
+    pub fn new() -> Self {
+        #[cfg(feature = "std")]
+        let slot = HANDLE.try_with(|handle| {
+            let used = handle.used.get();
+            let index = (!used).trailing_zeros() as usize;
+            assert!(index < SLOTS, "more than {} hazard pointers held by a thread", SLOTS);
+            handle.used.set(used | 1 << index);
+            (handle.record, index)
+        }).ok();
+        #[cfg(not(feature = "std"))]
+        let slot = None;
+        let (record, index, temporary) =
+            match slot {
+                Some((record, index)) => (record, index, false),
+                // The thread local is destroyed while the thread exits, or does not exist.
+                None => (Record::acquire(), 0, true),
+            };
+        Self {
+            record,
+            index,
+            temporary,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Load the pointer in `source` and protect it until the hazard protects another pointer or
+    /// is reset or dropped.
+    ///
+    /// The returned pointer can be dereferenced as long as it is protected, if it was only
+    /// retired after being removed from `source`.
+    pub fn protect<T>(&self, source: &sync::AtomicPtr<T>) -> *mut T {
+        let mut pointer = source.load(Ordering::SeqCst);
+        loop {
+            self.slot().store(pointer.cast(), Ordering::SeqCst);
+            // The pointer could have been removed and retired before it was published.
+            let current = source.load(Ordering::SeqCst);
+            if current == pointer {
+                return pointer;
+            }
+            pointer = current;
+        }
+    }
+
+    /// Stop protecting the pointer.
+    pub fn reset(&self) {
+        self.slot().store(ptr::null_mut(), Ordering::SeqCst);
+    }
+
+    fn slot(&self) -> &AtomicPtr<u8> {
+        &self.record.slots[self.index]
+    }
+}
+
+impl Default for Hazard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Hazard {
+    fn drop(&mut self) {
+        self.reset();
+        if self.temporary {
+            self.record.release();
+        }
+        else {
+            #[cfg(feature = "std")]
+            let _ = HANDLE.try_with(|handle| handle.used.set(handle.used.get() & !(1 << self.index)));
+        }
+    }
+}
+
+/// Call `function` once `pointer` is protected by no hazard.
+///
+/// # Safety
+///
+/// The pointer must have been removed from the shared memory, so that no hazard can protect it
+/// anymore. The function can be called from any thread, after everything it borrows is dropped.
+pub unsafe fn retire<T, F: FnOnce()>(pointer: *mut T, function: F) {
+    let function: Box<dyn FnOnce() + '_> = Box::new(function);
+    let retired = Retired {
+        pointer: pointer.cast(),
+        function: mem::transmute::<Box<dyn FnOnce() + '_>, Box<dyn FnOnce()>>(function),
+    };
+    #[cfg(feature = "std")]
+    let retired = {
+        let mut retired = Some(retired);
+        if HANDLE.try_with(|handle| handle.retire(retired.take().expect("retired pointer"))).is_ok() {
+            return;
+        }
+        retired.expect("retired pointer")
+    };
+    // The thread is exiting, or there are no thread locals: let any thread destroy it.
+    adopt(Some(retired));
+    #[cfg(not(feature = "std"))]
+    {
+        if ORPHANED.fetch_add(1, Ordering::SeqCst) + 1 >= SCAN_THRESHOLD {
+            ORPHANED.store(0, Ordering::SeqCst);
+            scan_orphans();
+        }
+    }
+}
+
+/// Destroy the retired pointers of the current thread which are not protected anymore.
+#[cfg(feature = "std")]
+pub fn flush() {
+    let _ = HANDLE.try_with(Handle::scan);
+}
+
+/// Destroy the retired pointers which are not protected anymore.
+#[cfg(not(feature = "std"))]
+pub fn flush() {
+    scan_orphans();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use sync::AtomicPtr;
+    use super::{Hazard, flush, retire};
+
+    fn retire_count(pointer: *mut u32, count: &Arc<AtomicUsize>) {
+        let count = count.clone();
+        unsafe {
+            retire(pointer, move || {
+                drop(Box::from_raw(pointer));
+                count.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+    }
+
+    #[test]
+    fn test_protect() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let shared = AtomicPtr::new(Box::into_raw(Box::new(1)));
+        let hazard = Hazard::new();
+        let pointer = hazard.protect(&shared);
+        shared.store(Box::into_raw(Box::new(2)), Ordering::SeqCst);
+        retire_count(pointer, &count);
+        flush();
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        assert_eq!(unsafe { *pointer }, 1);
+
+        hazard.reset();
+        flush();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        retire_count(shared.load(Ordering::SeqCst), &count);
+        flush();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_stalled_thread() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let shared = Arc::new(AtomicPtr::new(Box::into_raw(Box::new(0))));
+        let hazard = Hazard::new();
+        let protected = hazard.protect(&shared);
+        // Unlike with epochs, the thread holding a hazard only keeps its own pointer alive.
+        {
+            let shared = shared.clone();
+            let count = count.clone();
+            thread::spawn(move || {
+                for i in 1..=1_000 {
+                    let old = shared.swap(Box::into_raw(Box::new(i)), Ordering::SeqCst);
+                    retire_count(old, &count);
+                }
+                flush();
+            }).join().expect("join");
+        }
+        // The pointers left by the exited thread are destroyed by this one.
+        flush();
+        assert_eq!(count.load(Ordering::SeqCst), 999);
+        assert_eq!(unsafe { *protected }, 0);
+
+        drop(hazard);
+        retire_count(shared.load(Ordering::SeqCst), &count);
+        flush();
+        assert_eq!(count.load(Ordering::SeqCst), 1_001);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    #[should_panic(expected = "more than 8 hazard pointers")]
+    fn test_too_many_hazards() {
+        let _hazards: Vec<_> = (0..9).map(|_| Hazard::new()).collect();
+    }
+}
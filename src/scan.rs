@@ -0,0 +1,232 @@
+use core::fmt;
+use core::sync::atomic::Ordering;
+
+use {Node, Queue};
+use reclaim::{DefaultReclaim, ProtectsAll, Reclaim};
+
+impl<T: Clone + Sync, R: ProtectsAll> Queue<T, R> {
+    /// Return whether an element of the queue matches the predicate, for instance to check if a
+    /// job is already queued.
+    ///
+    /// The result is only weakly consistent: the elements enqueued or dequeued during the scan
+    /// may or may not be seen. Each element is cloned before being tested: a consumer removing
+    /// it only waits for the clone, and the predicate can use the queue.
+    pub fn any<F: Fn(&T) -> bool>(&self, predicate: F) -> bool {
+        self.position_of(predicate).is_some()
+    }
+
+    /// Return the number of elements in front of the first one matching the predicate, for
+    /// instance to tell a user how far their job is in line.
+    ///
+    /// Like `any()`, this is only approximate when the queue is modified during the scan.
+    pub fn position_of<F: Fn(&T) -> bool>(&self, predicate: F) -> Option<usize> {
+        let _guard = self.pin();
+        // SeqCst so that the head is read after the guard is published. The nodes reached from it
+        // stay protected, so the next loads only need to acquire their values.
+        let head = self.head.load(Ordering::SeqCst);
+        // The head is a sentinel, or a node whose value is being taken.
+        let mut node = unsafe { (*head).next.load(Ordering::Acquire) };
+        let mut position = 0;
+        // The counted elements are dequeued before the ones in the list.
+        let counted = self.counted();
+        if counted > 0 {
+            if predicate(unsafe { Self::counted_ref() }) {
+                return Some(0);
+            }
+            position = counted;
+        }
+        while !node.is_null() {
+            unsafe {
+                match Node::clone_value(node).map(|value| predicate(&value)) {
+                    Some(true) => return Some(position),
+                    Some(false) => position += 1,
+                    // A sentinel, or an element being dequeued.
+                    None => (),
+                }
+                node = (*node).next.load(Ordering::Acquire);
+            }
+        }
+        None
+    }
+
+    /// Call `function` on the first element without removing it, for instance to decide whether
+    /// to dequeue it.
+    ///
+    /// Another consumer can dequeue the element in the meantime. The function is called on a
+    /// clone, so it does not delay the consumers and can dequeue the element itself.
+    pub fn peek_with<U, F: FnOnce(&T) -> U>(&self, function: F) -> Option<U> {
+        self.peek().map(|value| {
+            fail_point!("scan.cloned");
+            function(&value)
+        })
+    }
+
+    /// Clone the first element, see `peek_with()`.
+    pub fn peek(&self) -> Option<T> {
+        // The counted elements are dequeued before the ones in the list.
+        if self.counted() > 0 {
+            return Some(unsafe { Self::counted_ref() }.clone());
+        }
+        let _guard = self.pin();
+        let head = self.head.load(Ordering::SeqCst);
+        let mut node = unsafe { (*head).next.load(Ordering::Acquire) };
+        while !node.is_null() {
+            unsafe {
+                // Skip the sentinels and the elements being dequeued.
+                if let Some(value) = Node::clone_value(node) {
+                    return Some(value);
+                }
+                node = (*node).next.load(Ordering::Acquire);
+            }
+        }
+        None
+    }
+
+    /// Format the first `count` elements, for instance to log what a stuck pipeline is holding.
+    ///
+    /// Like `any()`, the elements are cloned under a guard and only weakly consistent.
+    pub fn debug_front(&self, count: usize) -> DebugFront<'_, T, R> {
+        DebugFront {
+            queue: self,
+            count,
+        }
+    }
+}
+
+/// Formats the first elements of a queue as a list.
+///
+/// Returned by `Queue::debug_front()`.
+pub struct DebugFront<'a, T: 'a, R: 'a + Reclaim = DefaultReclaim> {
+    queue: &'a Queue<T, R>,
+    count: usize,
+}
+
+impl<'a, T: Clone + fmt::Debug + Sync, R: ProtectsAll> fmt::Debug for DebugFront<'a, T, R> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let mut list = formatter.debug_list();
+        // The counted elements are dequeued before the ones in the list.
+        let counted = self.queue.counted().min(self.count);
+        for _ in 0..counted {
+            list.entry(unsafe { Queue::<T, R>::counted_ref() });
+        }
+        let mut remaining = self.count - counted;
+        let _guard = self.queue.pin();
+        let head = self.queue.head.load(Ordering::SeqCst);
+        let mut node = unsafe { (*head).next.load(Ordering::Acquire) };
+        while !node.is_null() && remaining > 0 {
+            unsafe {
+                // Skip the sentinels and the elements being dequeued.
+                if let Some(value) = Node::clone_value(node) {
+                    list.entry(&value);
+                    remaining -= 1;
+                }
+                node = (*node).next.load(Ordering::Acquire);
+            }
+        }
+        list.finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use Queue;
+
+    #[test]
+    fn test_any() {
+        let queue = Queue::new();
+        assert!(!queue.any(|_| true));
+        for i in 0..10 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        assert!(queue.any(|&element| element == 5));
+        assert!(!queue.any(|&element| element == 10));
+        while queue.dequeue() != Some(5) {
+        }
+        assert!(!queue.any(|&element| element == 5));
+
+        // The elements given back by claims are still found.
+        drop(queue.claim());
+        assert!(queue.any(|&element| element == 6));
+    }
+
+    #[test]
+    fn test_position_of() {
+        let queue = Queue::new();
+        for i in 0..10 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        assert_eq!(queue.position_of(|&element| element == 0), Some(0));
+        assert_eq!(queue.position_of(|&element| element % 4 == 3), Some(3));
+        assert_eq!(queue.position_of(|&element| element == 10), None);
+        queue.dequeue();
+        let claim = queue.claim().expect("claim");
+        assert_eq!(queue.position_of(|&element| element == 5), Some(3));
+        claim.abort();
+        // The sentinel added by the abort is not counted.
+        assert_eq!(queue.position_of(|&element| element == 5), Some(4));
+    }
+
+    #[test]
+    fn test_peek() {
+        let queue = Queue::new();
+        assert_eq!(queue.peek(), None);
+        queue.enqueue("first".to_string()).expect("enqueue");
+        queue.enqueue("second".to_string()).expect("enqueue");
+        assert_eq!(queue.peek_with(|element| element.len()), Some(5));
+        assert_eq!(queue.peek(), Some("first".to_string()));
+        let claim = queue.claim().expect("claim");
+        assert_eq!(queue.peek(), Some("second".to_string()));
+        drop(claim);
+        // Behind the sentinel left by the abort.
+        assert_eq!(queue.peek(), Some("first".to_string()));
+
+        let tickets = Queue::new();
+        tickets.enqueue(()).expect("enqueue");
+        assert_eq!(tickets.peek(), Some(()));
+
+        // The function can dequeue the element it is given.
+        assert_eq!(queue.peek_with(|element| (element.clone(), queue.dequeue())),
+            Some(("first".to_string(), Some("first".to_string()))));
+        assert_eq!(queue.peek(), Some("second".to_string()));
+    }
+
+    #[test]
+    fn test_debug_front() {
+        let queue = Queue::new();
+        assert_eq!(format!("{:?}", queue.debug_front(2)), "[]");
+        queue.enqueue_batch(0..5).expect("enqueue");
+        drop(queue.claim());
+        assert_eq!(format!("{:?}", queue.debug_front(3)), "[0, 1, 2]");
+        assert_eq!(format!("{:?}", queue.debug_front(10)), "[0, 1, 2, 3, 4]");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_any_while_dequeuing() {
+        let queue = Arc::new(Queue::new());
+        for i in 0..100_000 {
+            queue.enqueue(i.to_string()).expect("enqueue");
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let scanner = {
+            let queue = queue.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    // The last element is never dequeued.
+                    assert!(queue.any(|element| element == "99999"));
+                }
+            })
+        };
+        for _ in 0..99_999 {
+            assert!(queue.dequeue().is_some());
+        }
+        done.store(true, Ordering::SeqCst);
+        scanner.join().expect("join");
+        assert_eq!(queue.dequeue(), Some("99999".to_string()));
+    }
+}
@@ -0,0 +1,302 @@
+//! Recording and deterministic replay of the interleaving of threads, available with the
+//! `fail-points` feature.
+//!
+//! A `Recorder` logs the order in which the threads reach the fail points during a stress run.
+//! When the run fails, its `Schedule` can be printed and given to a `Replayer`, which lets only
+//! one thread run at a time, from one of its fail points to the next, in the recorded order. This
+//! turns a rare interleaving into a reproducible unit test.
+//!
+//! Each thread attaches itself with the identifier it has in the schedule before using the queue,
+//! and detaches itself when it is done.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use failpoint::{clear_hook, set_hook};
+
+/// How long a replayed thread waits for its turn before the schedule is considered diverged.
+const TURN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The order in which threads reached the fail points: a list of (thread, fail point).
+///
+/// Its `Debug` output can be pasted in `Schedule::new(vec![...])`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Schedule {
+    steps: Vec<(usize, &'static str)>,
+}
+
+impl Schedule {
+    pub fn new(steps: Vec<(usize, &'static str)>) -> Self {
+        Self {
+            steps,
+        }
+    }
+
+    pub fn steps(&self) -> &[(usize, &'static str)] {
+        &self.steps
+    }
+}
+
+impl Debug for Schedule {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.debug_list()
+            .entries(&self.steps)
+            .finish()
+    }
+}
+
+/// Records the fail points reached by the attached threads.
+///
+/// Like the `Replayer`, it lets only one attached thread run between two fail points, so that the
+/// recorded order is the order of the transitions of the queue.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    state: Arc<(Mutex<Recording>, Condvar)>,
+}
+
+#[derive(Default)]
+struct Recording {
+    steps: Vec<(usize, &'static str)>,
+    holder: Option<usize>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the fail points reached by the current thread under the identifier `thread`.
+    pub fn attach(&self, thread: usize) {
+        let recorder = self.clone();
+        set_hook(move |point| recorder.step(thread, point));
+    }
+
+    /// Stop recording the current thread, which had the identifier `thread`.
+    pub fn detach(&self, thread: usize) {
+        clear_hook();
+        let (ref state, ref turn) = *self.state;
+        let mut state = lock(state);
+        if state.holder == Some(thread) {
+            state.holder = None;
+            turn.notify_all();
+        }
+    }
+
+    /// The steps recorded so far.
+    pub fn schedule(&self) -> Schedule {
+        Schedule::new(lock(&self.state.0).steps.clone())
+    }
+
+    fn step(&self, thread: usize, point: &'static str) {
+        let (ref state, ref turn) = *self.state;
+        let mut state = lock(state);
+        if state.holder == Some(thread) {
+            state.holder = None;
+            turn.notify_all();
+        }
+        while state.holder.is_some() {
+            state = turn.wait(state).unwrap_or_else(|error| error.into_inner());
+        }
+        state.holder = Some(thread);
+        state.steps.push((thread, point));
+    }
+}
+
+struct State {
+    /// Index of the step being run.
+    position: usize,
+    /// Thread running the current step, until it reaches its next fail point.
+    holder: Option<usize>,
+    detached: Vec<usize>,
+    diverged: bool,
+}
+
+/// Runs the attached threads in the order of a schedule.
+///
+/// When a thread reaches a fail point which is not the one expected by the schedule, or the
+/// thread expected does not reach it in time, the schedule has diverged: the threads then run
+/// freely, and `diverged()` returns `true`. They also run freely at the end of the schedule.
+#[derive(Clone)]
+pub struct Replayer {
+    schedule: Arc<Schedule>,
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl Replayer {
+    pub fn new(schedule: Schedule) -> Self {
+        Self {
+            schedule: Arc::new(schedule),
+            state: Arc::new((Mutex::new(State {
+                position: 0,
+                holder: None,
+                detached: vec![],
+                diverged: false,
+            }), Condvar::new())),
+        }
+    }
+
+    /// Make the current thread follow the steps of `thread` in the schedule.
+    pub fn attach(&self, thread: usize) {
+        let replayer = self.clone();
+        set_hook(move |point| replayer.step(thread, point));
+    }
+
+    /// Detach the current thread, which had the identifier `thread`, letting the next step run.
+    pub fn detach(&self, thread: usize) {
+        clear_hook();
+        let (ref state, ref turn) = *self.state;
+        let mut state = lock(state);
+        self.release(&mut state, thread);
+        state.detached.push(thread);
+        turn.notify_all();
+    }
+
+    /// Whether the threads did not follow the schedule.
+    pub fn diverged(&self) -> bool {
+        lock(&self.state.0).diverged
+    }
+
+    fn release(&self, state: &mut State, thread: usize) {
+        if state.holder == Some(thread) {
+            state.holder = None;
+            state.position += 1;
+        }
+    }
+
+    fn step(&self, thread: usize, point: &'static str) {
+        let (ref state, ref turn) = *self.state;
+        let mut state = lock(state);
+        self.release(&mut state, thread);
+        turn.notify_all();
+        loop {
+            if state.diverged {
+                return;
+            }
+            let (expected_thread, expected_point) =
+                match self.schedule.steps.get(state.position) {
+                    Some(&step) => step,
+                    None => return,
+                };
+            if expected_thread == thread {
+                if expected_point == point {
+                    state.holder = Some(thread);
+                }
+                else {
+                    state.diverged = true;
+                    turn.notify_all();
+                }
+                return;
+            }
+            if state.detached.contains(&expected_thread) {
+                state.diverged = true;
+                turn.notify_all();
+                return;
+            }
+            let (guard, result) = turn.wait_timeout(state, TURN_TIMEOUT).expect("wait turn");
+            state = guard;
+            if result.timed_out() && state.holder.is_none() {
+                state.diverged = true;
+                turn.notify_all();
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A panicking thread does not invalidate the steps.
+    mutex.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    use Queue;
+    use super::{Recorder, Replayer, Schedule};
+
+    /// Run two threads enqueuing concurrently, each one attached with `attach` and detached with
+    /// `detach`, and return the order of the elements in the queue.
+    fn run<A, D>(attach: A, detach: D) -> Vec<usize>
+    where A: Fn(usize) + Clone + Send + 'static,
+          D: Fn(usize) + Clone + Send + 'static,
+    {
+        let queue = Arc::new(Queue::new());
+        let barrier = Arc::new(Barrier::new(2));
+        let handles: Vec<_> = (0..2).map(|thread| {
+            let queue = queue.clone();
+            let barrier = barrier.clone();
+            let attach = attach.clone();
+            let detach = detach.clone();
+            thread::spawn(move || {
+                attach(thread);
+                barrier.wait();
+                for i in 0..20 {
+                    queue.enqueue(thread * 100 + i).expect("enqueue");
+                }
+                detach(thread);
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().expect("join");
+        }
+        let mut elements = vec![];
+        while let Some(element) = queue.dequeue() {
+            elements.push(element);
+        }
+        elements
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_record_replay() {
+        let recorder = Recorder::new();
+        let order = {
+            let attach = recorder.clone();
+            let detach = recorder.clone();
+            run(move |thread| attach.attach(thread), move |thread| detach.detach(thread))
+        };
+        let schedule = recorder.schedule();
+        assert_eq!(schedule.steps().iter().filter(|&&(_, point)| point == "enqueue.linked").count(), 40);
+
+        for _ in 0..3 {
+            let replayer = Replayer::new(schedule.clone());
+            let attach = replayer.clone();
+            let detach = replayer.clone();
+            let replayed = run(move |thread| attach.attach(thread), move |thread| detach.detach(thread));
+            assert!(!replayer.diverged());
+            assert_eq!(replayed, order);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_handwritten_schedule() {
+        let queue = Arc::new(Queue::new());
+        // Thread 0 is suspended after linking its node: thread 1 finds the tail lagging behind, so
+        // it needs a second attempt after helping.
+        let replayer = Replayer::new(Schedule::new(vec![
+            (0, "enqueue.loop"),
+            (1, "enqueue.loop"),
+            (1, "enqueue.loop"),
+            (1, "enqueue.linked"),
+            (0, "enqueue.linked"),
+        ]));
+        let handles: Vec<_> = (0..2).map(|thread| {
+            let queue = queue.clone();
+            let replayer = replayer.clone();
+            thread::spawn(move || {
+                replayer.attach(thread);
+                queue.enqueue(thread).expect("enqueue");
+                replayer.detach(thread);
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().expect("join");
+        }
+        assert!(!replayer.diverged());
+        assert_eq!(queue.dequeue(), Some(0));
+        assert_eq!(queue.dequeue(), Some(1));
+    }
+}
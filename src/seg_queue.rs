@@ -0,0 +1,480 @@
+//! A queue storing its elements in blocks of slots, so that it only allocates once per
+//! block instead of once per element.
+//!
+//! The positions of the head and the tail count the slots of all the blocks, plus one unused
+//! position per block at which the block is being replaced by the next one. Each slot has a state
+//! telling whether its value was written and read. No reclamation scheme is needed: a block is
+//! only freed when all its values were read, by the last reader.
+//!
+//! An elastic queue also counts its elements, to throttle the producers beyond a soft capacity
+//! which grows under pressure up to a hard maximum, like an elastic `BoundedQueue`.
+
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize, Ordering};
+use std::thread;
+
+use SendError;
+use array_queue::CachePadded;
+use bounded::Capacity;
+
+/// Number of positions per block, including the one to move to the next block.
+const LAP: usize = 64;
+
+/// Number of slots per block.
+const BLOCK_CAP: usize = LAP - 1;
+
+/// The positions are shifted to keep the lowest bit for the `HAS_NEXT` flag.
+const SHIFT: usize = 1;
+
+/// Flag of the head position telling that its block is not the last one, so that the consumers
+/// do not need to check the tail.
+const HAS_NEXT: usize = 1;
+
+/// Bits of the slot states.
+const WRITE: usize = 1;
+const READ: usize = 2;
+/// Set by a reader destroying the block when the value of the slot is still being read: the
+/// reader of the slot will continue the destruction.
+const DESTROY: usize = 4;
+
+struct Slot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicUsize,
+}
+
+impl<T> Slot<T> {
+    const fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    fn wait_write(&self) {
+        while self.state.load(Ordering::Acquire) & WRITE == 0 {
+            thread::yield_now();
+        }
+    }
+}
+
+struct Block<T> {
+    next: AtomicPtr<Block<T>>,
+    slots: [Slot<T>; BLOCK_CAP],
+}
+
+impl<T> Block<T> {
+    fn new() -> Box<Self> {
+        Box::new(Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            slots: [const { Slot::new() }; BLOCK_CAP],
+        })
+    }
+
+    /// Wait until the producer which filled the last slot links the next block.
+    fn wait_next(&self) -> *mut Self {
+        loop {
+            let next = self.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            thread::yield_now();
+        }
+    }
+
+    /// Free the block once the slots from `start` were read, or leave it to the thread still
+    /// reading one of them.
+    unsafe fn destroy(block: *mut Self, start: usize) {
+        // The reader of the last slot starts the destruction, so it does not need to be marked.
+        for index in start..BLOCK_CAP - 1 {
+            let slot = &(*block).slots[index];
+            if slot.state.load(Ordering::Acquire) & READ == 0 && slot.state.fetch_or(DESTROY, Ordering::AcqRel) & READ == 0 {
+                return;
+            }
+        }
+        drop(Box::from_raw(block));
+    }
+}
+
+struct Position<T> {
+    index: AtomicUsize,
+    block: AtomicPtr<Block<T>>,
+}
+
+/// A lock-free unbounded MPMC queue allocating its slots by blocks.
+///
+/// It is faster than `Queue` for many small elements, but has none of its features (claims,
+/// closing, notifications...).
+pub struct SegQueue<T> {
+    head: CachePadded<Position<T>>,
+    tail: CachePadded<Position<T>>,
+    /// Soft capacity of an elastic queue.
+    capacity: Option<Capacity>,
+    /// Number of elements of an elastic queue, including the ones being enqueued.
+    count: CachePadded<AtomicUsize>,
+    _marker: PhantomData<T>,
+}
+
+// The values are only accessed by the thread which reserved their slot.
+unsafe impl<T: Send> Send for SegQueue<T> {}
+unsafe impl<T: Send> Sync for SegQueue<T> {}
+
+impl<T> SegQueue<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: CachePadded(Position {
+                index: AtomicUsize::new(0),
+                block: AtomicPtr::new(ptr::null_mut()),
+            }),
+            tail: CachePadded(Position {
+                index: AtomicUsize::new(0),
+                block: AtomicPtr::new(ptr::null_mut()),
+            }),
+            capacity: None,
+            count: CachePadded(AtomicUsize::new(0)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Create a queue throttling its producers beyond `capacity` elements. The capacity doubles,
+    /// up to `max_capacity`, when the producers keep finding the queue full, and halves back when
+    /// it stays mostly empty.
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn elastic(capacity: usize, max_capacity: usize) -> Self {
+        assert!(capacity > 0, "the capacity of a SegQueue must be positive");
+        let mut queue = Self::new();
+        queue.capacity = Some(Capacity::new(capacity, max_capacity));
+        queue
+    }
+
+    /// Add an element, waiting for room if the queue is elastic and full.
+    pub fn enqueue(&self, mut value: T) {
+        while let Err(error) = self.try_enqueue(value) {
+            value = error.into_inner();
+            thread::yield_now();
+        }
+    }
+
+    /// Add an element, or give it back if the queue is elastic and full even after growing.
+    pub fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        if let Some(ref capacity) = self.capacity {
+            while self.count.fetch_add(1, Ordering::SeqCst) >= capacity.get() {
+                self.count.fetch_sub(1, Ordering::SeqCst);
+                if !capacity.grow() {
+                    return Err(SendError::Full(value));
+                }
+            }
+        }
+        self.push(value);
+        Ok(())
+    }
+
+    fn push(&self, value: T) {
+        let mut tail = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block = None;
+        loop {
+            let offset = (tail >> SHIFT) % LAP;
+            if offset == BLOCK_CAP {
+                // Another producer is installing the next block.
+                thread::yield_now();
+                tail = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+            // Allocate the next block before taking the last slot, to install it quickly.
+            if offset + 1 == BLOCK_CAP && next_block.is_none() {
+                next_block = Some(Block::new());
+            }
+            if block.is_null() {
+                // The first element: install the first block.
+                let new = Box::into_raw(Block::new());
+                if self.tail.block.compare_exchange(block, new, Ordering::Release, Ordering::Relaxed).is_ok() {
+                    self.head.block.store(new, Ordering::Release);
+                    block = new;
+                }
+                else {
+                    next_block = Some(unsafe { Box::from_raw(new) });
+                    tail = self.tail.index.load(Ordering::Acquire);
+                    block = self.tail.block.load(Ordering::Acquire);
+                    continue;
+                }
+            }
+            let new_tail = tail + (1 << SHIFT);
+            match self.tail.index.compare_exchange_weak(tail, new_tail, Ordering::SeqCst, Ordering::Acquire) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next_block = Box::into_raw(next_block.expect("next block"));
+                        self.tail.block.store(next_block, Ordering::Release);
+                        self.tail.index.store(new_tail.wrapping_add(1 << SHIFT), Ordering::Release);
+                        (*block).next.store(next_block, Ordering::Release);
+                    }
+                    let slot = &(*block).slots[offset];
+                    (*slot.value.get()).write(value);
+                    slot.state.fetch_or(WRITE, Ordering::Release);
+                    return;
+                },
+                Err(current) => {
+                    tail = current;
+                    block = self.tail.block.load(Ordering::Acquire);
+                },
+            }
+        }
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        let value = self.pop();
+        if let Some(ref capacity) = self.capacity {
+            if value.is_some() {
+                capacity.shrink(self.count.fetch_sub(1, Ordering::SeqCst) - 1);
+            }
+        }
+        value
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut head = self.head.index.load(Ordering::Acquire);
+        let mut block = self.head.block.load(Ordering::Acquire);
+        loop {
+            let offset = (head >> SHIFT) % LAP;
+            if offset == BLOCK_CAP {
+                // Another consumer is moving to the next block.
+                thread::yield_now();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+            let mut new_head = head + (1 << SHIFT);
+            if new_head & HAS_NEXT == 0 {
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.index.load(Ordering::Relaxed);
+                if head >> SHIFT == tail >> SHIFT {
+                    return None;
+                }
+                if (head >> SHIFT) / LAP != (tail >> SHIFT) / LAP {
+                    new_head |= HAS_NEXT;
+                }
+            }
+            if block.is_null() {
+                // The first block is being installed.
+                thread::yield_now();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+            match self.head.index.compare_exchange_weak(head, new_head, Ordering::SeqCst, Ordering::Acquire) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next = (*block).wait_next();
+                        let mut next_index = (new_head & !HAS_NEXT).wrapping_add(1 << SHIFT);
+                        if !(*next).next.load(Ordering::Relaxed).is_null() {
+                            next_index |= HAS_NEXT;
+                        }
+                        self.head.block.store(next, Ordering::Release);
+                        self.head.index.store(next_index, Ordering::Release);
+                    }
+                    let slot = &(*block).slots[offset];
+                    slot.wait_write();
+                    let value = (*slot.value.get()).assume_init_read();
+                    if offset + 1 == BLOCK_CAP {
+                        Block::destroy(block, 0);
+                    }
+                    else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+                        Block::destroy(block, offset + 1);
+                    }
+                    return Some(value);
+                },
+                Err(current) => {
+                    head = current;
+                    block = self.head.block.load(Ordering::Acquire);
+                },
+            }
+        }
+    }
+
+    /// Number of elements in the queue, which is only a snapshot with concurrent operations.
+    pub fn len(&self) -> usize {
+        loop {
+            let mut tail = self.tail.index.load(Ordering::SeqCst);
+            let mut head = self.head.index.load(Ordering::SeqCst);
+            // Retry until the positions are consistent with each other.
+            if self.tail.index.load(Ordering::SeqCst) != tail {
+                continue;
+            }
+            tail &= !((1 << SHIFT) - 1);
+            head &= !((1 << SHIFT) - 1);
+            // The positions at the end of a block are the start of the next one.
+            if (tail >> SHIFT) % LAP == BLOCK_CAP {
+                tail = tail.wrapping_add(1 << SHIFT);
+            }
+            if (head >> SHIFT) % LAP == BLOCK_CAP {
+                head = head.wrapping_add(1 << SHIFT);
+            }
+            // Count from the block of the head, and skip the unused position of each block.
+            let lap = (head >> SHIFT) / LAP;
+            tail = tail.wrapping_sub((lap * LAP) << SHIFT) >> SHIFT;
+            head = head.wrapping_sub((lap * LAP) << SHIFT) >> SHIFT;
+            return tail - head - tail / LAP;
+        }
+    }
+
+    /// The current soft capacity of an elastic queue, or `None` if the queue is unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity.as_ref().map(Capacity::get)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let head = self.head.index.load(Ordering::SeqCst);
+        let tail = self.tail.index.load(Ordering::SeqCst);
+        head >> SHIFT == tail >> SHIFT
+    }
+}
+
+impl<T> Default for SegQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for SegQueue<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.index.get_mut() & !((1 << SHIFT) - 1);
+        let tail = *self.tail.index.get_mut() & !((1 << SHIFT) - 1);
+        let mut block = *self.head.block.get_mut();
+        unsafe {
+            while head != tail {
+                let offset = (head >> SHIFT) % LAP;
+                if offset < BLOCK_CAP {
+                    (*(*block).slots[offset].value.get()).assume_init_drop();
+                }
+                else {
+                    let next = *(*block).next.get_mut();
+                    drop(Box::from_raw(block));
+                    block = next;
+                }
+                head = head.wrapping_add(1 << SHIFT);
+            }
+            if !block.is_null() {
+                drop(Box::from_raw(block));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use SendError;
+    use super::{BLOCK_CAP, SegQueue};
+
+    #[test]
+    fn test_blocks() {
+        let queue = SegQueue::new();
+        assert!(queue.is_empty());
+        for lap in 0..3 {
+            for i in 0..3 * BLOCK_CAP {
+                queue.enqueue(lap * 1_000 + i);
+            }
+            assert_eq!(queue.len(), 3 * BLOCK_CAP);
+            for i in 0..2 * BLOCK_CAP + 1 {
+                assert_eq!(queue.dequeue(), Some(lap * 1_000 + i));
+            }
+            assert_eq!(queue.len(), BLOCK_CAP - 1);
+            for i in 2 * BLOCK_CAP + 1..3 * BLOCK_CAP {
+                assert_eq!(queue.dequeue(), Some(lap * 1_000 + i));
+            }
+            assert!(queue.is_empty());
+            assert_eq!(queue.dequeue(), None);
+        }
+
+        // The elements left are dropped with the queue, across blocks.
+        let queue = SegQueue::new();
+        let value = Arc::new(());
+        for _ in 0..BLOCK_CAP + 10 {
+            queue.enqueue(value.clone());
+        }
+        drop(queue.dequeue());
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_elastic() {
+        let queue = SegQueue::elastic(4, 16);
+        for i in 0..4 {
+            queue.enqueue(i);
+        }
+        // The pressure of the full queue makes the capacity double until the maximum.
+        let mut rejected = 0;
+        for i in 4..100 {
+            if let Err(error) = queue.try_enqueue(i) {
+                assert_eq!(error, SendError::Full(i));
+                rejected += 1;
+            }
+        }
+        assert_eq!(queue.capacity(), Some(16));
+        assert_eq!(rejected, 100 - 16);
+        assert_eq!(queue.len(), 16);
+
+        // Once the queue stays mostly empty, the capacity goes back to its initial value.
+        while queue.dequeue().is_some() {
+        }
+        for i in 0..100 {
+            queue.enqueue(i);
+            queue.dequeue();
+        }
+        assert_eq!(queue.capacity(), Some(4));
+        assert_eq!(SegQueue::<u32>::new().capacity(), None);
+
+        // A full queue throttles the producers until a consumer makes room.
+        let queue = Arc::new(SegQueue::elastic(1, 1));
+        queue.enqueue(0);
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.enqueue(1))
+        };
+        assert_eq!(queue.dequeue(), Some(0));
+        producer.join().expect("join");
+        assert_eq!(queue.dequeue(), Some(1));
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 200 } else { 100_000 };
+        let queue = Arc::new(SegQueue::new());
+        let producers: Vec<_> = (0..4).map(|thread| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..count {
+                    queue.enqueue(thread * count + i);
+                }
+            })
+        }).collect();
+        let consumers: Vec<_> = (0..2).map(|_| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut elements = vec![];
+                while elements.len() < 2 * count {
+                    match queue.dequeue() {
+                        Some(element) => elements.push(element),
+                        None => thread::yield_now(),
+                    }
+                }
+                elements
+            })
+        }).collect();
+        for producer in producers {
+            producer.join().expect("join");
+        }
+        let mut received: Vec<_> = consumers.into_iter()
+            .flat_map(|consumer| consumer.join().expect("join"))
+            .collect();
+        received.sort();
+        assert_eq!(received, (0..4 * count).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,181 @@
+//! Time spent by the elements in the queue, measured with the `sojourn-time` feature.
+
+#[cfg(feature = "sojourn-time")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "sojourn-time")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "sojourn-time")]
+use {Claim, Queue};
+#[cfg(feature = "sojourn-time")]
+use reclaim::Reclaim;
+
+/// One dequeue out of this number is recorded in the latency histogram.
+#[cfg(feature = "sojourn-time")]
+const SAMPLE_RATE: u64 = 8;
+
+/// Bucket `i` of the histogram counts the sojourn times lower than 2^i microseconds, and not in a
+/// previous bucket. The last one also counts everything above.
+#[cfg(feature = "sojourn-time")]
+const BUCKET_COUNT: usize = 40;
+
+/// When an element was enqueued. Empty without the `sojourn-time` feature.
+#[derive(Clone, Copy)]
+pub(crate) struct Stamp {
+    #[cfg(feature = "sojourn-time")]
+    enqueued: Instant,
+}
+
+impl Stamp {
+    pub(crate) fn now() -> Self {
+        Stamp {
+            #[cfg(feature = "sojourn-time")]
+            enqueued: Instant::now(),
+        }
+    }
+
+    #[cfg(feature = "sojourn-time")]
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.enqueued.elapsed()
+    }
+}
+
+/// A lock-free histogram of sampled sojourn times, on a logarithmic scale.
+#[cfg(feature = "sojourn-time")]
+pub(crate) struct Histogram {
+    dequeues: AtomicU64,
+    buckets: [AtomicU64; BUCKET_COUNT],
+}
+
+#[cfg(feature = "sojourn-time")]
+impl Histogram {
+    pub(crate) fn new() -> Self {
+        Self {
+            dequeues: AtomicU64::new(0),
+            buckets: [const { AtomicU64::new(0) }; BUCKET_COUNT],
+        }
+    }
+
+    pub(crate) fn record(&self, sojourn: Duration) {
+        if !self.dequeues.fetch_add(1, Ordering::Relaxed).is_multiple_of(SAMPLE_RATE) {
+            return;
+        }
+        let micros = sojourn.as_micros().min(u64::MAX as u128) as u64;
+        let index = ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKET_COUNT - 1);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn percentiles(&self) -> Option<LatencyPercentiles> {
+        let counts: Vec<_> = self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect();
+        let samples: u64 = counts.iter().sum();
+        if samples == 0 {
+            return None;
+        }
+        let percentile = |percentile: f64| {
+            // Rank of the sample, starting at 1.
+            let rank = ((samples as f64 * percentile / 100.0).ceil() as u64).max(1);
+            let mut seen = 0;
+            let index = counts.iter()
+                .position(|&count| {
+                    seen += count;
+                    seen >= rank
+                })
+                .unwrap_or(BUCKET_COUNT - 1);
+            Duration::from_micros(1 << index)
+        };
+        Some(LatencyPercentiles {
+            samples,
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            p999: percentile(99.9),
+        })
+    }
+}
+
+/// Percentiles of the time spent in the queue, computed from a sample of the dequeued elements.
+///
+/// They are upper bounds: each one is rounded up to a power of two microseconds.
+#[cfg(feature = "sojourn-time")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    /// Number of elements sampled.
+    pub samples: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+}
+
+#[cfg(feature = "sojourn-time")]
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Percentiles of the time spent in the queue by the dequeued elements, or `None` if none
+    /// was sampled yet.
+    pub fn latency_percentiles(&self) -> Option<LatencyPercentiles> {
+        self.latencies.percentiles()
+    }
+
+    /// Dequeue an element along with the time it spent in the queue.
+    pub fn dequeue_timed(&self) -> Option<(T, Duration)> {
+        self.claim().map(|claim| {
+            let sojourn = claim.sojourn();
+            (claim.commit(), sojourn)
+        })
+    }
+}
+
+#[cfg(feature = "sojourn-time")]
+impl<'a, T, R: Reclaim> Claim<'a, T, R> {
+    /// Time since the element was enqueued. Aborting the claim does not reset it.
+    pub fn sojourn(&self) -> Duration {
+        self.stamp.elapsed()
+    }
+}
+
+#[cfg(all(test, feature = "sojourn-time"))]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use Queue;
+
+    #[test]
+    fn test_dequeue_timed() {
+        let queue = Queue::new();
+        queue.enqueue(1).expect("enqueue");
+        thread::sleep(Duration::from_millis(20));
+        queue.enqueue(2).expect("enqueue");
+        let (value, sojourn) = queue.dequeue_timed().expect("element");
+        assert_eq!(value, 1);
+        assert!(sojourn >= Duration::from_millis(20));
+
+        // The time spent in a claim which is aborted is counted.
+        let claim = queue.claim().expect("claim");
+        thread::sleep(Duration::from_millis(20));
+        claim.abort();
+        let (value, sojourn) = queue.dequeue_timed().expect("element");
+        assert_eq!(value, 2);
+        assert!(sojourn >= Duration::from_millis(20));
+        assert!(queue.dequeue_timed().is_none());
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let queue = Queue::new();
+        assert_eq!(queue.latency_percentiles(), None);
+        for i in 0..1_000 {
+            queue.enqueue(i).expect("enqueue");
+        }
+        thread::sleep(Duration::from_millis(10));
+        // Claims only count when they are committed.
+        drop(queue.claim());
+        while queue.dequeue().is_some() {
+        }
+        let percentiles = queue.latency_percentiles().expect("percentiles");
+        assert_eq!(percentiles.samples, 1_000 / super::SAMPLE_RATE);
+        assert!(percentiles.p50 >= Duration::from_millis(10));
+        assert!(percentiles.p50 <= percentiles.p90);
+        assert!(percentiles.p90 <= percentiles.p99);
+        assert!(percentiles.p99 <= percentiles.p999);
+    }
+}
@@ -0,0 +1,431 @@
+//! Single-producer single-consumer structures, which only need loads and stores on their hot
+//! path.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::cmp;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use SendError;
+use array_queue::CachePadded;
+use sync::atomic::{AtomicUsize, Ordering};
+
+/// A lock-free ring of `Copy` values, to stream data (audio samples, serial, logs) between two
+/// threads without framing it into elements.
+///
+/// The values are moved in and out by contiguous chunks with `ptr::copy_nonoverlapping()`.
+/// Use `split()` to get the writing and reading ends.
+pub struct Pipe<T: Copy> {
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Number of values read since the creation, wrapping around.
+    head: AtomicUsize,
+    /// Number of values written since the creation, wrapping around.
+    tail: AtomicUsize,
+}
+
+/// A pipe streaming bytes.
+pub type BytePipe = Pipe<u8>;
+
+// The buffer is only accessed through the unique writer and reader, on disjoint ranges.
+unsafe impl<T: Copy + Send> Sync for Pipe<T> {}
+
+impl<T: Copy> Pipe<T> {
+    /// Create a pipe able to hold at least `capacity` values (rounded up to a power of two).
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        Self {
+            buffer: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Number of values written but not yet read.
+    pub fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of values which can be written before the pipe is full.
+    pub fn remaining(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn split(&mut self) -> (PipeWriter<'_, T>, PipeReader<'_, T>) {
+        (PipeWriter { pipe: self }, PipeReader { pipe: self })
+    }
+
+}
+
+/// Copy `data` in the ring, whose length is a power of two, starting at `position`, in at most
+/// two chunks.
+unsafe fn copy_in<T: Copy>(ring: &[UnsafeCell<MaybeUninit<T>>], position: usize, data: &[T]) {
+    let start = UnsafeCell::raw_get(ring.as_ptr()).cast::<T>();
+    let offset = position & (ring.len() - 1);
+    let first = cmp::min(data.len(), ring.len() - offset);
+    ptr::copy_nonoverlapping(data.as_ptr(), start.add(offset), first);
+    ptr::copy_nonoverlapping(data.as_ptr().add(first), start, data.len() - first);
+}
+
+/// Fill `buffer` from the ring, whose length is a power of two, starting at `position`, in at
+/// most two chunks.
+unsafe fn copy_out<T: Copy>(ring: &[UnsafeCell<MaybeUninit<T>>], position: usize, buffer: &mut [T]) {
+    let start = UnsafeCell::raw_get(ring.as_ptr()).cast::<T>();
+    let offset = position & (ring.len() - 1);
+    let first = cmp::min(buffer.len(), ring.len() - offset);
+    ptr::copy_nonoverlapping(start.add(offset), buffer.as_mut_ptr(), first);
+    ptr::copy_nonoverlapping(start, buffer.as_mut_ptr().add(first), buffer.len() - first);
+}
+
+/// The writing end of a `Pipe`.
+pub struct PipeWriter<'a, T: Copy + 'a> {
+    pipe: &'a Pipe<T>,
+}
+
+impl<'a, T: Copy> PipeWriter<'a, T> {
+    /// Write as many values as there is room for, returning how many were written.
+    pub fn write(&mut self, data: &[T]) -> usize {
+        let tail = self.pipe.tail.load(Ordering::Relaxed);
+        let len = cmp::min(self.remaining(), data.len());
+        unsafe {
+            copy_in(&self.pipe.buffer, tail, &data[..len]);
+        }
+        // Release to publish the values to the reader.
+        self.pipe.tail.store(tail.wrapping_add(len), Ordering::Release);
+        len
+    }
+
+    /// Number of values which can be written: the reader can only make it grow.
+    pub fn remaining(&self) -> usize {
+        let tail = self.pipe.tail.load(Ordering::Relaxed);
+        // Acquire so that the reader is done with the values we are going to overwrite.
+        let head = self.pipe.head.load(Ordering::Acquire);
+        self.pipe.capacity() - tail.wrapping_sub(head)
+    }
+}
+
+/// The reading end of a `Pipe`.
+pub struct PipeReader<'a, T: Copy + 'a> {
+    pipe: &'a Pipe<T>,
+}
+
+impl<'a, T: Copy> PipeReader<'a, T> {
+    /// Read as many values as available and fitting in `buffer`, returning how many were read.
+    pub fn read(&mut self, buffer: &mut [T]) -> usize {
+        let head = self.pipe.head.load(Ordering::Relaxed);
+        // Acquire so that the values written by the writer are visible.
+        let tail = self.pipe.tail.load(Ordering::Acquire);
+        let len = cmp::min(tail.wrapping_sub(head), buffer.len());
+        unsafe {
+            copy_out(&self.pipe.buffer, head, &mut buffer[..len]);
+        }
+        // Release so that the writer only overwrites these values after we copied them.
+        self.pipe.head.store(head.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+/// A bounded queue between one producer and one consumer, for instance between an audio thread
+/// and a UI thread.
+///
+/// Unlike a `Pipe`, the elements do not need to be `Copy`. Each end caches the position of the
+/// other one, and only loads it again when the queue looks full or empty. Use `split()` to get
+/// the producer and the consumer.
+pub struct Queue<T> {
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Number of elements dequeued since the creation, wrapping around.
+    head: CachePadded<AtomicUsize>,
+    /// Number of elements enqueued since the creation, wrapping around.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// The elements are only accessed through the unique producer and consumer, on disjoint slots.
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    /// Create a queue holding at least `capacity` elements (rounded up to a power of two).
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        Self {
+            buffer: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Number of elements enqueued but not yet dequeued.
+    pub fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn split(&mut self) -> (Producer<'_, T>, Consumer<'_, T>) {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed);
+        (Producer { queue: self, head }, Consumer { queue: self, tail })
+    }
+
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        self.buffer[position & (self.buffer.len() - 1)].get()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            unsafe { (*self.slot(head)).assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// The producing end of a `spsc::Queue`.
+pub struct Producer<'a, T: 'a> {
+    queue: &'a Queue<T>,
+    /// Position of the consumer when it was last loaded.
+    head: usize,
+}
+
+impl<'a, T> Producer<'a, T> {
+    /// Add an element, or give it back if the queue is full.
+    pub fn try_enqueue(&mut self, value: T) -> Result<(), SendError<T>> {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head) == self.queue.capacity() {
+            // Acquire so that the consumer is done with the element we are going to overwrite.
+            self.head = self.queue.head.load(Ordering::Acquire);
+            if tail.wrapping_sub(self.head) == self.queue.capacity() {
+                return Err(SendError::Full(value));
+            }
+        }
+        unsafe { (*self.queue.slot(tail)).write(value) };
+        // Release to publish the element to the consumer.
+        self.queue.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<'a, T: Copy> Producer<'a, T> {
+    /// Copy the values at the start of `data` for which there is room, in at most two chunks,
+    /// returning how many were enqueued.
+    ///
+    /// Unlike in `ArrayQueue` and `BoundedQueue`, the elements are contiguous, so each chunk is a
+    /// single `memcpy`.
+    pub fn try_enqueue_slice(&mut self, data: &[T]) -> usize {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        if self.queue.capacity() - tail.wrapping_sub(self.head) < data.len() {
+            // Acquire so that the consumer is done with the elements we are going to overwrite.
+            self.head = self.queue.head.load(Ordering::Acquire);
+        }
+        let len = cmp::min(self.queue.capacity() - tail.wrapping_sub(self.head), data.len());
+        unsafe { copy_in(&self.queue.buffer, tail, &data[..len]) };
+        // Release to publish the elements to the consumer.
+        self.queue.tail.store(tail.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+/// The consuming end of a `spsc::Queue`.
+pub struct Consumer<'a, T: 'a> {
+    queue: &'a Queue<T>,
+    /// Position of the producer when it was last loaded.
+    tail: usize,
+}
+
+impl<'a, T> Consumer<'a, T> {
+    /// Remove the first element, or return `None` if the queue is empty.
+    pub fn dequeue(&mut self) -> Option<T> {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        if head == self.tail {
+            // Acquire so that the element written by the producer is visible.
+            self.tail = self.queue.tail.load(Ordering::Acquire);
+            if head == self.tail {
+                return None;
+            }
+        }
+        let value = unsafe { (*self.queue.slot(head)).assume_init_read() };
+        // Release so that the producer only overwrites the slot after we read it.
+        self.queue.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+}
+
+impl<'a, T: Copy> Consumer<'a, T> {
+    /// Copy the first elements to `buffer`, in at most two chunks, until it is full or the queue
+    /// is empty, returning how many were dequeued.
+    pub fn dequeue_slice(&mut self, buffer: &mut [T]) -> usize {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        if self.tail.wrapping_sub(head) < buffer.len() {
+            // Acquire so that the elements written by the producer are visible.
+            self.tail = self.queue.tail.load(Ordering::Acquire);
+        }
+        let len = cmp::min(self.tail.wrapping_sub(head), buffer.len());
+        unsafe { copy_out(&self.queue.buffer, head, &mut buffer[..len]) };
+        // Release so that the producer only overwrites the slots after we copied them.
+        self.queue.head.store(head.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use std::sync::Arc;
+
+    use SendError;
+    use super::{BytePipe, Pipe, Queue};
+
+    #[test]
+    fn test_wrap_around() {
+        let mut pipe = BytePipe::new(6);
+        assert_eq!(pipe.capacity(), 8);
+        let (mut writer, mut reader) = pipe.split();
+        let mut buffer = [0; 8];
+
+        assert_eq!(writer.write(b"abcdef"), 6);
+        assert_eq!(writer.remaining(), 2);
+        assert_eq!(reader.read(&mut buffer[..4]), 4);
+        assert_eq!(&buffer[..4], b"abcd");
+        assert_eq!(writer.remaining(), 6);
+        assert_eq!(writer.write(b"ghijklmn"), 6);
+        assert_eq!(writer.remaining(), 0);
+        assert_eq!(writer.write(b"z"), 0);
+        assert_eq!(reader.read(&mut buffer), 8);
+        assert_eq!(&buffer, b"efghijkl");
+        assert_eq!(reader.read(&mut buffer), 0);
+        assert!(pipe.is_empty());
+        assert_eq!(pipe.remaining(), 8);
+    }
+
+    #[test]
+    fn test_stream() {
+        const LEN: usize = 100_000;
+        let mut pipe = BytePipe::new(1024);
+        let (mut writer, mut reader) = pipe.split();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+                let mut written = 0;
+                while written < LEN {
+                    let end = (written + 300).min(LEN);
+                    written += writer.write(&data[written..end]);
+                }
+            });
+
+            let mut buffer = [0; 700];
+            let mut read = 0;
+            while read < LEN {
+                let len = reader.read(&mut buffer);
+                for (i, &byte) in buffer[..len].iter().enumerate() {
+                    assert_eq!(byte, (read + i) as u8);
+                }
+                read += len;
+            }
+        });
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut pipe = Pipe::new(4);
+        let (mut writer, mut reader) = pipe.split();
+        let mut buffer = [0.0f32; 3];
+        assert_eq!(writer.write(&[0.5, 1.5, 2.5]), 3);
+        assert_eq!(reader.read(&mut buffer[..2]), 2);
+        assert_eq!(writer.write(&[3.5, 4.5, 5.5, 6.5]), 3);
+        assert_eq!(reader.read(&mut buffer), 3);
+        assert_eq!(buffer, [2.5, 3.5, 4.5]);
+        assert_eq!(reader.read(&mut buffer), 1);
+        assert_eq!(buffer[0], 5.5);
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut queue = Queue::new(3);
+        let (mut producer, mut consumer) = queue.split();
+        for lap in 0..3 {
+            for i in 0..4 {
+                producer.try_enqueue(lap * 10 + i).expect("enqueue");
+            }
+            assert_eq!(producer.try_enqueue(99), Err(SendError::Full(99)));
+            for i in 0..4 {
+                assert_eq!(consumer.dequeue(), Some(lap * 10 + i));
+            }
+            assert_eq!(consumer.dequeue(), None);
+        }
+
+        // The elements left are dropped with the queue.
+        let mut queue = Queue::new(4);
+        let value = Arc::new(());
+        queue.split().0.try_enqueue(value.clone()).expect("enqueue");
+        assert_eq!(queue.len(), 1);
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_queue_slices() {
+        let mut queue = Queue::new(4);
+        let (mut producer, mut consumer) = queue.split();
+        let mut buffer = [0.0f32; 3];
+        assert_eq!(producer.try_enqueue_slice(&[0.5, 1.5, 2.5]), 3);
+        assert_eq!(consumer.dequeue_slice(&mut buffer[..2]), 2);
+        assert_eq!(buffer[..2], [0.5, 1.5]);
+        // Copied in two chunks around the end of the buffer.
+        assert_eq!(producer.try_enqueue_slice(&[3.5, 4.5, 5.5, 6.5]), 3);
+        assert_eq!(producer.try_enqueue(7.5), Err(SendError::Full(7.5)));
+        assert_eq!(consumer.dequeue(), Some(2.5));
+        assert_eq!(consumer.dequeue_slice(&mut buffer), 3);
+        assert_eq!(buffer, [3.5, 4.5, 5.5]);
+        assert_eq!(consumer.dequeue_slice(&mut buffer), 0);
+    }
+
+    #[test]
+    fn test_queue_threads() {
+        let count = if cfg!(miri) { 500 } else { 100_000 };
+        let mut queue = Queue::new(64);
+        let (mut producer, mut consumer) = queue.split();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..count {
+                    let mut value = i.to_string();
+                    while let Err(error) = producer.try_enqueue(value) {
+                        value = error.into_inner();
+                        thread::yield_now();
+                    }
+                }
+            });
+
+            for i in 0..count {
+                loop {
+                    if let Some(element) = consumer.dequeue() {
+                        assert_eq!(element, i.to_string());
+                        break;
+                    }
+                    thread::yield_now();
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use Queue;
+
+/// A submission queue paired with a completion queue, in the style of io_uring, for drivers and
+/// offload workers.
+///
+/// The producers submit requests of type `T` with a tag of their choice, and later reap the
+/// results of type `R` along with the tag of their request. The workers take the submissions and
+/// complete them. The completions are in the order in which the workers finished the requests,
+/// which is not necessarily the order of the submissions.
+pub struct SqCq<T, R> {
+    submissions: Queue<(u64, T)>,
+    completions: Queue<(u64, R)>,
+    in_flight: AtomicUsize,
+}
+
+impl<T, R> SqCq<T, R> {
+    pub fn new() -> Self {
+        Self {
+            submissions: Queue::new(),
+            completions: Queue::new(),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Submit a request, identified by `tag` in its completion.
+    pub fn submit(&self, tag: u64, request: T) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.submissions.enqueue((tag, request)).expect("enqueue, since the queue is never closed");
+    }
+
+    /// Take the next request to process, if any.
+    pub fn next_submission(&self) -> Option<Submission<'_, T, R>> {
+        self.submissions.dequeue().map(|(tag, request)| Submission {
+            queue: self,
+            tag,
+            request,
+        })
+    }
+
+    /// Take the result of a completed request, with the tag of the request.
+    pub fn reap(&self) -> Option<(u64, R)> {
+        let completion = self.completions.dequeue()?;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        Some(completion)
+    }
+
+    /// Take the result of a completed request, waiting for one until the timeout expires.
+    pub fn reap_timeout(&self, timeout: Duration) -> Option<(u64, R)> {
+        let completion = self.completions.dequeue_timeout(timeout).ok()?;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        Some(completion)
+    }
+
+    /// Number of requests submitted whose result was not reaped yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    fn complete(&self, tag: u64, result: R) {
+        self.completions.enqueue((tag, result)).expect("enqueue, since the queue is never closed");
+    }
+}
+
+impl<T, R> Default for SqCq<T, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A request taken from a `SqCq`, which remembers its tag to correlate its result.
+///
+/// A submission dropped without being completed stays in flight.
+pub struct Submission<'a, T: 'a, R: 'a> {
+    queue: &'a SqCq<T, R>,
+    tag: u64,
+    request: T,
+}
+
+impl<'a, T, R> Submission<'a, T, R> {
+    pub fn tag(&self) -> u64 {
+        self.tag
+    }
+
+    pub fn request(&self) -> &T {
+        &self.request
+    }
+
+    /// Post the result of the request to the completion queue, and give back the request.
+    pub fn complete(self, result: R) -> T {
+        self.queue.complete(self.tag, result);
+        self.request
+    }
+
+    /// Process the request with `f` and post its result.
+    pub fn complete_with<F: FnOnce(T) -> R>(self, f: F) {
+        let result = f(self.request);
+        self.queue.complete(self.tag, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::SqCq;
+
+    #[test]
+    fn test_correlation() {
+        let queue = SqCq::new();
+        queue.submit(7, "read");
+        queue.submit(8, "write");
+        assert_eq!(queue.in_flight(), 2);
+
+        let read = queue.next_submission().expect("submission");
+        let write = queue.next_submission().expect("submission");
+        assert!(queue.next_submission().is_none());
+        assert_eq!((read.tag(), *read.request()), (7, "read"));
+        write.complete_with(|request| request.len());
+        assert_eq!(read.complete(0), "read");
+
+        assert_eq!(queue.reap(), Some((8, 5)));
+        assert_eq!(queue.in_flight(), 1);
+        assert_eq!(queue.reap(), Some((7, 0)));
+        assert_eq!(queue.reap(), None);
+        assert_eq!(queue.in_flight(), 0);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_worker() {
+        let queue = Arc::new(SqCq::new());
+        let worker = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut processed = 0;
+                while processed < 100 {
+                    if let Some(submission) = queue.next_submission() {
+                        submission.complete_with(|request: u64| request * 2);
+                        processed += 1;
+                    }
+                }
+            })
+        };
+        for tag in 0..100 {
+            queue.submit(tag, tag + 1);
+        }
+        let mut results = vec![];
+        while results.len() < 100 {
+            let (tag, result) = queue.reap_timeout(Duration::from_secs(10)).expect("completion");
+            assert_eq!(result, (tag + 1) * 2);
+            results.push(tag);
+        }
+        worker.join().expect("join");
+        results.sort();
+        assert_eq!(results, (0..100).collect::<Vec<_>>());
+        assert_eq!(queue.in_flight(), 0);
+    }
+}
@@ -0,0 +1,179 @@
+//! A lock-free stack, sharing the nodes, the reclamation strategies and the free list of the
+//! queue.
+//!
+//! This is the algorithm of Treiber: the threads push and pop by a compare-and-swap of the top
+//! node. A popped node is retired like the ones removed from the queue, so it cannot be recycled
+//! while another thread still reads its next field, which also prevents the ABA problem of a node
+//! popped and pushed again between the load and the compare-and-swap of another thread.
+
+use core::marker::PhantomData;
+use core::ptr;
+
+use Node;
+use backoff::{Backoff, BackoffPolicy};
+use free_list::FreeList;
+use reclaim::{DefaultReclaim, Reclaim};
+use sync::{Arc, AtomicPtr, Exclusive};
+use sync::atomic::Ordering;
+
+/// A lock-free stack, whose popped nodes are reclaimed with the strategy `R`.
+pub struct Stack<T, R: Reclaim = DefaultReclaim> {
+    head: AtomicPtr<Node<T>>,
+    reclaim: R,
+    /// Popped nodes kept for new elements, shared with the deferred reclamation.
+    free_list: Arc<FreeList<T>>,
+    _marker: PhantomData<*const T>,
+}
+
+// Like for the queue, an element is only accessed by the thread pushing it and the one popping it.
+unsafe impl<T: Send, R: Reclaim + Send> Send for Stack<T, R> {}
+unsafe impl<T: Send, R: Reclaim + Sync> Sync for Stack<T, R> {}
+
+impl<T> Stack<T> {
+    pub fn new() -> Self {
+        Self::with_reclaim(DefaultReclaim::default())
+    }
+}
+
+impl<T, R: Reclaim> Stack<T, R> {
+    /// Create a stack reclaiming its popped nodes with the specified strategy.
+    pub fn with_reclaim(reclaim: R) -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            reclaim,
+            free_list: Arc::new(FreeList::new()),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = self.free_list.create(Node::new(value, None));
+        let mut head = self.head.load(Ordering::Relaxed);
+        let mut step = 0;
+        loop {
+            unsafe { (*node).next.store(head, Ordering::Relaxed) };
+            match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+            Backoff::new().backoff(step);
+            step = step.saturating_add(1);
+        }
+    }
+
+    /// Remove the last element pushed.
+    pub fn pop(&self) -> Option<T> {
+        let guard = self.reclaim.guard();
+        let mut step = 0;
+        loop {
+            let head = self.reclaim.protect(&guard, 0, &self.head);
+            if head.is_null() {
+                return None;
+            }
+            let next = unsafe { (*head).next.load(Ordering::Relaxed) };
+            if self.head.compare_exchange_weak(head, next, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                unsafe {
+                    // The other threads protecting the node only read its next field.
+                    let value = (*head).value.take();
+                    let free_list = self.free_list.clone();
+                    self.reclaim.retire(&guard, head, move || free_list.recycle(head));
+                    return value;
+                }
+            }
+            Backoff::new().backoff(step);
+            step = step.saturating_add(1);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+
+    /// Destroy the popped nodes which are not accessed anymore, instead of waiting for the
+    /// reclamation strategy to do it periodically.
+    pub fn collect_garbage(&self) {
+        self.reclaim.collect(&self.reclaim.guard());
+    }
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, R: Reclaim> Drop for Stack<T, R> {
+    fn drop(&mut self) {
+        let mut node = self.head.load_mut();
+        while !node.is_null() {
+            unsafe {
+                let next = (*node).next.load_mut();
+                ptr::drop_in_place(node);
+                self.free_list.deallocate(node);
+                node = next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use reclaim::HazardPointers;
+    use super::Stack;
+
+    #[test]
+    fn test_single_thread() {
+        let stack = Stack::new();
+        assert!(stack.is_empty());
+        assert_eq!(stack.pop(), None);
+        for i in 0..3 {
+            stack.push(i);
+        }
+        assert_eq!(stack.pop(), Some(2));
+        stack.push(3);
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(stack.pop(), Some(0));
+        assert!(stack.is_empty());
+
+        // The elements left are dropped with the stack.
+        let value = Arc::new(());
+        let stack = Stack::new();
+        stack.push(value.clone());
+        stack.push(value.clone());
+        drop(stack.pop());
+        stack.collect_garbage();
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_multithread() {
+        let count = if cfg!(miri) { 200 } else { 100_000 };
+        let stack = Stack::with_reclaim(HazardPointers);
+        let popped: Vec<_> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..4).map(|thread| {
+                let stack = &stack;
+                scope.spawn(move || {
+                    let mut popped = vec![];
+                    for i in 0..count {
+                        stack.push(thread * count + i);
+                        if i % 2 == 1 {
+                            popped.extend(stack.pop());
+                        }
+                    }
+                    popped
+                })
+            }).collect();
+            threads.into_iter().flat_map(|thread| thread.join().expect("join")).collect()
+        });
+
+        // Every element is popped exactly once.
+        let mut received: Vec<_> = popped.into_iter().chain(std::iter::from_fn(|| stack.pop())).collect();
+        received.sort();
+        assert_eq!(received, (0..4 * count).collect::<Vec<_>>());
+    }
+}
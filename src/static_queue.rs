@@ -0,0 +1,142 @@
+//! A bounded queue whose slots are stored inline, to be placed in a `static` without any heap
+//! allocation, for instance in firmware.
+
+use SendError;
+use array_queue::{self, CachePadded, Slot};
+use sync::atomic::AtomicUsize;
+
+/// An `ArrayQueue` of `N` elements stored in the structure itself, which can be created in a
+/// constant context.
+///
+/// `N` must be a power of two, which is checked at compile time.
+///
+/// ```
+/// # use lock_free_queue::StaticQueue;
+/// static EVENTS: StaticQueue<u32, 16> = StaticQueue::new();
+///
+/// EVENTS.try_enqueue(1).expect("enqueue");
+/// assert_eq!(EVENTS.dequeue(), Some(1));
+/// ```
+pub struct StaticQueue<T, const N: usize> {
+    slots: [Slot<T>; N],
+    /// Position of the next element to dequeue.
+    head: CachePadded<AtomicUsize>,
+    /// Position of the next element to enqueue.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// The values are only accessed by the thread which reserved their slot.
+unsafe impl<T: Send, const N: usize> Send for StaticQueue<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for StaticQueue<T, N> {}
+
+impl<T, const N: usize> StaticQueue<T, N> {
+    pub const fn new() -> Self {
+        const {
+            assert!(N.is_power_of_two(), "the capacity of a StaticQueue must be a power of two");
+        }
+        let mut slots = [const { Slot::new(0) }; N];
+        let mut position = 0;
+        while position < N {
+            slots[position] = Slot::new(position);
+            position += 1;
+        }
+        Self {
+            slots,
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Number of elements in the queue, which is only a snapshot with concurrent operations.
+    pub fn len(&self) -> usize {
+        array_queue::len(&self.slots, &self.head, &self.tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// Add an element, or give it back if the queue is full.
+    pub fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        array_queue::enqueue(&self.slots, &self.tail, value, usize::MAX)
+    }
+
+    /// Remove the first element, or return `None` if the queue is empty.
+    pub fn dequeue(&self) -> Option<T> {
+        array_queue::dequeue(&self.slots, &self.head, usize::MAX)
+    }
+}
+
+impl<T, const N: usize> Default for StaticQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for StaticQueue<T, N> {
+    fn drop(&mut self) {
+        while self.dequeue().is_some() {
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use SendError;
+    use super::StaticQueue;
+
+    static_queue!(QUEUE: usize; 8);
+
+    #[test]
+    fn test_static() {
+        let producers: Vec<_> = (0..2).map(|thread| {
+            thread::spawn(move || {
+                for i in 0..100 {
+                    let mut value = thread * 100 + i;
+                    while let Err(error) = QUEUE.try_enqueue(value) {
+                        value = error.into_inner();
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect();
+        let mut received = vec![];
+        while received.len() < 200 {
+            match QUEUE.dequeue() {
+                Some(element) => received.push(element),
+                None => thread::yield_now(),
+            }
+        }
+        for producer in producers {
+            producer.join().expect("join");
+        }
+        received.sort();
+        assert_eq!(received, (0..200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_full() {
+        let queue = StaticQueue::<_, 2>::new();
+        let value = Arc::new(());
+        queue.try_enqueue(value.clone()).expect("enqueue");
+        queue.try_enqueue(value.clone()).expect("enqueue");
+        assert!(queue.is_full());
+        assert!(matches!(queue.try_enqueue(value.clone()), Err(SendError::Full(_))));
+        drop(queue.dequeue());
+        assert_eq!(queue.len(), 1);
+        // The elements left are dropped with the queue.
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
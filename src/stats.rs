@@ -0,0 +1,57 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use SendError;
+
+/// A snapshot of the state of a bounded queue, including how many elements it lost and why.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub len: usize,
+    pub capacity: usize,
+    /// Elements dropped or rejected because the queue was full.
+    pub full: usize,
+    /// Elements rejected because the queue was closed.
+    pub closed: usize,
+    /// Elements rejected because their node could not be allocated.
+    pub alloc_failed: usize,
+}
+
+impl QueueStats {
+    /// Number of elements lost for any reason.
+    pub fn lost(&self) -> usize {
+        self.full + self.closed + self.alloc_failed
+    }
+}
+
+/// Counters of the elements lost by a queue, per reason.
+#[derive(Default)]
+pub(crate) struct Losses {
+    full: AtomicUsize,
+    closed: AtomicUsize,
+    alloc_failed: AtomicUsize,
+}
+
+impl Losses {
+    pub(crate) fn record<T>(&self, error: &SendError<T>) {
+        let counter =
+            match *error {
+                SendError::Full(_) => &self.full,
+                SendError::Closed(_) => &self.closed,
+                SendError::AllocFailed(_) | SendError::PoolExhausted(_) => &self.alloc_failed,
+            };
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn record_full(&self) {
+        self.full.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn stats(&self, len: usize, capacity: usize) -> QueueStats {
+        QueueStats {
+            len,
+            capacity,
+            full: self.full.load(Ordering::SeqCst),
+            closed: self.closed.load(Ordering::SeqCst),
+            alloc_failed: self.alloc_failed.load(Ordering::SeqCst),
+        }
+    }
+}
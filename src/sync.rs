@@ -0,0 +1,340 @@
+//! The atomics of the queue and of its nodes, and the spinning on them, which can be replaced by
+//! the ones of a concurrency checker:
+//!
+//! - loom checks every interleaving of a few operations:
+//!   RUSTFLAGS="--cfg loom" cargo test --release --lib sync::loom_tests
+//! - shuttle runs many threads and operations under randomized schedules:
+//!   RUSTFLAGS="--cfg shuttle" cargo test --release --lib sync::shuttle_tests
+//!
+//! Only these tests can run with a checker. The reclamation strategies other than `Leak`, the
+//! free list and the notifiers keep the atomics and the thread locals of the standard library,
+//! which the checkers do not model.
+//!
+//! With the `portable-atomic` feature, the atomics come from the portable-atomic crate instead
+//! of the standard library, and so does `Arc` on the targets where the standard library has none.
+//!
+//! On wasm32 without the `atomics` target feature, the atomics are compiled to plain loads and
+//! stores since there is a single thread, so the queue needs no separate non-atomic version. The
+//! crate is checked on both with:
+//!   cargo build --target wasm32-unknown-unknown
+//!   RUSTFLAGS="-C target-feature=+atomics,+bulk-memory" \
+//!       cargo +nightly build -Zbuild-std=std,panic_abort --target wasm32-unknown-unknown
+
+#[cfg(loom)]
+use core::sync::atomic::Ordering;
+
+#[cfg(loom)]
+pub(crate) use loom::hint;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+#[cfg(shuttle)]
+pub(crate) use shuttle::hint;
+#[cfg(shuttle)]
+pub(crate) use shuttle::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+#[cfg(not(any(loom, shuttle)))]
+pub(crate) use core::hint;
+#[cfg(not(any(loom, shuttle)))]
+pub(crate) use self::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+
+/// The atomics of the free list, the pools and the hazard pointers, which are never replaced by
+/// the ones of a checker.
+#[cfg(not(feature = "portable-atomic"))]
+pub(crate) use core::sync::atomic;
+#[cfg(feature = "portable-atomic")]
+pub(crate) use portable_atomic as atomic;
+
+#[cfg(target_has_atomic = "ptr")]
+pub(crate) use alloc::sync::Arc;
+#[cfg(all(feature = "portable-atomic", not(target_has_atomic = "ptr")))]
+pub(crate) use portable_atomic_util::Arc;
+
+/// Access to the value of an atomic through an exclusive reference, which needs no
+/// synchronization.
+pub(crate) trait Exclusive {
+    type Value;
+
+    fn load_mut(&mut self) -> Self::Value;
+
+    fn store_mut(&mut self, value: Self::Value);
+}
+
+macro_rules! exclusive {
+    ($atomic:ty, $value:ty $(, $parameter:ident)*) => {
+        impl<$($parameter)*> Exclusive for $atomic {
+            type Value = $value;
+
+            #[cfg(not(loom))]
+            fn load_mut(&mut self) -> $value {
+                *self.get_mut()
+            }
+
+            #[cfg(loom)]
+            fn load_mut(&mut self) -> $value {
+                // No other thread can access the atomic.
+                unsafe { self.unsync_load() }
+            }
+
+            #[cfg(not(loom))]
+            fn store_mut(&mut self, value: $value) {
+                *self.get_mut() = value;
+            }
+
+            #[cfg(loom)]
+            fn store_mut(&mut self, value: $value) {
+                self.store(value, Ordering::Relaxed);
+            }
+        }
+    };
+}
+
+exclusive!(AtomicBool, bool);
+exclusive!(AtomicPtr<T>, *mut T, T);
+exclusive!(AtomicUsize, usize);
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::model::Builder;
+    use loom::thread;
+    use std::sync::Arc;
+
+    use Queue;
+    use reclaim::Leak;
+
+    /// Check every interleaving of `function`, preempting the threads at most three times so
+    /// that the models finish.
+    fn model<F: Fn() + Sync + Send + 'static>(function: F) {
+        let mut builder = Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(function);
+    }
+
+    #[test]
+    fn test_two_producers_two_consumers() {
+        model(|| {
+            let queue = Arc::new(Queue::with_reclaim(Leak));
+            let threads: Vec<_> = (0..2).map(|i| {
+                let producer = {
+                    let queue = queue.clone();
+                    thread::spawn(move || queue.enqueue(i).expect("enqueue"))
+                };
+                let consumer = {
+                    let queue = queue.clone();
+                    thread::spawn(move || queue.dequeue())
+                };
+                (producer, consumer)
+            }).collect();
+            let mut received = vec![];
+            for (producer, consumer) in threads {
+                producer.join().expect("join");
+                received.extend(consumer.join().expect("join"));
+            }
+            received.extend(queue.dequeue());
+            received.extend(queue.dequeue());
+            received.sort();
+            assert_eq!(received, [0, 1]);
+            assert_eq!(queue.dequeue(), None);
+        });
+    }
+
+    #[test]
+    fn test_empty_race() {
+        model(|| {
+            let queue = Arc::new(Queue::with_reclaim(Leak));
+            let producer = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.enqueue(1).expect("enqueue"))
+            };
+            // The element is either dequeued by the consumer or left in the queue.
+            let dequeued = queue.dequeue();
+            producer.join().expect("join");
+            match dequeued {
+                Some(element) => assert_eq!(element, 1),
+                None => assert_eq!(queue.dequeue(), Some(1)),
+            }
+            assert!(queue.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_tail_lag() {
+        model(|| {
+            // The second producer finds the tail behind the batch of the first one, and both
+            // batches stay contiguous.
+            let queue = Arc::new(Queue::with_reclaim(Leak));
+            let producer = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.enqueue_batch(vec![0, 1]).expect("enqueue"))
+            };
+            queue.enqueue_batch(vec![2, 3]).expect("enqueue");
+            producer.join().expect("join");
+            let first = queue.dequeue().expect("dequeue");
+            assert_eq!(queue.dequeue(), Some(first + 1));
+            let second = queue.dequeue().expect("dequeue");
+            assert_eq!(queue.dequeue(), Some(second + 1));
+            assert_eq!(first + second, 2);
+            assert_eq!(queue.dequeue(), None);
+        });
+    }
+
+    #[test]
+    fn test_peek_while_dequeuing() {
+        model(|| {
+            let queue = Arc::new(Queue::with_reclaim(Leak));
+            queue.enqueue(1).expect("enqueue");
+            let consumer = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.dequeue())
+            };
+            // The element is seen until it is taken.
+            let peeked = queue.peek();
+            assert!(peeked.is_none() || peeked == Some(1));
+            assert_eq!(consumer.join().expect("join"), Some(1));
+            assert_eq!(queue.peek(), None);
+        });
+    }
+
+    #[test]
+    fn test_batch_while_dequeuing() {
+        model(|| {
+            // The links of the chain and the length are updated relaxed before the CAS linking
+            // the chain, which publishes them: a consumer reaching the chain sees all of it.
+            let queue = Arc::new(Queue::with_reclaim(Leak));
+            let producer = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.enqueue_batch(vec![0, 1]).expect("enqueue"))
+            };
+            let first = queue.dequeue();
+            // Reading the length also makes loom run the producer before the dequeue: it only keeps
+            // the last access of each atomic, so it misses the race between the loads of the
+            // consumer and the CAS of the producer, which loads the same link just before.
+            let len = queue.len();
+            if first.is_some() {
+                assert_eq!(first, Some(0));
+                assert_eq!(len, 1);
+                assert_eq!(queue.dequeue(), Some(1));
+            }
+            producer.join().expect("join");
+            assert_eq!(queue.len(), if first.is_some() { 0 } else { 2 });
+        });
+    }
+
+    #[test]
+    fn test_abort_while_dequeuing() {
+        model(|| {
+            // The nodes put back by the abort are linked relaxed, and published by the CAS of the
+            // head: the consumer always finds an element.
+            let queue = Arc::new(Queue::with_reclaim(Leak));
+            queue.enqueue(1).expect("enqueue");
+            queue.enqueue(2).expect("enqueue");
+            let consumer = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.claim().map(|claim| *claim))
+            };
+            let dequeued = queue.dequeue().expect("dequeue");
+            consumer.join().expect("join").expect("claim");
+            assert_eq!(queue.len(), 1);
+            // The aborted element is dequeued again, either before or after.
+            let mut received = [dequeued, queue.dequeue().expect("dequeue")];
+            received.sort();
+            assert_eq!(received, [1, 2]);
+            assert_eq!(queue.dequeue(), None);
+        });
+    }
+}
+
+#[cfg(all(test, shuttle))]
+mod shuttle_tests {
+    use shuttle::{check_pct, check_random, thread};
+    use std::sync::Arc;
+
+    use Queue;
+    use reclaim::Leak;
+
+    const PRODUCERS: usize = 4;
+    const CONSUMERS: usize = 2;
+    const ELEMENTS: usize = 25;
+
+    /// The producer of an element and its index.
+    type Element = (usize, usize);
+
+    /// Run the producers and the consumers, each consumer attempting to dequeue as many elements
+    /// as a producer enqueues, and return what each consumer received and what was left.
+    fn run<P, C>(produce: P, consume: C) -> (Vec<Vec<Element>>, Vec<Element>)
+        where P: Fn(&Queue<Element, Leak>, usize) + Send + Sync + 'static,
+              C: Fn(&Queue<Element, Leak>) -> Option<Element> + Send + Sync + 'static,
+    {
+        let queue = Arc::new(Queue::with_reclaim(Leak));
+        let produce = Arc::new(produce);
+        let consume = Arc::new(consume);
+        let producers: Vec<_> = (0..PRODUCERS).map(|producer| {
+            let queue = queue.clone();
+            let produce = produce.clone();
+            thread::spawn(move || produce(&queue, producer))
+        }).collect();
+        let consumers: Vec<_> = (0..CONSUMERS).map(|_| {
+            let queue = queue.clone();
+            let consume = consume.clone();
+            thread::spawn(move || (0..ELEMENTS).filter_map(|_| consume(&queue)).collect::<Vec<_>>())
+        }).collect();
+        for producer in producers {
+            producer.join().expect("join");
+        }
+        let received = consumers.into_iter().map(|consumer| consumer.join().expect("join")).collect();
+        let left = queue.try_iter().collect();
+        (received, left)
+    }
+
+    /// Check that every element was received exactly once.
+    fn check_conservation(received: &[Vec<Element>], left: &[Element]) {
+        let mut elements: Vec<_> = received.iter().flatten().chain(left).cloned().collect();
+        elements.sort();
+        let expected: Vec<_> = (0..PRODUCERS).flat_map(|producer| (0..ELEMENTS).map(move |i| (producer, i))).collect();
+        assert_eq!(elements, expected);
+    }
+
+    fn enqueue_and_dequeue() {
+        let (received, left) = run(|queue, producer| {
+            for i in 0..ELEMENTS {
+                queue.enqueue((producer, i)).expect("enqueue");
+            }
+        }, Queue::dequeue);
+        check_conservation(&received, &left);
+        // Each consumer receives the elements of a producer in the order they were enqueued.
+        for elements in received.iter().chain(Some(&left)) {
+            let mut next = [0; PRODUCERS];
+            for &(producer, i) in elements {
+                assert!(i >= next[producer]);
+                next[producer] = i + 1;
+            }
+        }
+    }
+
+    fn batches_and_claims() {
+        let (received, left) = run(|queue, producer| {
+            for batch in 0..ELEMENTS / 5 {
+                queue.enqueue_batch((0..5).map(|i| (producer, batch * 5 + i))).expect("enqueue");
+            }
+        }, |queue| {
+            let claim = queue.claim()?;
+            // Give back the odd elements once, to race with the other consumer.
+            if claim.1 % 2 == 1 {
+                claim.abort();
+                return queue.dequeue();
+            }
+            Some(claim.commit())
+        });
+        check_conservation(&received, &left);
+    }
+
+    #[test]
+    fn test_enqueue_and_dequeue() {
+        check_random(enqueue_and_dequeue, 1_000);
+        check_pct(enqueue_and_dequeue, 1_000, 3);
+    }
+
+    #[test]
+    fn test_batches_and_claims() {
+        check_random(batches_and_claims, 1_000);
+        check_pct(batches_and_claims, 1_000, 3);
+    }
+}
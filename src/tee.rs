@@ -0,0 +1,158 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use {LossyQueue, Queue, SendError};
+
+/// A queue which can receive the elements sent through a `Tee`.
+pub trait TeeTarget<T>: Send + Sync {
+    /// Add an element, giving it back if the queue is full or closed.
+    fn try_enqueue(&self, value: T) -> Result<(), SendError<T>>;
+
+    /// Count an element which was not mirrored in the queue because of `error`.
+    fn record_loss(&self, _error: &SendError<T>) {
+    }
+}
+
+impl<T: Send> TeeTarget<T> for Queue<T> {
+    fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        Queue::try_enqueue(self, value)
+    }
+
+    fn record_loss(&self, error: &SendError<T>) {
+        Queue::record_loss(self, error);
+    }
+}
+
+impl<T: Send> TeeTarget<T> for LossyQueue<T> {
+    fn try_enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        LossyQueue::try_enqueue(self, value)
+    }
+
+    fn record_loss(&self, error: &SendError<T>) {
+        LossyQueue::record_loss(self, error);
+    }
+}
+
+/// What a `Tee` does when its secondary queue is full (or fails otherwise).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MirrorPolicy {
+    /// Only enqueue in the primary queue and count the element as skipped, and as lost by the
+    /// secondary queue.
+    Skip,
+    /// Retry until the secondary queue has room.
+    Wait,
+    /// Enqueue in neither queue and give the element back.
+    Reject,
+}
+
+/// A producer wrapper enqueuing every element in a primary queue and a copy of it in a secondary
+/// queue (e.g. for audit or sampling).
+pub struct Tee<T> {
+    primary: Arc<dyn TeeTarget<T>>,
+    secondary: Arc<dyn TeeTarget<T>>,
+    policy: MirrorPolicy,
+    skipped: AtomicUsize,
+}
+
+impl<T: Clone> Tee<T> {
+    pub fn new(primary: Arc<dyn TeeTarget<T>>, secondary: Arc<dyn TeeTarget<T>>, policy: MirrorPolicy) -> Self {
+        Self {
+            primary,
+            secondary,
+            policy,
+            skipped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Enqueue the element in both queues.
+    ///
+    /// The element is given back if the primary queue fails, or if the secondary queue fails
+    /// with the `Reject` policy. With the `Wait` policy, only a full secondary queue is retried.
+    pub fn enqueue(&self, value: T) -> Result<(), SendError<T>> {
+        let mut copy = value.clone();
+        loop {
+            match self.secondary.try_enqueue(copy) {
+                Ok(()) => break,
+                Err(error) => {
+                    match self.policy {
+                        MirrorPolicy::Skip => {
+                            self.skipped.fetch_add(1, Ordering::SeqCst);
+                            self.secondary.record_loss(&error);
+                            break;
+                        },
+                        MirrorPolicy::Wait if error.is_full() => thread::yield_now(),
+                        MirrorPolicy::Wait | MirrorPolicy::Reject => return Err(error),
+                    }
+                    copy = error.into_inner();
+                },
+            }
+        }
+        self.primary.try_enqueue(value)
+    }
+
+    /// Number of elements which were not mirrored because the secondary queue was full.
+    pub fn skipped(&self) -> usize {
+        self.skipped.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use {LossyQueue, Queue, SendError};
+    use super::{MirrorPolicy, Tee};
+
+    #[test]
+    fn test_skip() {
+        let primary = Arc::new(Queue::new());
+        let audit = Arc::new(LossyQueue::new(2));
+        let tee = Tee::new(primary.clone(), audit.clone(), MirrorPolicy::Skip);
+        for i in 0..3 {
+            tee.enqueue(i).expect("enqueue");
+        }
+        assert_eq!(tee.skipped(), 1);
+        assert_eq!(audit.dropped(), 0);
+        assert_eq!(audit.stats().full, 1);
+        assert_eq!(audit.dequeue(), Some(0));
+        assert_eq!(audit.dequeue(), Some(1));
+        assert_eq!(audit.dequeue(), None);
+        for i in 0..3 {
+            assert_eq!(primary.dequeue(), Some(i));
+        }
+    }
+
+    #[test]
+    fn test_reject() {
+        let primary = Arc::new(Queue::new());
+        let audit = Arc::new(LossyQueue::new(1));
+        let tee = Tee::new(primary.clone(), audit.clone(), MirrorPolicy::Reject);
+        assert_eq!(tee.enqueue(1), Ok(()));
+        assert_eq!(tee.enqueue(2), Err(SendError::Full(2)));
+        assert_eq!(primary.dequeue(), Some(1));
+        assert_eq!(primary.dequeue(), None);
+    }
+
+    #[test]
+    fn test_wait() {
+        let primary = Arc::new(Queue::new());
+        let audit = Arc::new(LossyQueue::new(1));
+        let tee = Tee::new(primary.clone(), audit.clone(), MirrorPolicy::Wait);
+        tee.enqueue(1).expect("enqueue");
+        let auditor = {
+            let audit = audit.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                audit.dequeue()
+            })
+        };
+        tee.enqueue(2).expect("enqueue");
+        assert_eq!(auditor.join().expect("join"), Some(1));
+        assert_eq!(audit.dequeue(), Some(2));
+        assert_eq!(primary.dequeue(), Some(1));
+        assert_eq!(primary.dequeue(), Some(2));
+    }
+}
@@ -0,0 +1,249 @@
+//! A queue of enum messages keeping one lane per variant, so that consumers can dequeue a specific
+//! kind of message without going through the others.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use Queue;
+
+/// An enum whose variants each get their own lane in a `VariantQueue`.
+///
+/// Implemented by the `variants!` macro.
+pub trait Variants: Sized {
+    /// Number of variants.
+    const COUNT: usize;
+
+    /// Index of the variant of this message, lower than `COUNT`.
+    fn variant(&self) -> usize;
+}
+
+/// The payload of a variant of the enum `E`.
+///
+/// Implemented by the `variants!` macro.
+pub trait Variant<E>: Sized {
+    const INDEX: usize;
+
+    /// Extract the payload from the message, if it is of this variant.
+    fn from_message(message: E) -> Option<Self>;
+}
+
+/// A queue of enum messages where each variant has its own lane.
+///
+/// `dequeue_variant()` only looks at the lane of the requested variant, while `dequeue()` returns
+/// the messages of every variant in the order of their arrival. When several consumers call
+/// `dequeue()` concurrently, or producers enqueue concurrently, this order is only approximate.
+pub struct VariantQueue<E> {
+    lanes: Vec<Queue<(u64, E)>>,
+    sequence: AtomicU64,
+}
+
+impl<E: Variants> VariantQueue<E> {
+    pub fn new() -> Self {
+        Self {
+            lanes: (0..E::COUNT).map(|_| Queue::new()).collect(),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enqueue(&self, message: E) {
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+        self.lanes[message.variant()].enqueue((sequence, message)).expect("enqueue, since the queue is never closed");
+    }
+
+    /// Dequeue the oldest message, whatever its variant.
+    pub fn dequeue(&self) -> Option<E> {
+        // Claim the first message of every lane to find the oldest one: the others are given back
+        // to the front of their lane when their claim is dropped.
+        let mut claims: Vec<_> = self.lanes.iter()
+            .filter_map(Queue::claim)
+            .collect();
+        let index = claims.iter()
+            .enumerate()
+            .min_by_key(|(_, claim)| claim.0)
+            .map(|(index, _)| index)?;
+        let (_, message) = claims.swap_remove(index).commit();
+        Some(message)
+    }
+
+    /// Dequeue the oldest message of the variant `V`, leaving the other variants in the queue.
+    pub fn dequeue_variant<V: Variant<E>>(&self) -> Option<V> {
+        self.lanes[V::INDEX].dequeue()
+            .and_then(|(_, message)| V::from_message(message))
+    }
+}
+
+impl<E: Variants> Default for VariantQueue<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Declare an enum whose variants each wrap a distinct payload type, and implement `Variants`
+/// for it and `Variant` for its payloads, so that it can be used in a `VariantQueue`.
+///
+/// ```
+/// # #[macro_use] extern crate lock_free_queue;
+/// # use lock_free_queue::variant::VariantQueue;
+/// pub struct Resize(u32, u32);
+/// pub struct Quit;
+///
+/// variants! {
+///     pub enum Event {
+///         Resize(Resize),
+///         Quit(Quit),
+///     }
+/// }
+///
+/// # fn main() {
+/// let queue = VariantQueue::new();
+/// queue.enqueue(Event::Resize(Resize(800, 600)));
+/// queue.enqueue(Event::Quit(Quit));
+/// assert!(queue.dequeue_variant::<Quit>().is_some());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! variants {
+    ($(#[$attribute:meta])* $visibility:vis enum $name:ident { $($variant:ident($type:ty)),* $(,)? }) => {
+        $(#[$attribute])*
+        $visibility enum $name {
+            $($variant($type)),*
+        }
+
+        impl $crate::variant::Variants for $name {
+            const COUNT: usize = [$(stringify!($variant)),*].len();
+
+            fn variant(&self) -> usize {
+                match *self {
+                    $($name::$variant(_) => <$type as $crate::variant::Variant<$name>>::INDEX),*
+                }
+            }
+        }
+
+        variants!(@payloads $name, 0, $($variant($type)),*);
+    };
+    (@payloads $name:ident, $index:expr, $variant:ident($type:ty) $(, $rest:ident($rest_type:ty))*) => {
+        impl $crate::variant::Variant<$name> for $type {
+            const INDEX: usize = $index;
+
+            #[allow(unreachable_patterns)]
+            fn from_message(message: $name) -> Option<Self> {
+                match message {
+                    $name::$variant(payload) => Some(payload),
+                    _ => None,
+                }
+            }
+        }
+
+        variants!(@payloads $name, $index + 1, $($rest($rest_type)),*);
+    };
+    (@payloads $name:ident, $index:expr,) => {};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::VariantQueue;
+
+    #[derive(Debug, PartialEq)]
+    pub struct Resize(u32, u32);
+    #[derive(Debug, PartialEq)]
+    pub struct Key(char);
+
+    variants! {
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            Resize(Resize),
+            Key(Key),
+            Tick(u64),
+        }
+    }
+
+    #[test]
+    fn test_dequeue_variant() {
+        let queue = VariantQueue::new();
+        queue.enqueue(Event::Key(Key('a')));
+        queue.enqueue(Event::Resize(Resize(800, 600)));
+        queue.enqueue(Event::Tick(1));
+        queue.enqueue(Event::Key(Key('b')));
+        assert_eq!(queue.dequeue_variant::<Resize>(), Some(Resize(800, 600)));
+        assert_eq!(queue.dequeue_variant::<Resize>(), None);
+        assert_eq!(queue.dequeue_variant::<Key>(), Some(Key('a')));
+        assert_eq!(queue.dequeue(), Some(Event::Tick(1)));
+        assert_eq!(queue.dequeue(), Some(Event::Key(Key('b'))));
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_arrival_order() {
+        let queue = VariantQueue::new();
+        queue.enqueue(Event::Tick(1));
+        queue.enqueue(Event::Key(Key('a')));
+        queue.enqueue(Event::Tick(2));
+        queue.enqueue(Event::Resize(Resize(1, 2)));
+        queue.enqueue(Event::Key(Key('b')));
+        let mut received = vec![];
+        while let Some(event) = queue.dequeue() {
+            received.push(event);
+        }
+        assert_eq!(received, [
+            Event::Tick(1),
+            Event::Key(Key('a')),
+            Event::Tick(2),
+            Event::Resize(Resize(1, 2)),
+            Event::Key(Key('b')),
+        ]);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_multithread() {
+        let queue = Arc::new(VariantQueue::new());
+        for i in 0..10_000 {
+            queue.enqueue(Event::Tick(i));
+            queue.enqueue(Event::Key(Key('k')));
+        }
+
+        let handles: Vec<_> = (0..4).map(|thread| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut ticks = vec![];
+                let mut keys = 0;
+                loop {
+                    // Half of the consumers only want the ticks.
+                    let event =
+                        if thread % 2 == 0 {
+                            queue.dequeue_variant::<u64>().map(Event::Tick)
+                        }
+                        else {
+                            queue.dequeue()
+                        };
+                    match event {
+                        Some(Event::Tick(tick)) => ticks.push(tick),
+                        Some(_) => keys += 1,
+                        None => break,
+                    }
+                }
+                (ticks, keys)
+            })
+        }).collect();
+
+        let mut ticks = vec![];
+        let mut keys = 0;
+        for handle in handles {
+            let (thread_ticks, thread_keys) = handle.join().expect("join");
+            ticks.extend(thread_ticks);
+            keys += thread_keys;
+        }
+        // The consumers of ticks can stop before the others: drain what is left.
+        while let Some(event) = queue.dequeue() {
+            match event {
+                Event::Tick(tick) => ticks.push(tick),
+                _ => keys += 1,
+            }
+        }
+        ticks.sort();
+        assert_eq!(ticks, (0..10_000).collect::<Vec<_>>());
+        assert_eq!(keys, 10_000);
+    }
+}
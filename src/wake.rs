@@ -0,0 +1,645 @@
+//! How waiting threads go to sleep and are woken up again.
+//!
+//! Every blocking operation of the crate is built on a `WakeBackend`, selected when the queue is
+//! created, so that supporting a new platform or runtime only requires implementing this trait.
+//! Asynchronous tasks are always woken through their `Waker`.
+//!
+//! Without the `std` feature, nothing can wait, so there is nothing to wake up either.
+//!
+//! On wasm32 without the `atomics` target feature, there is a single thread: the default backend
+//! is then `NoThreadBackend`, since parking the only thread would never end.
+
+#[cfg(feature = "std")]
+use std::cell::UnsafeCell;
+#[cfg(feature = "std")]
+use std::ptr;
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "std")]
+use std::task::Waker;
+#[cfg(feature = "std")]
+use std::thread::{self, Thread};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// A futex-like primitive: threads wait while an atomic holds an expected value.
+#[cfg(feature = "std")]
+pub trait WakeBackend: Send + Sync {
+    /// Block the current thread while `state` holds `expected`, until `wake_all()` is called on
+    /// the same `state` or the deadline passes.
+    ///
+    /// The check of `state` must be atomic with respect to `wake_all()` so that no wake up is
+    /// lost. Spurious returns are allowed.
+    fn wait(&self, state: &AtomicU32, expected: u32, deadline: Option<Instant>);
+
+    /// Wake all the threads blocked in `wait()` on `state`.
+    fn wake_all(&self, state: &AtomicU32);
+}
+
+/// Backend using the thread parking of the standard library. This is the default one.
+///
+/// Unlike the rest of the notifications, it takes a lock, to keep the list of the parked threads:
+/// `wake_all()` is only called when a thread is listening, and the waiting threads are about to
+/// sleep anyway, but a producer notifying them can then be blocked by another thread. On Linux,
+/// `FutexBackend` needs no lock.
+#[cfg(feature = "std")]
+pub struct ParkBackend {
+    waiters: Mutex<Vec<(usize, Thread)>>,
+}
+
+#[cfg(feature = "std")]
+impl ParkBackend {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for ParkBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl WakeBackend for ParkBackend {
+    fn wait(&self, state: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+        let address = ptr::from_ref(state).addr();
+        {
+            let mut waiters = self.waiters.lock().expect("lock");
+            if state.load(Ordering::SeqCst) != expected {
+                return;
+            }
+            waiters.push((address, thread::current()));
+        }
+        match deadline {
+            // A wake up between the unlock and the park is not lost thanks to the park token.
+            Some(deadline) => thread::park_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => thread::park(),
+        }
+        let id = thread::current().id();
+        self.waiters.lock().expect("lock")
+            .retain(|&(waiter_address, ref thread)| waiter_address != address || thread.id() != id);
+    }
+
+    fn wake_all(&self, state: &AtomicU32) {
+        let address = ptr::from_ref(state).addr();
+        let mut waiters = self.waiters.lock().expect("lock");
+        waiters.retain(|&(waiter_address, ref thread)| {
+            if waiter_address == address {
+                thread.unpark();
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+}
+
+/// Backend calling the futex system call directly.
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+pub struct FutexBackend;
+
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+impl WakeBackend for FutexBackend {
+    fn wait(&self, state: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+        let timeout = deadline.map(|deadline| {
+            let duration = deadline.saturating_duration_since(Instant::now());
+            libc::timespec {
+                tv_sec: duration.as_secs() as libc::time_t,
+                tv_nsec: duration.subsec_nanos() as libc::c_long,
+            }
+        });
+        if let Some(libc::timespec { tv_sec: 0, tv_nsec: 0 }) = timeout {
+            return;
+        }
+        let timeout = timeout.as_ref().map_or(ptr::null(), ptr::from_ref);
+        unsafe {
+            libc::syscall(libc::SYS_futex, ptr::from_ref(state), libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                expected, timeout);
+        }
+    }
+
+    fn wake_all(&self, state: &AtomicU32) {
+        unsafe {
+            libc::syscall(libc::SYS_futex, ptr::from_ref(state), libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                i32::MAX);
+        }
+    }
+}
+
+/// Backend of the programs with a single thread, like the ones built for wasm32 without the
+/// `atomics` target feature: no other thread can notify, so waiting returns immediately.
+#[cfg(feature = "std")]
+pub struct NoThreadBackend;
+
+#[cfg(feature = "std")]
+impl WakeBackend for NoThreadBackend {
+    fn wait(&self, _state: &AtomicU32, _expected: u32, _deadline: Option<Instant>) {
+    }
+
+    fn wake_all(&self, _state: &AtomicU32) {
+    }
+}
+
+#[cfg(all(feature = "std", not(all(target_arch = "wasm32", not(target_feature = "atomics")))))]
+static DEFAULT_BACKEND: ParkBackend = ParkBackend::new();
+#[cfg(all(feature = "std", target_arch = "wasm32", not(target_feature = "atomics")))]
+static DEFAULT_BACKEND: NoThreadBackend = NoThreadBackend;
+
+/// No thread is accessing the waker of a slot.
+#[cfg(feature = "std")]
+const IDLE: usize = 0;
+/// The owner of the slot is replacing its waker.
+#[cfg(feature = "std")]
+const REGISTERING: usize = 1;
+/// A notification is taking the waker of the slot.
+#[cfg(feature = "std")]
+const WAKING: usize = 2;
+
+/// The waker of one future, reused by every poll of this future.
+///
+/// The waker is guarded by `state`, like the `AtomicWaker` of the futures crate: the owner of the
+/// slot is the only one replacing it, so a notification taking it concurrently only has to tell
+/// the owner to wake the task itself.
+#[cfg(feature = "std")]
+struct WakerSlot {
+    /// Whether a future currently uses this slot.
+    owned: AtomicBool,
+    /// Whether the slot is counted in `Registry::waiting`.
+    armed: AtomicBool,
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+    next: *mut WakerSlot,
+}
+
+#[cfg(feature = "std")]
+impl WakerSlot {
+    /// Only called by the owner of the slot.
+    fn store(&self, waker: &Waker) {
+        match self.state.compare_exchange(IDLE, REGISTERING, Ordering::Acquire, Ordering::Acquire) {
+            Ok(_) => {
+                let current = unsafe { &mut *self.waker.get() };
+                if !current.as_ref().is_some_and(|current| current.will_wake(waker)) {
+                    *current = Some(waker.clone());
+                }
+                if self.state.compare_exchange(REGISTERING, IDLE, Ordering::AcqRel, Ordering::Acquire).is_err() {
+                    // A notification came in the meantime and left the waker to us.
+                    let waker = current.take();
+                    self.state.store(IDLE, Ordering::Release);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            },
+            // A notification is taking the previous waker: the task is polled again anyway.
+            Err(_) => waker.wake_by_ref(),
+        }
+    }
+
+    fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            IDLE => {
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            },
+            // The owner is storing its waker and will wake it when it sees WAKING.
+            _ => None,
+        }
+    }
+}
+
+/// The waker slots of a notifier. The slots are only freed with the registry, and are reused by
+/// the next futures when theirs are dropped, so their number is the largest number of futures
+/// which waited at the same time.
+#[cfg(feature = "std")]
+struct Registry {
+    /// Number of the armed slots, so that notifying is cheap when no task is waiting.
+    waiting: AtomicUsize,
+    /// Top of the stack of the slots.
+    slots: AtomicPtr<WakerSlot>,
+}
+
+#[cfg(feature = "std")]
+unsafe impl Send for Registry {}
+#[cfg(feature = "std")]
+unsafe impl Sync for Registry {}
+
+#[cfg(feature = "std")]
+impl Registry {
+    fn claim(&self) -> *const WakerSlot {
+        let mut slot = self.slots.load(Ordering::Acquire);
+        while !slot.is_null() {
+            let current = unsafe { &*slot };
+            if current.owned.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                return slot;
+            }
+            slot = current.next;
+        }
+        let slot = Box::into_raw(Box::new(WakerSlot {
+            owned: AtomicBool::new(true),
+            armed: AtomicBool::new(false),
+            state: AtomicUsize::new(IDLE),
+            waker: UnsafeCell::new(None),
+            next: ptr::null_mut(),
+        }));
+        // The slots are never removed, so the top cannot be reused between the load and the CAS.
+        let mut top = self.slots.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*slot).next = top };
+            match self.slots.compare_exchange_weak(top, slot, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return slot,
+                Err(current) => top = current,
+            }
+        }
+    }
+
+    fn notify(&self) {
+        let mut slot = self.slots.load(Ordering::Acquire);
+        while !slot.is_null() {
+            let current = unsafe { &*slot };
+            if current.armed.load(Ordering::SeqCst) && current.armed.swap(false, Ordering::SeqCst) {
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                if let Some(waker) = current.take() {
+                    waker.wake();
+                }
+            }
+            slot = current.next;
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Registry {
+    fn drop(&mut self) {
+        let mut slot = *self.slots.get_mut();
+        while !slot.is_null() {
+            let current = unsafe { Box::from_raw(slot) };
+            slot = current.next;
+        }
+    }
+}
+
+/// The waker slot of a future, kept between its polls so that polling it again replaces its waker
+/// instead of adding one. Dropping the future gives the slot back.
+#[cfg(feature = "std")]
+pub(crate) struct Registration {
+    /// Keeps the slot alive, even if the notifier is dropped first.
+    registry: Option<Arc<Registry>>,
+    slot: *const WakerSlot,
+}
+
+// The slot is only accessed through its atomics, and its waker as described in WakerSlot.
+#[cfg(feature = "std")]
+unsafe impl Send for Registration {}
+#[cfg(feature = "std")]
+unsafe impl Sync for Registration {}
+
+#[cfg(feature = "std")]
+impl Default for Registration {
+    fn default() -> Self {
+        Self {
+            registry: None,
+            slot: ptr::null(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(ref registry) = self.registry {
+            let slot = unsafe { &*self.slot };
+            if slot.armed.swap(false, Ordering::SeqCst) {
+                registry.waiting.fetch_sub(1, Ordering::SeqCst);
+            }
+            drop(slot.take());
+            slot.owned.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Wake up mechanism shared by the blocking and asynchronous operations.
+///
+/// A waiter calls `listen()` before checking its condition, so that a notification happening
+/// between the check and the wait is not lost. Notifying is cheap when nobody is listening.
+///
+/// Each waiting future has its own waker slot, which `notify()` empties: the registry of the slots
+/// is only allocated once a future waits.
+#[cfg(feature = "std")]
+pub(crate) struct Notifier {
+    epoch: AtomicU32,
+    listeners: AtomicUsize,
+    /// Created by `Arc::into_raw()` on the first registration.
+    registry: AtomicPtr<Registry>,
+    backend: Option<Arc<dyn WakeBackend>>,
+}
+
+#[cfg(feature = "std")]
+impl Notifier {
+    pub(crate) fn new(backend: Option<Arc<dyn WakeBackend>>) -> Self {
+        Self {
+            epoch: AtomicU32::new(0),
+            listeners: AtomicUsize::new(0),
+            registry: AtomicPtr::new(ptr::null_mut()),
+            backend,
+        }
+    }
+
+    pub(crate) fn listen(&self) -> Listener<'_> {
+        // Announce ourselves before reading the epoch so that the notifier either sees us or
+        // changes the epoch after we read it.
+        self.listeners.fetch_add(1, Ordering::SeqCst);
+        Listener {
+            notifier: self,
+            token: self.epoch.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Wake the task on the next notification. The condition must be checked again after the
+    /// registration.
+    ///
+    /// Registering again with the same `registration` before a notification replaces the waker:
+    /// the task is woken once.
+    pub(crate) fn register(&self, registration: &mut Registration, waker: &Waker) {
+        if registration.registry.is_none() {
+            let registry = self.registry();
+            registration.slot = registry.claim();
+            registration.registry = Some(registry);
+        }
+        let registry = registration.registry.as_ref().expect("registry");
+        let slot = unsafe { &*registration.slot };
+        slot.store(waker);
+        if !slot.armed.swap(true, Ordering::SeqCst) {
+            registry.waiting.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn registry(&self) -> Arc<Registry> {
+        let mut registry = self.registry.load(Ordering::Acquire);
+        if registry.is_null() {
+            let new = Arc::into_raw(Arc::new(Registry {
+                waiting: AtomicUsize::new(0),
+                slots: AtomicPtr::new(ptr::null_mut()),
+            })).cast_mut();
+            registry = match self.registry.compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => new,
+                Err(current) => {
+                    drop(unsafe { Arc::from_raw(new) });
+                    current
+                },
+            };
+        }
+        unsafe {
+            Arc::increment_strong_count(registry);
+            Arc::from_raw(registry)
+        }
+    }
+
+    pub(crate) fn notify(&self) {
+        if self.listeners.load(Ordering::SeqCst) > 0 {
+            self.epoch.fetch_add(1, Ordering::SeqCst);
+            self.backend().wake_all(&self.epoch);
+        }
+        let registry = self.registry.load(Ordering::Acquire);
+        if !registry.is_null() {
+            let registry = unsafe { &*registry };
+            if registry.waiting.load(Ordering::SeqCst) > 0 {
+                registry.notify();
+            }
+        }
+    }
+
+    /// Number of the waker slots and of the armed ones.
+    #[cfg(test)]
+    pub(crate) fn registrations(&self) -> (usize, usize) {
+        let registry = self.registry.load(Ordering::Acquire);
+        if registry.is_null() {
+            return (0, 0);
+        }
+        let registry = unsafe { &*registry };
+        let mut slots = 0;
+        let mut slot = registry.slots.load(Ordering::Acquire);
+        while !slot.is_null() {
+            slots += 1;
+            slot = unsafe { (*slot).next };
+        }
+        (slots, registry.waiting.load(Ordering::SeqCst))
+    }
+
+    pub(crate) fn backend_handle(&self) -> Option<Arc<dyn WakeBackend>> {
+        self.backend.clone()
+    }
+
+    fn backend(&self) -> &dyn WakeBackend {
+        match self.backend {
+            Some(ref backend) => &**backend,
+            None => &DEFAULT_BACKEND,
+        }
+    }
+}
+
+/// A registration to the notifications, which lasts until it is dropped.
+#[cfg(feature = "std")]
+pub(crate) struct Listener<'a> {
+    notifier: &'a Notifier,
+    token: u32,
+}
+
+#[cfg(feature = "std")]
+impl<'a> Listener<'a> {
+    /// Block until a notification happening after the call to `listen()`, or until the
+    /// deadline. Can return spuriously.
+    pub(crate) fn wait(self, deadline: Option<Instant>) {
+        self.notifier.backend().wait(&self.notifier.epoch, self.token, deadline);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> Drop for Listener<'a> {
+    fn drop(&mut self) {
+        self.notifier.listeners.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        let registry = *self.registry.get_mut();
+        if !registry.is_null() {
+            // The futures still registered keep the registry alive.
+            drop(unsafe { Arc::from_raw(registry) });
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+/// Without the `std` feature, no thread can wait for the notifications.
+#[cfg(not(feature = "std"))]
+#[derive(Default)]
+pub(crate) struct Notifier {}
+
+#[cfg(not(feature = "std"))]
+impl Notifier {
+    pub(crate) fn notify(&self) {
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{NoThreadBackend, Notifier, ParkBackend, Registration, WakeBackend};
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    use super::FutexBackend;
+
+    /// A user-provided backend, as could be written for a platform without thread parking.
+    struct SpinBackend {
+        waits: AtomicUsize,
+    }
+
+    impl WakeBackend for SpinBackend {
+        fn wait(&self, state: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+            self.waits.fetch_add(1, Ordering::SeqCst);
+            while state.load(Ordering::SeqCst) == expected && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+                thread::yield_now();
+            }
+        }
+
+        fn wake_all(&self, _state: &AtomicU32) {
+        }
+    }
+
+    /// A waker counting how many times it was woken.
+    struct CountingWaker {
+        wakes: AtomicUsize,
+    }
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wakes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn check_backend(backend: Arc<dyn WakeBackend>) {
+        let notifier = Arc::new(Notifier::new(Some(backend)));
+        let flag = Arc::new(AtomicUsize::new(0));
+
+        // Nobody notifies: this must return once the deadline passes.
+        let deadline = Instant::now() + Duration::from_millis(20);
+        while Instant::now() < deadline {
+            notifier.listen().wait(Some(deadline));
+        }
+
+        let waiter = {
+            let notifier = notifier.clone();
+            let flag = flag.clone();
+            thread::spawn(move || {
+                loop {
+                    let listener = notifier.listen();
+                    if flag.load(Ordering::SeqCst) == 1 {
+                        break;
+                    }
+                    listener.wait(None);
+                }
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+        flag.store(1, Ordering::SeqCst);
+        notifier.notify();
+        waiter.join().expect("join");
+    }
+
+    #[test]
+    fn test_wakers() {
+        let count = if cfg!(miri) { 20 } else { 1_000 };
+        let notifier = Notifier::default();
+        let counter = Arc::new(CountingWaker {
+            wakes: AtomicUsize::new(0),
+        });
+        let waker = Waker::from(counter.clone());
+
+        // Registering again replaces the waker.
+        let mut registration = Registration::default();
+        for _ in 0..count {
+            notifier.register(&mut registration, &waker);
+        }
+        assert_eq!(notifier.registrations(), (1, 1));
+        notifier.notify();
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), 1);
+        assert_eq!(notifier.registrations(), (1, 0));
+        notifier.notify();
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), 1);
+
+        // The slot of a dropped registration is reused.
+        drop(registration);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let mut registration = Registration::default();
+                    for _ in 0..count {
+                        notifier.register(&mut registration, &waker);
+                    }
+                });
+            }
+            for _ in 0..count {
+                notifier.notify();
+            }
+        });
+        let (slots, waiting) = notifier.registrations();
+        assert!(slots <= 4);
+        assert_eq!(waiting, 0);
+
+        // A registration keeps its waker until it is woken or dropped, even after the notifier.
+        let mut registration = Registration::default();
+        notifier.register(&mut registration, &waker);
+        drop(notifier);
+        drop(waker);
+        assert_eq!(Arc::strong_count(&counter), 2);
+        drop(registration);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_park_backend() {
+        check_backend(Arc::new(ParkBackend::new()));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn test_futex_backend() {
+        check_backend(Arc::new(FutexBackend));
+    }
+
+    #[test]
+    fn test_no_thread_backend() {
+        let notifier = Notifier::new(Some(Arc::new(NoThreadBackend)));
+        // Returns without a notification nor a deadline.
+        notifier.listen().wait(None);
+    }
+
+    #[test]
+    fn test_custom_backend() {
+        let backend = Arc::new(SpinBackend {
+            waits: AtomicUsize::new(0),
+        });
+        check_backend(backend.clone());
+        assert!(backend.waits.load(Ordering::SeqCst) >= 2);
+    }
+}
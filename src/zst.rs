@@ -0,0 +1,162 @@
+//! Queues of zero-sized elements, like `Queue<()>` used as a ticket counter, which only need to
+//! count their elements instead of allocating nodes.
+//!
+//! The elements enqueued with `enqueue_tracked()` or given back by a claim still go through the
+//! linked list, so they can be dequeued after counted elements enqueued later. Since zero-sized
+//! values are indistinguishable, only their completions can tell.
+
+use core::mem;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::Ordering;
+
+use Queue;
+use reclaim::Reclaim;
+use sync::Exclusive;
+
+impl<T, R: Reclaim> Queue<T, R> {
+    /// Whether the elements are only counted. The `sojourn-time` feature needs the nodes to
+    /// stamp the elements.
+    pub(crate) const fn is_counted() -> bool {
+        mem::size_of::<T>() == 0 && !cfg!(feature = "sojourn-time")
+    }
+
+    pub(crate) fn enqueue_counted(&self, value: T) {
+        // The value will be recreated when it is dequeued.
+        mem::forget(value);
+        self.record_enqueue(1);
+        self.counted.fetch_add(1, Ordering::SeqCst);
+        self.notifier.notify();
+    }
+
+    /// Dequeue a counted element, if any.
+    pub(crate) fn pop_counted(&self) -> Option<T> {
+        let mut count = self.counted.load(Ordering::SeqCst);
+        while count > 0 {
+            match self.counted.compare_exchange_weak(count, count - 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => {
+                    if count == 1 && self.is_drained() {
+                        self.drained.notify();
+                    }
+                    return Some(unsafe { Self::recreate() });
+                },
+                Err(current) => count = current,
+            }
+        }
+        None
+    }
+
+    /// Remove every counted element, returning how many there were.
+    pub(crate) fn take_counted(&self) -> usize {
+        self.counted.swap(0, Ordering::SeqCst)
+    }
+
+    pub(crate) fn counted(&self) -> usize {
+        self.counted.load(Ordering::SeqCst)
+    }
+
+    /// Drop the counted elements when the queue is destroyed or reset.
+    pub(crate) fn drop_counted(&mut self) -> usize {
+        let count = self.counted.load_mut();
+        self.counted.store_mut(0);
+        if mem::needs_drop::<T>() {
+            for _ in 0..count {
+                drop(unsafe { Self::recreate() });
+            }
+        }
+        count
+    }
+
+    /// Recreate a zero-sized value which was forgotten when it was enqueued.
+    ///
+    /// Every call must correspond to a value previously given to `enqueue_counted()`.
+    pub(crate) unsafe fn recreate() -> T {
+        debug_assert!(mem::size_of::<T>() == 0);
+        // Reading a zero-sized value does not access memory: any aligned pointer is valid.
+        ptr::read(NonNull::<T>::dangling().as_ptr())
+    }
+
+    /// A reference to a counted element, without recreating it.
+    pub(crate) unsafe fn counted_ref<'a>() -> &'a T {
+        debug_assert!(mem::size_of::<T>() == 0);
+        &*NonNull::<T>::dangling().as_ptr()
+    }
+}
+
+#[cfg(all(test, not(feature = "sojourn-time")))]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use Queue;
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Token;
+
+    impl Drop for Token {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_tickets() {
+        assert!(Queue::<()>::is_counted());
+        let queue = Arc::new(Queue::new());
+        for _ in 0..1_000 {
+            queue.enqueue(()).expect("enqueue");
+        }
+        let handles: Vec<_> = (0..4).map(|_| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut count = 0;
+                while queue.dequeue().is_some() {
+                    count += 1;
+                }
+                count
+            })
+        }).collect();
+        let total: usize = handles.into_iter().map(|handle| handle.join().expect("join")).sum();
+        assert_eq!(total, 1_000);
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_counted_with_nodes() {
+        let queue = Queue::new();
+        queue.enqueue(()).expect("enqueue");
+        let completion = queue.enqueue_tracked(()).expect("enqueue");
+        queue.enqueue(()).expect("enqueue");
+        assert_eq!(queue.position_of(|_| true), Some(0));
+        // The counted elements are dequeued first.
+        drop(queue.claim());
+        assert_eq!(queue.dequeue(), Some(()));
+        assert_eq!(queue.dequeue(), Some(()));
+        assert_eq!(completion.try_wait(), None);
+        assert_eq!(queue.dequeue(), Some(()));
+        assert_eq!(completion.try_wait(), Some(true));
+        assert_eq!(queue.dequeue(), None);
+
+        queue.enqueue(()).expect("enqueue");
+        queue.enqueue(()).expect("enqueue");
+        assert_eq!(queue.into_raw_list().len(), 2);
+        assert!(!queue.any(|_| true));
+    }
+
+    #[test]
+    fn test_drop_counted() {
+        let mut queue = Queue::new();
+        for _ in 0..3 {
+            queue.enqueue(Token).expect("enqueue");
+        }
+        drop(queue.dequeue());
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        queue.reset();
+        assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+        queue.enqueue(Token).expect("enqueue");
+        drop(queue);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 4);
+    }
+}